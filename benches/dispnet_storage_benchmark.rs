@@ -1,10 +1,12 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use dispnet_shared::Package;
-use dispnet_storage::{policy::{PolicyManager, PolicyRule, PolicyType, TriggerPolicy, PolicyTrigger, IncomingPolicy, LayerPolicy}, storage_manager::StorageManager, filestorage::FileStorageProvider, StorageProvider};
+use dispnet_storage::{checksum::crc32, policy::{PolicyManager, PolicyRule, PolicyType, TriggerPolicy, PolicyTrigger, IncomingPolicy, LayerPolicy}, storage_manager::StorageManager, filestorage::FileStorageProvider, StorageProvider};
 
 const FILE_STORAGE: &str = "test_fstore";
 const DELETE_STORAGE: &str = "test_fdelete";
 const FILE_KEY: &str = "1234";
+/// Byte size of the entry for the read benchmarks.
+const LARGE_SIZE: usize = 16 * 1024 * 1024;
 
 fn clean_up(test_key: &str) {
     let f_path = format!("{}_{}", FILE_STORAGE, test_key);
@@ -23,7 +25,7 @@ fn storage_provider_instance(test_key: &str) -> Box<dyn StorageProvider> {
     let f_path = format!("{}_{}", FILE_STORAGE, test_key);
     let d_path = format!("{}_{}", DELETE_STORAGE, test_key);
 
-    Box::new(FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()))
+    Box::new(FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap())
 }
 
 fn get_package() -> Package {
@@ -109,12 +111,26 @@ fn find_in_provider_manager() {
     clean_up(f_key);
 }
 
+fn read_large_entry(c: &mut Criterion) {
+    let f_key = "read_large";
+    let f_path = format!("{}_{}", FILE_STORAGE, f_key);
+    let d_path = format!("{}_{}", DELETE_STORAGE, f_key);
+    let provider = FileStorageProvider::new(f_path, d_path).unwrap();
+    provider.save(FILE_KEY, vec![7; LARGE_SIZE]).unwrap();
+    // both paths checksum the data, so every byte is read
+    c.bench_function("Get large entry with read_to_end", |b| b.iter(|| crc32(&provider.get(FILE_KEY).unwrap().data)));
+    #[cfg(feature = "mmap")]
+    c.bench_function("Get large entry mapped", |b| b.iter(|| crc32(&provider.get_mapped(FILE_KEY).unwrap())));
+    clean_up(f_key);
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("Policy validate trigger", |b| b.iter(|| policy_validate_trigger()));
     c.bench_function("Policy validate incoming", |b| b.iter(|| policy_validate_incoming()));
     c.bench_function("Policy resolve layer", |b| b.iter(|| policy_resolve_layer()));
     c.bench_function("Get result from storage provider manager", |b| b.iter(|| get_from_provider_manager()));
     c.bench_function("Find result in storage provider manager", |b| b.iter(|| find_in_provider_manager()));
+    read_large_entry(c);
 }
 
 criterion_group!(benches, criterion_benchmark);
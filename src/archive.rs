@@ -0,0 +1,228 @@
+use std::{
+    io::{self, Read, Take, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{checksum::ChecksumReader, Metadata, StorageError};
+
+/// First bytes of every archive, followed by the format version.
+const MAGIC: &[u8; 4] = b"DSPA";
+const FORMAT_VERSION: u8 = 1;
+const TAG_END: u8 = 0;
+const TAG_ENTRY: u8 = 1;
+
+/// Handling of archive entries whose key already exists in the layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    /// Keep the existing entry and ignore the archive entry.
+    Skip,
+    /// Replace the existing entry with the archive entry.
+    Overwrite,
+    /// Import the archive entry under the first free key `{key}~{n}`.
+    Rename,
+}
+
+/// Result of an import.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Keys of the imported entries.
+    pub imported: Vec<String>,
+    /// Keys which already existed and were not imported.
+    pub skipped: Vec<String>,
+    /// Keys of the archive and the keys they were imported under.
+    pub renamed: Vec<(String, String)>,
+    /// Keys which could not be imported or failed the verification.
+    pub failed: Vec<(String, StorageError)>,
+}
+
+/// Header of an entry in an archive, the data follows the header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveEntry {
+    /// Key of the entry.
+    pub key: String,
+    /// Byte size of the data.
+    pub size: u64,
+    /// Time after which the entry expires, `None` if the entry never expires.
+    pub expires: Option<SystemTime>,
+    /// Metadata of the entry.
+    pub metadata: Metadata,
+}
+
+/// Writes entries into an archive.
+///
+/// Every entry is stored with its header, data and the CRC32 checksum of the data.
+pub struct ArchiveWriter<W: Write> {
+    inner: W,
+    entries: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&[FORMAT_VERSION])?;
+        Ok(Self { inner, entries: 0 })
+    }
+
+    /// Add an entry, exactly `entry.size` bytes are read from `data`. Returns the checksum of the data.
+    pub fn add(self: &mut Self, entry: &ArchiveEntry, data: &mut dyn Read) -> io::Result<u32> {
+        self.inner.write_all(&[TAG_ENTRY])?;
+        write_string(&mut self.inner, &entry.key)?;
+        let expires = entry.expires.map(|expires| expires.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0));
+        write_u64(&mut self.inner, expires.unwrap_or(0))?;
+        self.inner.write_all(&[expires.is_some() as u8])?;
+        write_u64(&mut self.inner, entry.metadata.len() as u64)?;
+        for (name, value) in entry.metadata.iter() {
+            write_string(&mut self.inner, name)?;
+            write_string(&mut self.inner, value)?;
+        }
+        write_u64(&mut self.inner, entry.size)?;
+        let mut reader = ChecksumReader::new(data.take(entry.size));
+        let copied = io::copy(&mut reader, &mut self.inner)?;
+        if copied != entry.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Data of `{}` is shorter than its size", entry.key)));
+        }
+        let (checksum, _size) = reader.finish();
+        self.inner.write_all(&checksum.to_le_bytes())?;
+        self.entries += 1;
+        Ok(checksum)
+    }
+
+    /// Write the end of the archive, returns the inner writer.
+    pub fn finish(mut self: Self) -> io::Result<W> {
+        self.inner.write_all(&[TAG_END])?;
+        write_u64(&mut self.inner, self.entries)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads the entries of an archive.
+///
+/// After `next_entry` the data must be read completely with `entry_data` and afterwards the checksum with `read_checksum`.
+pub struct ArchiveReader<R: Read> {
+    inner: R,
+    entries: u64,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 5];
+        inner.read_exact(&mut magic)?;
+        if &magic[..4] != MAGIC || magic[4] != FORMAT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a supported archive"));
+        }
+        Ok(Self { inner, entries: 0 })
+    }
+
+    /// Header of the next entry, `None` at the end of the archive.
+    pub fn next_entry(self: &mut Self) -> io::Result<Option<ArchiveEntry>> {
+        match read_u8(&mut self.inner)? {
+            TAG_END => {
+                if read_u64(&mut self.inner)? != self.entries {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Archive entry count does not match"));
+                }
+                Ok(None)
+            }
+            TAG_ENTRY => {
+                let key = read_string(&mut self.inner)?;
+                let expires_millis = read_u64(&mut self.inner)?;
+                let expires = match read_u8(&mut self.inner)? {
+                    0 => None,
+                    _ => Some(UNIX_EPOCH + Duration::from_millis(expires_millis)),
+                };
+                let mut metadata = Metadata::new();
+                for _ in 0..read_u64(&mut self.inner)? {
+                    let name = read_string(&mut self.inner)?;
+                    metadata.insert(name, read_string(&mut self.inner)?);
+                }
+                let size = read_u64(&mut self.inner)?;
+                self.entries += 1;
+                Ok(Some(ArchiveEntry { key, size, expires, metadata }))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid archive entry")),
+        }
+    }
+
+    /// Reader for the data of the entry, calculates the checksum of the data which is read through it.
+    pub fn entry_data(self: &mut Self, entry: &ArchiveEntry) -> ChecksumReader<Take<&mut R>> {
+        ChecksumReader::new((&mut self.inner).take(entry.size))
+    }
+
+    /// Stored checksum of the entry data.
+    pub fn read_checksum(self: &mut Self) -> io::Result<u32> {
+        let mut buffer = [0u8; 4];
+        self.inner.read_exact(&mut buffer)?;
+        Ok(u32::from_le_bytes(buffer))
+    }
+}
+
+fn write_u64(writer: &mut dyn Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_string(writer: &mut dyn Write, value: &str) -> io::Result<()> {
+    write_u64(writer, value.len() as u64)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_u8(reader: &mut dyn Read) -> io::Result<u8> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u64(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn read_string(reader: &mut dyn Read) -> io::Result<String> {
+    let len = read_u64(reader)?;
+    let mut buffer = vec![];
+    reader.take(len).read_to_end(&mut buffer)?;
+    if buffer.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Archive is truncated"));
+    }
+    String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{checksum::crc32, Metadata, META_CONTENT_TYPE};
+
+    use super::{ArchiveEntry, ArchiveReader, ArchiveWriter};
+
+    #[test]
+    fn roundtrip() {
+        let entry = ArchiveEntry {
+            key: "1234".to_owned(),
+            size: 4,
+            expires: None,
+            metadata: Metadata::from([(META_CONTENT_TYPE.to_owned(), "text/plain".to_owned())]),
+        };
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        assert_eq!(writer.add(&entry, &mut "test".as_bytes()).unwrap(), crc32(b"test"));
+        let archive = writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::new(archive.as_slice()).unwrap();
+        let read_entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(read_entry, entry);
+        let mut data = vec![];
+        let mut entry_data = reader.entry_data(&read_entry);
+        entry_data.read_to_end(&mut data).unwrap();
+        let (checksum, _size) = entry_data.finish();
+        assert_eq!(data, b"test");
+        assert_eq!(reader.read_checksum().unwrap(), checksum);
+        assert!(reader.next_entry().unwrap().is_none());
+
+        assert!(ArchiveReader::new(&archive[1..]).is_err());
+        let mut truncated = ArchiveReader::new(&archive[..archive.len() - 9]).unwrap();
+        truncated.next_entry().unwrap();
+        assert!(truncated.entry_data(&read_entry).read_to_end(&mut vec![]).is_ok());
+        truncated.read_checksum().unwrap();
+        assert!(truncated.next_entry().is_err());
+    }
+}
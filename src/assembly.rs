@@ -0,0 +1,212 @@
+use std::io::Read;
+
+use crate::{
+    checksum::{crc32_reader, ChecksumReader},
+    SaveData, SaveOptions, StorageProvider, META_PACKAGE_ID,
+};
+
+/// Separator between the package id and the part index in a part key.
+const PART_SEPARATOR: &str = ".";
+
+/// Checksum validation for an assembled package.
+pub struct ChecksumValidation<'a> {
+    /// Expected CRC32 checksum of the complete package as hex digits, e.g. `format!("{:08x}", checksum::crc32(&data))`.
+    pub expected: &'a str,
+}
+
+impl<'a> ChecksumValidation<'a> {
+    /// `true` if the CRC32 checksum of the assembled data is the expected checksum.
+    pub fn matches(self: &Self, checksum: u32) -> bool {
+        u32::from_str_radix(self.expected, 16) == Ok(checksum)
+    }
+}
+
+/// Storage key of a package part.
+pub fn part_key(package_id: &str, index: u64) -> String {
+    format!("{}{}{}", package_id, PART_SEPARATOR, index)
+}
+
+/// Save options of a package part, the part is tagged with the package id.
+pub fn part_options(package_id: &str) -> SaveOptions {
+    let mut options = SaveOptions::default();
+    options.metadata.insert(META_PACKAGE_ID.to_owned(), package_id.to_owned());
+    options
+}
+
+/// Save a single part of a package.
+pub fn save_part(provider: &dyn StorageProvider, package_id: &str, index: u64, raw: Vec<u8>) -> Result<SaveData, String> {
+    provider.save_with_options(&part_key(package_id, index), raw, &part_options(package_id))
+}
+
+/// Get the sorted indices of all stored parts of a package.
+pub fn part_indices(provider: &dyn StorageProvider, package_id: &str) -> Result<Vec<u64>, String> {
+    let prefix = format!("{}{}", package_id, PART_SEPARATOR);
+    let mut indices: Vec<u64> = provider
+        .list(&prefix)?
+        .iter()
+        .filter_map(|key| key[prefix.len()..].parse::<u64>().ok())
+        .collect();
+    indices.sort_unstable();
+    Ok(indices)
+}
+
+/// Get the indices which are missing to complete a package with `part_count` parts.
+pub fn missing_part_indices(provider: &dyn StorageProvider, package_id: &str, part_count: u64) -> Result<Vec<u64>, String> {
+    let present = part_indices(provider, package_id)?;
+    Ok((0..part_count).filter(|index| present.binary_search(index).is_err()).collect())
+}
+
+/// Byte size of all parts of a package.
+pub fn package_size(provider: &dyn StorageProvider, package_id: &str, part_count: u64) -> Result<u64, String> {
+    (0..part_count).map(|index| provider.stat(&part_key(package_id, index)).map(|stat| stat.size as u64)).sum()
+}
+
+/// Assemble all parts of a package into a single entry with the key `target_key`, the parts are streamed into the entry.
+/// 
+/// With a checksum validation the entry is only replaced if the checksum matches, providers without staging read the parts twice.
+pub fn assemble(
+    provider: &dyn StorageProvider,
+    package_id: &str,
+    part_count: u64,
+    target_key: &str,
+    checksum: Option<&ChecksumValidation>,
+) -> Result<SaveData, String> {
+    let mismatch = || format!("Checksum mismatch for package `{}`", package_id);
+    let options = SaveOptions::default();
+    let mut reader = ChecksumReader::new(open_reader(provider, package_id, part_count)?);
+    match checksum {
+        None => provider.save_from_reader(target_key, &mut reader, &options),
+        Some(validation) if provider.supports_staging() => {
+            let token = provider.stage_save_from_reader(target_key, &mut reader, &options)?;
+            let (checksum, size) = reader.finish();
+            if !validation.matches(checksum) {
+                provider.discard_staged(&token);
+                return Err(mismatch());
+            }
+            provider.commit_staged(target_key, &token)?;
+            Ok(SaveData {
+                key: target_key.to_owned(),
+                size: size as usize,
+                version: None,
+            })
+        }
+        Some(validation) => {
+            let (checksum, _size) = crc32_reader(&mut reader).map_err(|e| format!("Could not read parts of package `{}`: {}", package_id, e))?;
+            if !validation.matches(checksum) {
+                return Err(mismatch());
+            }
+            provider.save_from_reader(target_key, &mut open_reader(provider, package_id, part_count)?, &options)
+        }
+    }
+}
+
+/// Open a reader which streams all parts of a package in index order.
+pub fn open_reader<'a>(provider: &'a dyn StorageProvider, package_id: &str, part_count: u64) -> Result<PackageReader<'a>, String> {
+    let missing = missing_part_indices(provider, package_id, part_count)?;
+    if !missing.is_empty() {
+        return Err(format!("Package `{}` is missing the parts: {:?}", package_id, missing));
+    }
+    Ok(PackageReader {
+        provider,
+        package_id: package_id.to_owned(),
+        part_count,
+        next_index: 0,
+        current: None,
+    })
+}
+
+/// Streams the parts of a package, the readers of the parts are chained in index order.
+pub struct PackageReader<'a> {
+    provider: &'a dyn StorageProvider,
+    package_id: String,
+    part_count: u64,
+    next_index: u64,
+    current: Option<Box<dyn Read + 'a>>,
+}
+
+impl<'a> Read for PackageReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(current) = self.current.as_mut() {
+                let read = current.read(buf)?;
+                if read > 0 {
+                    return Ok(read);
+                }
+            }
+            if self.next_index >= self.part_count {
+                return Ok(0);
+            }
+            let part = self
+                .provider
+                .get_reader(&part_key(&self.package_id, self.next_index))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))?;
+            self.current = Some(part);
+            self.next_index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::{checksum::crc32, filestorage::FileStorageProvider, StorageProvider, META_PACKAGE_ID};
+
+    use super::{assemble, missing_part_indices, open_reader, part_indices, save_part, ChecksumValidation};
+
+    const FILE_STORAGE: &str = "test_fstore";
+    const DELETE_STORAGE: &str = "test_fdelete";
+
+    fn clean_up(test_key: &str) {
+        let _result = std::fs::remove_dir_all(format!("{}_{}", FILE_STORAGE, test_key));
+        let _result = std::fs::remove_dir_all(format!("{}_{}", DELETE_STORAGE, test_key));
+    }
+
+    fn storage_provider_instance(test_key: &str) -> FileStorageProvider {
+        let f_path = format!("{}_{}", FILE_STORAGE, test_key);
+        let d_path = format!("{}_{}", DELETE_STORAGE, test_key);
+
+        FileStorageProvider::new(f_path, d_path).unwrap()
+    }
+
+    #[test]
+    fn missing_parts() {
+        let f_key = "assembly_missing";
+        let provider = storage_provider_instance(f_key);
+        save_part(&provider, "p1", 0, b"ab".to_vec()).unwrap();
+        save_part(&provider, "p1", 2, b"ef".to_vec()).unwrap();
+        save_part(&provider, "p10", 1, b"xx".to_vec()).unwrap();
+        assert_eq!(part_indices(&provider, "p1").unwrap(), vec![0, 2]);
+        assert_eq!(missing_part_indices(&provider, "p1", 4).unwrap(), vec![1, 3]);
+        assert!(open_reader(&provider, "p1", 3).is_err());
+        assert_eq!(provider.stat("p1.2").unwrap().metadata.get(META_PACKAGE_ID).unwrap(), "p1");
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn assemble_package() {
+        let f_key = "assembly_assemble";
+        let provider = storage_provider_instance(f_key);
+        save_part(&provider, "p1", 1, b"cd".to_vec()).unwrap();
+        save_part(&provider, "p1", 0, b"ab".to_vec()).unwrap();
+
+        let mut streamed = String::new();
+        open_reader(&provider, "p1", 2).unwrap().read_to_string(&mut streamed).unwrap();
+        assert_eq!(streamed, "abcd");
+
+        let expected = format!("{:08x}", crc32(b"abcd"));
+        let validation = ChecksumValidation { expected: &expected };
+        let result = assemble(&provider, "p1", 2, "p1", Some(&validation)).unwrap();
+        assert_eq!(result.size, 4);
+        assert_eq!(provider.get("p1").unwrap().data, b"abcd".to_vec());
+
+        let invalid = ChecksumValidation { expected: "0" };
+        assert!(assemble(&provider, "p1", 2, "p1_invalid", Some(&invalid)).is_err());
+        assert!(provider.stat("p1_invalid").is_err());
+        assert_eq!(std::fs::read_dir(format!("{}_{}/.tmp", FILE_STORAGE, f_key)).unwrap().count(), 0);
+        clean_up(f_key);
+    }
+}
@@ -0,0 +1,127 @@
+//! Incremental backups of a storage layer into another storage provider.
+//!
+//! Every backup stores a manifest `backup.{id}.manifest` with all entries of the layer at the time of the backup.
+//! The data of an entry is stored as `backup.{id}.{key}` by the first backup which saw the entry in its current version,
+//! later backups reference this data until the entry is changed.
+
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{sidecar, StorageError, StorageProvider};
+
+/// Prefix of all keys written by a backup.
+const BACKUP_PREFIX: &str = "backup.";
+const MANIFEST_SUFFIX: &str = ".manifest";
+/// First line of every manifest.
+const MANIFEST_HEADER: &str = "dispnet-backup 1";
+
+/// ID of a backup, the IDs sort in the order the backups were created.
+pub type BackupId = String;
+
+/// Result of a backup.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupReport {
+    /// ID of the created backup.
+    pub id: BackupId,
+    /// Keys which were copied because they were new or changed.
+    pub copied: Vec<String>,
+    /// Count of entries which were unchanged and reference the data of an older backup.
+    pub unchanged: usize,
+}
+
+/// Entry of a backup manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ManifestRecord {
+    pub key: String,
+    /// Backup which holds the data of the entry.
+    pub data_backup: BackupId,
+    /// Etag of the entry in the source layer, used to detect changes.
+    pub etag: String,
+    pub size: u64,
+    pub checksum: u32,
+    pub expires: Option<SystemTime>,
+}
+
+pub(crate) fn new_backup_id() -> BackupId {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:032x}", nanos)
+}
+
+pub(crate) fn data_key(backup: &str, key: &str) -> String {
+    format!("{}{}.{}", BACKUP_PREFIX, backup, key)
+}
+
+pub(crate) fn manifest_key(backup: &str) -> String {
+    format!("{}{}{}", BACKUP_PREFIX, backup, MANIFEST_SUFFIX)
+}
+
+/// IDs of all backups in the provider, the oldest backup first.
+pub(crate) fn list_backups(provider: &dyn StorageProvider) -> Result<Vec<BackupId>, StorageError> {
+    let mut backups: Vec<BackupId> = provider
+        .list(BACKUP_PREFIX)?
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(BACKUP_PREFIX).and_then(|key| key.strip_suffix(MANIFEST_SUFFIX)).map(|id| id.to_owned()))
+        .filter(|id| !id.contains('.'))
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+pub(crate) fn read_manifest(provider: &dyn StorageProvider, backup: &str) -> Result<Vec<ManifestRecord>, StorageError> {
+    let data = provider.get(&manifest_key(backup)).map_err(|_e| StorageError::NotFound(manifest_key(backup)))?.data;
+    let content = String::from_utf8(data).map_err(|_e| StorageError::Provider(format!("Manifest of backup `{}` is invalid", backup)))?;
+    let mut lines = content.lines();
+    if lines.next() != Some(MANIFEST_HEADER) {
+        return Err(StorageError::Provider(format!("Manifest of backup `{}` is invalid", backup)));
+    }
+    lines.map(|line| parse_record(line).ok_or_else(|| StorageError::Provider(format!("Manifest of backup `{}` is invalid", backup)))).collect()
+}
+
+pub(crate) fn write_manifest(provider: &dyn StorageProvider, backup: &str, records: &[ManifestRecord]) -> Result<(), StorageError> {
+    let mut content = format!("{}\n", MANIFEST_HEADER);
+    for record in records {
+        let expires = record.expires.and_then(|expires| expires.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_millis().to_string()).unwrap_or_default();
+        let _result = writeln!(
+            content,
+            "{}\t{}\t{}\t{}\t{:08x}\t{}",
+            sidecar::escape(&record.key),
+            record.data_backup,
+            sidecar::escape(&record.etag),
+            record.size,
+            record.checksum,
+            expires
+        );
+    }
+    provider.save(&manifest_key(backup), content.into_bytes())?;
+    Ok(())
+}
+
+fn parse_record(line: &str) -> Option<ManifestRecord> {
+    let mut fields = line.split('\t');
+    let key = sidecar::unescape(fields.next()?);
+    let data_backup = fields.next()?.to_owned();
+    let etag = sidecar::unescape(fields.next()?);
+    let size = fields.next()?.parse().ok()?;
+    let checksum = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let expires = match fields.next()? {
+        "" => None,
+        millis => Some(UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?)),
+    };
+    Some(ManifestRecord { key, data_backup, etag, size, checksum, expires })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_record;
+
+    #[test]
+    fn manifest_record() {
+        let record = parse_record("a\\tb\t0001\tetag\t4\t0000abcd\t").unwrap();
+        assert_eq!(record.key, "a\tb");
+        assert_eq!(record.checksum, 0xabcd);
+        assert!(record.expires.is_none());
+        assert!(parse_record("key\t0001").is_none());
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// Free space thresholds of a layer in bytes, `critical_free_bytes` should be lower than `low_free_bytes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watermarks {
+    /// Below this free space the delete queue of the layer is purged with `force_free`.
+    pub low_free_bytes: u64,
+    /// Below this free space the layer is purged and saves are refused while the free space stays below.
+    pub critical_free_bytes: u64,
+}
+
+impl Watermarks {
+    pub fn level(self: &Self, free_bytes: u64) -> CapacityLevel {
+        if free_bytes < self.critical_free_bytes {
+            CapacityLevel::Critical
+        } else if free_bytes < self.low_free_bytes {
+            CapacityLevel::Low
+        } else {
+            CapacityLevel::Normal
+        }
+    }
+}
+
+/// Free space of a layer compared to its watermarks.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum CapacityLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+struct LayerCapacity {
+    watermarks: Watermarks,
+    level: CapacityLevel,
+}
+
+/// Keeps track of the watermarks and the last capacity level of all layers.
+#[derive(Default)]
+pub(crate) struct CapacityMonitor {
+    layers: Mutex<HashMap<String, LayerCapacity>>,
+}
+
+impl CapacityMonitor {
+    pub fn set_watermarks(self: &Self, layer: &str, watermarks: Option<Watermarks>) {
+        let mut layers = self.layers.lock().unwrap();
+        match watermarks {
+            // the level is kept, so the next check emits an event if the new watermarks change it
+            Some(watermarks) => match layers.get_mut(layer) {
+                Some(capacity) => capacity.watermarks = watermarks,
+                None => {
+                    layers.insert(layer.to_owned(), LayerCapacity { watermarks, level: CapacityLevel::Normal });
+                }
+            },
+            None => {
+                layers.remove(layer);
+            }
+        }
+    }
+
+    pub fn watermarks(self: &Self, layer: &str) -> Option<Watermarks> {
+        self.layers.lock().unwrap().get(layer).map(|capacity| capacity.watermarks.clone())
+    }
+
+    /// Last level of the layer, `Normal` for layers without watermarks.
+    pub fn level(self: &Self, layer: &str) -> CapacityLevel {
+        self.layers.lock().unwrap().get(layer).map(|capacity| capacity.level).unwrap_or(CapacityLevel::Normal)
+    }
+
+    /// Store the new level, returns `true` if the level changed.
+    pub fn update(self: &Self, layer: &str, level: CapacityLevel) -> bool {
+        match self.layers.lock().unwrap().get_mut(layer) {
+            Some(capacity) if capacity.level != level => {
+                capacity.level = level;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CapacityLevel, CapacityMonitor, Watermarks};
+
+    #[test]
+    fn levels() {
+        let watermarks = Watermarks { low_free_bytes: 100, critical_free_bytes: 10 };
+        assert_eq!(watermarks.level(100), CapacityLevel::Normal);
+        assert_eq!(watermarks.level(99), CapacityLevel::Low);
+        assert_eq!(watermarks.level(9), CapacityLevel::Critical);
+
+        let monitor = CapacityMonitor::default();
+        assert!(!monitor.update("layer1", CapacityLevel::Low));
+        monitor.set_watermarks("layer1", Some(watermarks));
+        assert!(monitor.update("layer1", CapacityLevel::Low));
+        assert!(!monitor.update("layer1", CapacityLevel::Low));
+        assert_eq!(monitor.level("layer1"), CapacityLevel::Low);
+    }
+}
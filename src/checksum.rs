@@ -0,0 +1,55 @@
+use std::io::Read;
+
+/// CRC32 checksum of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+/// CRC32 checksum and byte size of all data of a reader.
+pub fn crc32_reader(reader: &mut dyn Read) -> std::io::Result<(u32, u64)> {
+    let mut checksum_reader = ChecksumReader::new(reader);
+    std::io::copy(&mut checksum_reader, &mut std::io::sink())?;
+    Ok(checksum_reader.finish())
+}
+
+/// Calculates the CRC32 checksum of all data which is read through it.
+pub struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    /// Checksum and byte size of the data read so far.
+    pub fn finish(self) -> (u32, u64) {
+        (self.hasher.finalize(), self.size)
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, crc32_reader};
+
+    #[test]
+    fn reader_checksum() {
+        let data = "test".to_owned().into_bytes();
+        assert_eq!(crc32_reader(&mut data.as_slice()).unwrap(), (crc32(&data), 4));
+    }
+}
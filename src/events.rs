@@ -0,0 +1,315 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::sidecar;
+
+/// Count of events between two entries of the offset index of the event log.
+const INDEX_INTERVAL: u64 = 1_024;
+
+/// Kind of change of an entry or of a layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageEventKind {
+    /// The entry was created or overwritten.
+    Saved,
+    /// The entry was queued for deletion.
+    Deleted,
+    /// The entry was restored from the delete queue.
+    Restored,
+    /// The entry was removed from the delete queue.
+    Purged,
+    /// The free space of the layer dropped below the low watermark, the event has no key and the size is the free space.
+    CapacityLow,
+    /// The free space of the layer dropped below the critical watermark, saves are refused.
+    CapacityCritical,
+    /// The free space of the layer is above the watermarks again.
+    CapacityNormal,
+}
+
+/// Change of an entry in a storage layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageEvent {
+    /// Increases by one for every event, continues after a restart if the event log is enabled.
+    pub sequence: u64,
+    pub kind: StorageEventKind,
+    /// Layer of the entry.
+    pub layer: String,
+    /// Key of the entry.
+    pub key: String,
+    /// Byte size of the entry.
+    pub size: u64,
+    /// Time the change was made.
+    pub timestamp: SystemTime,
+}
+
+/// Callback which is called for every event.
+pub type EventCallback = Arc<dyn Fn(&StorageEvent) + Send + Sync>;
+
+/// Distributes the events of a storage manager to all subscribers.
+#[derive(Default)]
+pub(crate) struct EventFeed {
+    state: Mutex<FeedState>,
+}
+
+#[derive(Default)]
+struct FeedState {
+    sequence: u64,
+    subscribers: Vec<Sender<StorageEvent>>,
+    callbacks: Vec<EventCallback>,
+    log: Option<EventLog>,
+}
+
+/// Append-only file of all events.
+struct EventLog {
+    path: String,
+    file: File,
+    /// Byte size of the complete records, new records are appended at this offset.
+    size: u64,
+    /// Sequence and byte offset of every `INDEX_INTERVAL`th record, so a read starts close to the requested sequence.
+    index: Vec<(u64, u64)>,
+}
+
+impl EventLog {
+    /// Open the log and index its records, returns the log and the sequence of its last record.
+    /// 
+    /// A partially written last record is removed, so the next record is not appended to it.
+    fn open(path: &str) -> Result<(EventLog, u64), String> {
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path).map_err(|e| format!("Could not open event log: {}", e))?;
+        let mut log = EventLog {
+            path: path.to_owned(),
+            file,
+            size: 0,
+            index: vec![],
+        };
+        let mut last_sequence = 0;
+        let mut reader = BufReader::new(log.file.try_clone().map_err(|e| format!("Could not open event log: {}", e))?);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line).map_err(|e| format!("Could not read event log: {}", e))?;
+            if read == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            if let Some(event) = std::str::from_utf8(&line[..read - 1]).ok().and_then(parse_event) {
+                log.indexed(event.sequence, log.size);
+                last_sequence = event.sequence;
+            }
+            log.size += read as u64;
+        }
+        log.file.set_len(log.size).map_err(|e| format!("Could not open event log: {}", e))?;
+        Ok((log, last_sequence))
+    }
+
+    /// Add a record to the offset index if the last indexed record is `INDEX_INTERVAL` events before it.
+    fn indexed(self: &mut Self, sequence: u64, offset: u64) {
+        let due = match self.index.last() {
+            Some((indexed, _offset)) => sequence >= indexed + INDEX_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.index.push((sequence, offset));
+        }
+    }
+
+    /// Append a record and sync it to disk, a failed write is removed again so the log stays readable.
+    fn append(self: &mut Self, event: &StorageEvent) {
+        let record = format_event(event);
+        match self.file.write_all(record.as_bytes()).and_then(|_| self.file.sync_data()) {
+            Ok(()) => {
+                self.indexed(event.sequence, self.size);
+                self.size += record.len() as u64;
+            }
+            Err(_) => {
+                let _result = self.file.set_len(self.size);
+            }
+        }
+    }
+
+    /// Offset of the last indexed record with a sequence number not greater than `sequence`.
+    fn offset_before(self: &Self, sequence: u64) -> u64 {
+        let position = self.index.partition_point(|(indexed, _offset)| *indexed <= sequence);
+        position.checked_sub(1).map(|position| self.index[position].1).unwrap_or(0)
+    }
+}
+
+impl EventFeed {
+    pub fn subscribe(self: &Self) -> Receiver<StorageEvent> {
+        let (sender, receiver) = channel();
+        self.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    pub fn on_event(self: &Self, callback: EventCallback) {
+        self.state.lock().unwrap().callbacks.push(callback);
+    }
+
+    /// Append all events to the log file, the sequence continues after the last event in the log or after the last emitted event.
+    pub fn open_log(self: &Self, path: &str) -> Result<(), String> {
+        let (log, last_sequence) = EventLog::open(path)?;
+        let mut state = self.state.lock().unwrap();
+        state.sequence = state.sequence.max(last_sequence);
+        state.log = Some(log);
+        Ok(())
+    }
+
+    /// All logged events after the sequence number, the log is read from the closest indexed event.
+    pub fn events_since(self: &Self, sequence: u64) -> Result<Vec<StorageEvent>, String> {
+        let (path, offset, size) = match &self.state.lock().unwrap().log {
+            Some(log) => (log.path.to_owned(), log.offset_before(sequence), log.size),
+            None => return Err("Event log is not enabled".to_owned()),
+        };
+        let read_error = |e: std::io::Error| format!("Could not read event log: {}", e);
+        let mut file = File::open(&path).map_err(read_error)?;
+        file.seek(SeekFrom::Start(offset)).map_err(read_error)?;
+        let mut events = vec![];
+        for line in BufReader::new(file.take(size.saturating_sub(offset))).split(b'\n') {
+            let line = line.map_err(read_error)?;
+            if let Some(event) = std::str::from_utf8(&line).ok().and_then(parse_event) {
+                if event.sequence > sequence {
+                    events.push(event);
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    pub fn last_sequence(self: &Self) -> u64 {
+        self.state.lock().unwrap().sequence
+    }
+
+    pub fn emit(self: &Self, kind: StorageEventKind, layer: &str, key: &str, size: u64) {
+        let (event, callbacks) = {
+            let mut state = self.state.lock().unwrap();
+            state.sequence += 1;
+            let event = StorageEvent {
+                sequence: state.sequence,
+                kind,
+                layer: layer.to_owned(),
+                key: key.to_owned(),
+                size,
+                timestamp: SystemTime::now(),
+            };
+            if let Some(log) = &mut state.log {
+                log.append(&event);
+            }
+            // receivers which were dropped are removed
+            state.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            (event, state.callbacks.clone())
+        };
+        // the callbacks are called without the lock, so they can use the manager
+        for callback in callbacks {
+            callback(&event);
+        }
+    }
+}
+
+fn format_event(event: &StorageEvent) -> String {
+    let kind = match event.kind {
+        StorageEventKind::Saved => "saved",
+        StorageEventKind::Deleted => "deleted",
+        StorageEventKind::Restored => "restored",
+        StorageEventKind::Purged => "purged",
+        StorageEventKind::CapacityLow => "capacity_low",
+        StorageEventKind::CapacityCritical => "capacity_critical",
+        StorageEventKind::CapacityNormal => "capacity_normal",
+    };
+    let millis = event.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\n",
+        event.sequence,
+        kind,
+        millis,
+        event.size,
+        sidecar::escape(&event.layer),
+        sidecar::escape(&event.key)
+    )
+}
+
+fn parse_event(line: &str) -> Option<StorageEvent> {
+    let mut fields = line.split('\t');
+    let sequence = fields.next()?.parse().ok()?;
+    let kind = match fields.next()? {
+        "saved" => StorageEventKind::Saved,
+        "deleted" => StorageEventKind::Deleted,
+        "restored" => StorageEventKind::Restored,
+        "purged" => StorageEventKind::Purged,
+        "capacity_low" => StorageEventKind::CapacityLow,
+        "capacity_critical" => StorageEventKind::CapacityCritical,
+        "capacity_normal" => StorageEventKind::CapacityNormal,
+        _ => return None,
+    };
+    let timestamp = UNIX_EPOCH + Duration::from_millis(fields.next()?.parse().ok()?);
+    let size = fields.next()?.parse().ok()?;
+    let layer = sidecar::unescape(fields.next()?);
+    let key = sidecar::unescape(fields.next()?);
+    Some(StorageEvent { sequence, kind, layer, key, size, timestamp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_event, parse_event, EventFeed, EventLog, StorageEventKind, INDEX_INTERVAL};
+
+    #[test]
+    fn feed() {
+        let feed = EventFeed::default();
+        let receiver = feed.subscribe();
+        feed.emit(StorageEventKind::Saved, "layer\t1", "1234", 4);
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.sequence, 1);
+        assert_eq!(event.layer, "layer\t1");
+
+        let parsed = parse_event(format_event(&event).trim_end()).unwrap();
+        assert_eq!(parsed.key, event.key);
+        assert_eq!(parsed.kind, StorageEventKind::Saved);
+        drop(receiver);
+        feed.emit(StorageEventKind::Deleted, "layer1", "1234", 4);
+        assert_eq!(feed.last_sequence(), 2);
+    }
+
+    #[test]
+    fn log() {
+        let path = "test_events_log.events";
+        let _result = std::fs::remove_file(path);
+        let feed = EventFeed::default();
+        feed.emit(StorageEventKind::Saved, "layer1", "1234", 4);
+        // the sequence continues after the events which were emitted before the log was enabled
+        feed.open_log(path).unwrap();
+        feed.emit(StorageEventKind::Saved, "layer1", "5678", 4);
+        feed.emit(StorageEventKind::Deleted, "layer1", "5678", 4);
+        let events = feed.events_since(0).unwrap();
+        assert_eq!(events.iter().map(|event| event.sequence).collect::<Vec<u64>>(), vec![2, 3]);
+
+        // a partially written record is removed and its sequence is not reused
+        let mut content = std::fs::read(path).unwrap();
+        content.extend_from_slice(b"4\tsaved\t1");
+        std::fs::write(path, content).unwrap();
+        let restarted = EventFeed::default();
+        restarted.open_log(path).unwrap();
+        assert_eq!(restarted.last_sequence(), 3);
+        restarted.emit(StorageEventKind::Restored, "layer1", "5678", 4);
+        let events = restarted.events_since(2).unwrap();
+        assert_eq!(events.iter().map(|event| event.sequence).collect::<Vec<u64>>(), vec![3, 4]);
+        assert_eq!(events[1].kind, StorageEventKind::Restored);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn log_index() {
+        let path = "test_events_index.events";
+        let (mut log, _last_sequence) = EventLog::open(path).unwrap();
+        log.indexed(1, 0);
+        log.indexed(2, 10);
+        log.indexed(1 + INDEX_INTERVAL, 100);
+        assert_eq!(log.index, vec![(1, 0), (1 + INDEX_INTERVAL, 100)]);
+        assert_eq!(log.offset_before(0), 0);
+        assert_eq!(log.offset_before(INDEX_INTERVAL), 0);
+        assert_eq!(log.offset_before(1 + INDEX_INTERVAL), 100);
+        std::fs::remove_file(path).unwrap();
+    }
+}
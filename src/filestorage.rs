@@ -1,114 +1,1646 @@
-use std::{fs::{File, self}, io::{Read, Write}, time::SystemTime};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, self},
+    io::{Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock, RwLockReadGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{StorageProvider, GetData, SaveData};
+use crate::{
+    checksum::{crc32_reader, ChecksumReader},
+    index::{Index, IndexEntry},
+    journal::{Intent, Journal},
+    retention::{QueuedEntry, Retention},
+    sidecar, trace::OperationSpan, StorageProvider, ChangeKind, ExternalChange, CapacityData, GetData, HealthData, Metadata, SaveData, SaveOptions, SnapshotData, SnapshotId, StatData, StorageError, VersionData,
+};
 
-const DAY_IN_SECONDS: u64 = 86_400;
+/// Maximum count of threads used for batch operations.
+const MAX_BATCH_THREADS: usize = 8;
+
+/// Hidden folder inside the storage and delete folder for the metadata files of the entries.
+const META_FOLDER: &str = ".meta";
+/// Hidden folder inside the storage folder for files which are not completely written.
+const TEMP_FOLDER: &str = ".tmp";
+/// Hidden folder inside the storage folder for the lock files of saves.
+const LOCK_FOLDER: &str = ".locks";
+/// Lock files older than this are left over from a crashed writer.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time to wait for a lock.
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+/// The write counters of the health check are reset by the first write after this time.
+const ERROR_RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Hidden folder inside the storage folder for the versions of the entries.
+const VERSION_FOLDER: &str = ".versions";
+/// Hidden folder inside the storage folder for the snapshots.
+const SNAPSHOT_FOLDER: &str = ".snapshots";
+/// Hidden folder inside the storage folder for the write-ahead journal.
+const JOURNAL_FOLDER: &str = ".journal";
+/// Hidden folder inside the storage folder for the index log.
+const INDEX_FOLDER: &str = ".index";
+/// Metadata field with the checksum of the data and the etag of the file the checksum belongs to.
+const CHECKSUM_FIELD: &str = "checksum";
+/// Metadata field with the expire time of an entry as milliseconds since the unix epoch.
+const EXPIRES_FIELD: &str = "expires";
+/// Prefix of the metadata fields provided by the caller.
+const USER_FIELD_PREFIX: &str = "meta.";
+
+/// Configuration of a `FileStorageProvider`.
+#[derive(Clone, Debug, Default)]
+pub struct FileStorageConfig {
+    /// Retention of the entries in the delete folder.
+    pub retention: Retention,
+    /// Record all changes in a write-ahead journal, unfinished changes are completed or rolled back on the next start.
+    pub journal: bool,
+    /// Keep every saved version of an entry, `None` disables versioning.
+    /// 
+    /// The retention is applied to the versions of each key on `free` and `force_free`, `max_entries` is the count of versions kept per key.
+    /// The newest version of a key is always kept.
+    pub versions: Option<Retention>,
+    /// Watch the storage folder with inotify, so `poll_changes` only checks the changed files.
+    /// 
+    /// Requires the feature `watch`, otherwise `poll_changes` scans the whole folder.
+    pub watch: bool,
+    /// Keep a persistent index of all entries, `list` and `stat` are served from the index instead of the folder.
+    /// 
+    /// The index is synchronized with the folder on startup and rebuilt if it is lost, changes made outside of the provider
+    /// are picked up by `poll_changes`.
+    pub index: bool,
+}
+
+/// Data of an entry which is mapped into memory instead of read into a buffer, returned by `get_mapped`.
+/// 
+/// Dereferences to the bytes of the entry.
+#[cfg(feature = "mmap")]
+pub struct MappedData {
+    /// Key of the entry.
+    pub key: String,
+    /// Byte size of the entry.
+    pub size: usize,
+    /// Metadata of the entry.
+    pub metadata: Metadata,
+    /// Changes on every save of the entry, used for `save_if_match`.
+    pub etag: String,
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MappedData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MappedData {
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+/// Copy of an entry which was kept when a key was found in the storage and in the delete folder.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictResolution {
+    /// The entry in the storage folder was newer, the entry in the delete folder was removed.
+    KeptLive,
+    /// The entry in the delete folder was newer, the entry in the storage folder was removed.
+    KeptDeleted,
+}
+
+/// Changes made during the startup of a `FileStorageProvider`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecoveryReport {
+    /// Count of unfinished journal operations which were completed or rolled back.
+    pub replayed_operations: usize,
+    /// Temp files of unfinished saves which were removed.
+    pub removed_temp_files: Vec<String>,
+    /// Keys which existed in the storage and in the delete folder.
+    pub resolved_conflicts: Vec<(String, ConflictResolution)>,
+}
 
 pub struct FileStorageProvider {
     folder: String,
     delete: String,
+    config: FileStorageConfig,
+    journal: Option<Journal>,
+    index: Option<Index>,
+    temp_counter: AtomicU64,
+    /// Count of all and of failed writes since the start of the error rate window.
+    writes: AtomicU64,
+    failed_writes: AtomicU64,
+    write_window: Mutex<Instant>,
+    /// Changes of the entries hold a read lock, a snapshot and `poll_changes` hold the write lock.
+    snapshot_lock: RwLock<()>,
+    /// Etags of all files in the storage folder, used to detect changes made outside of the provider.
+    known_files: Mutex<HashMap<String, String>>,
+    #[cfg(feature = "watch")]
+    watcher: Option<watch::FolderWatcher>,
+    recovery_report: RecoveryReport,
 }
 
-impl FileStorageProvider {
-    pub fn new(storage_folder: String, delete_folder: String) -> Self {
-        let _result = std::fs::create_dir_all(&storage_folder);
-        let _result = std::fs::create_dir_all(&delete_folder);
-        Self {
-            folder: storage_folder,
-            delete: delete_folder,
-        }
+impl FileStorageProvider {
+    pub fn new(storage_folder: String, delete_folder: String) -> Result<Self, String> {
+        Self::with_config(storage_folder, delete_folder, FileStorageConfig::default())
+    }
+
+    /// Create a provider with a custom configuration.
+    /// 
+    /// Returns `Err` if the folders can not be created, are not writable or are not on the same filesystem.
+    pub fn with_config(storage_folder: String, delete_folder: String, config: FileStorageConfig) -> Result<Self, String> {
+        for folder in [
+            format!("{}/{}", storage_folder, META_FOLDER),
+            format!("{}/{}", storage_folder, TEMP_FOLDER),
+            format!("{}/{}", storage_folder, LOCK_FOLDER),
+            format!("{}/{}", storage_folder, VERSION_FOLDER),
+            format!("{}/{}", storage_folder, SNAPSHOT_FOLDER),
+            format!("{}/{}", delete_folder, META_FOLDER),
+        ] {
+            fs::create_dir_all(&folder).map_err(|e| format!("Could not create folder `{}`: {}", folder, e))?;
+        }
+        verify_writable(&storage_folder)?;
+        verify_writable(&delete_folder)?;
+        verify_same_filesystem(&storage_folder, &delete_folder)?;
+
+        let mut provider = Self {
+            folder: storage_folder,
+            delete: delete_folder,
+            config,
+            journal: None,
+            index: None,
+            temp_counter: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            failed_writes: AtomicU64::new(0),
+            write_window: Mutex::new(Instant::now()),
+            snapshot_lock: RwLock::new(()),
+            known_files: Mutex::new(HashMap::new()),
+            #[cfg(feature = "watch")]
+            watcher: None,
+            recovery_report: RecoveryReport::default(),
+        };
+        if provider.config.journal {
+            let journal_folder = format!("{}/{}", provider.folder, JOURNAL_FOLDER);
+            fs::create_dir_all(&journal_folder).map_err(|e| format!("Could not create folder `{}`: {}", journal_folder, e))?;
+            let (journal, pending) = Journal::open(&format!("{}/journal.log", journal_folder))?;
+            for (id, intent) in pending {
+                provider.replay(&intent);
+                journal.commit(id);
+                provider.recovery_report.replayed_operations += 1;
+            }
+            provider.journal = Some(journal);
+        }
+        provider.remove_temp_files();
+        provider.resolve_conflicts();
+        #[cfg(feature = "watch")]
+        if provider.config.watch {
+            provider.watcher = Some(watch::FolderWatcher::new(&provider.folder)?);
+        }
+        provider.known_files = Mutex::new(provider.scan_files(None));
+        if provider.config.index {
+            let index_folder = format!("{}/{}", provider.folder, INDEX_FOLDER);
+            fs::create_dir_all(&index_folder).map_err(|e| format!("Could not create folder `{}`: {}", index_folder, e))?;
+            provider.index = Some(Index::open(&format!("{}/index.log", index_folder))?);
+            provider.sync_index()?;
+        }
+        Ok(provider)
+    }
+
+    /// Map the data of an entry into memory without copying it, useful for large entries which are read often.
+    /// 
+    /// The provider never changes a data file in place: saves write a temp file and move it in place, deletes move the file
+    /// into the delete folder. A mapping therefore keeps showing the data at the time of the call, even if the entry is
+    /// overwritten or purged meanwhile. Files in the storage folder must not be truncated by other processes while mapped.
+    #[cfg(feature = "mmap")]
+    pub fn get_mapped(self: &FileStorageProvider, key: &str) -> Result<MappedData, String> {
+        OperationSpan::provider("get_mapped", &self.folder, key).entered().run(|span| {
+            let fields = sidecar::read(&self.internal_meta_path(key))?;
+            if is_expired(expires_field(&fields)) {
+                return Err("Expired".to_owned());
+            }
+            let file = File::open(self.internal_file_path(key)).map_err(|_e| "File open error".to_owned())?;
+            let meta = file.metadata().map_err(|e| format!("File stat error: {}", e))?;
+            if !meta.is_file() {
+                return Err("Not found".to_owned());
+            }
+            // SAFETY: the provider only replaces data files by renaming a new file over them, so the mapped file is never
+            // truncated or written by the provider, see the documentation of this function
+            let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| format!("Could not map file: {}", e))?;
+            span.record_size(map.len() as u64);
+            Ok(MappedData {
+                key: key.to_owned(),
+                size: map.len(),
+                metadata: user_metadata(&fields),
+                etag: etag(&meta),
+                map,
+            })
+        })
+    }
+
+    /// Indexed size, timestamps, checksum and metadata of an entry, `None` if the index is disabled or the key is not indexed.
+    pub fn index_entry(self: &FileStorageProvider, key: &str) -> Option<IndexEntry> {
+        self.index.as_ref().and_then(|index| index.get(key))
+    }
+
+    /// Rebuild the index from the storage folder, e.g. after the index log was damaged. Returns the count of indexed entries.
+    pub fn rebuild_index(self: &FileStorageProvider) -> Result<usize, String> {
+        let index = self.index.as_ref().ok_or_else(|| "Index is not enabled".to_owned())?;
+        let _lock = self.snapshot_lock.write().unwrap_or_else(|e| e.into_inner());
+        let mut entries = BTreeMap::new();
+        for key in self.scan_files(None).into_keys() {
+            if let Some(entry) = self.read_index_entry(&key) {
+                entries.insert(key, entry);
+            }
+        }
+        let count = entries.len();
+        index.replace(entries)?;
+        Ok(count)
+    }
+
+    /// Update the index entries of all files which were changed while the provider was not running and compact the index.
+    fn sync_index(self: &FileStorageProvider) -> Result<(), String> {
+        if let Some(index) = &self.index {
+            let indexed = index.etags();
+            let files = self.known_files.lock().unwrap().clone();
+            for key in indexed.keys().filter(|key| !files.contains_key(*key)) {
+                index.remove(key);
+            }
+            for (key, etag) in files.iter() {
+                if indexed.get(key) != Some(etag) {
+                    self.update_index(key);
+                }
+            }
+            index.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Update the index entry of a key from the files of the entry.
+    fn update_index(self: &FileStorageProvider, key: &str) {
+        if let Some(index) = &self.index {
+            match self.read_index_entry(key) {
+                Some(entry) => index.put(key, entry),
+                None => index.remove(key),
+            }
+        }
+    }
+
+    fn read_index_entry(self: &FileStorageProvider, key: &str) -> Option<IndexEntry> {
+        let path = self.internal_file_path(key);
+        let meta = fs::metadata(&path).ok().filter(|meta| meta.is_file())?;
+        let fields = sidecar::read(&self.internal_meta_path(key)).ok()?;
+        let etag = etag(&meta);
+        // the stored checksum is only valid if the file was not replaced outside of the provider
+        let stored = fields.get(CHECKSUM_FIELD).and_then(|field| field.split_once(':')).filter(|(_checksum, checksum_etag)| *checksum_etag == etag);
+        let checksum = match stored.and_then(|(checksum, _etag)| u32::from_str_radix(checksum, 16).ok()) {
+            Some(checksum) => checksum,
+            None => crc32_reader(&mut File::open(&path).ok()?).ok()?.0,
+        };
+        Some(IndexEntry {
+            size: meta.len(),
+            modified: meta.modified().ok()?,
+            expires: expires_field(&fields),
+            checksum,
+            metadata: user_metadata(&fields),
+            etag,
+        })
+    }
+
+    /// Changes made during the startup of the provider.
+    pub fn recovery_report(self: &FileStorageProvider) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Remove all temp files, at startup they can only belong to saves and snapshots which never finished.
+    fn remove_temp_files(self: &mut FileStorageProvider) {
+        if let Ok(read_dir) = fs::read_dir(format!("{}/{}", self.folder, TEMP_FOLDER)) {
+            for entry in read_dir.flatten() {
+                let result = if entry.path().is_dir() { fs::remove_dir_all(entry.path()) } else { fs::remove_file(entry.path()) };
+                if result.is_ok() {
+                    self.recovery_report.removed_temp_files.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    /// Keep only the newer copy of keys which are in the storage and in the delete folder.
+    fn resolve_conflicts(self: &mut FileStorageProvider) {
+        for entry in self.queued_entries() {
+            let live_modified = fs::metadata(self.internal_file_path(&entry.handle)).and_then(|meta| meta.modified());
+            if let Ok(live_modified) = live_modified {
+                if live_modified > entry.modified {
+                    self.purge_entry(&entry.handle);
+                    self.recovery_report.resolved_conflicts.push((entry.handle, ConflictResolution::KeptLive));
+                } else {
+                    // the delete was interrupted after the data was copied
+                    let _result = fs::remove_file(self.internal_file_path(&entry.handle));
+                    let _result = fs::rename(self.internal_meta_path(&entry.handle), self.internal_meta_delete_path(&entry.handle));
+                    self.recovery_report.resolved_conflicts.push((entry.handle, ConflictResolution::KeptDeleted));
+                }
+            }
+        }
+    }
+
+    /// Rewrite the journal with only the changes which are still in progress.
+    pub fn compact_journal(self: &FileStorageProvider) -> Result<(), String> {
+        match &self.journal {
+            Some(journal) => journal.compact(),
+            None => Ok(()),
+        }
+    }
+
+    fn internal_file_path(self: &FileStorageProvider, key: &str) -> String {
+        return format!("{}/{}", self.folder, key.to_owned());
+    }
+
+    fn internal_file_delete_path(self: &FileStorageProvider, key: &str) -> String {
+        return format!("{}/{}", self.delete, key.to_owned());
+    }
+
+    fn internal_temp_path(self: &FileStorageProvider, temp: &str, extension: &str) -> String {
+        format!("{}/{}/{}.{}", self.folder, TEMP_FOLDER, temp, extension)
+    }
+
+    /// Acquire the lock of a key, the lock is released when the returned guard is dropped.
+    /// 
+    /// The lock file is created exclusively, so only one writer of all processes which share the folder holds the lock.
+    /// The lock file contains a unique owner, so a stale lock is only removed if it was not replaced by a new lock in between.
+    fn lock(self: &FileStorageProvider, key: &str) -> Result<LockGuard, StorageError> {
+        let path = format!("{}/{}/{}", self.folder, LOCK_FOLDER, key);
+        let owner = self.temp_name();
+        let started = SystemTime::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    if let Err(e) = file.write_all(owner.as_bytes()) {
+                        let _result = fs::remove_file(&path);
+                        return Err(StorageError::Provider(format!("Could not create lock: {}", e)));
+                    }
+                    return Ok(LockGuard { path, owner });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if let Some(stale_owner) = stale_lock_owner(&path) {
+                        remove_lock(&path, &stale_owner, &self.temp_name());
+                    } else if SystemTime::now().duration_since(started).unwrap_or(Duration::ZERO) > LOCK_WAIT {
+                        return Err(StorageError::Provider(format!("Timeout while waiting for the lock of `{}`", key)));
+                    } else {
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                }
+                Err(e) => return Err(StorageError::Provider(format!("Could not create lock: {}", e))),
+            }
+        }
+    }
+
+    /// Move a staged entry in place while holding the lock of the key, so a save never replaces the entry between the check and the commit of a conditional save.
+    fn commit_locked(self: &FileStorageProvider, key: &str, token: &str) -> Result<Option<String>, String> {
+        let _guard = self.lock(key).map_err(|e| self.write_failed(e.to_string()))?;
+        self.commit_version(key, token)
+    }
+
+    /// Stage an entry, acquire the lock of the key and commit the entry if `condition` returns `Ok`.
+    fn save_conditional(
+        self: &FileStorageProvider,
+        key: &str,
+        raw: Vec<u8>,
+        options: &SaveOptions,
+        condition: impl Fn() -> Result<(), StorageError>,
+    ) -> Result<SaveData, StorageError> {
+        let (token, size) = self.stage_from_reader(&mut raw.as_slice(), options)?;
+        let result = self.lock(key).and_then(|_guard| {
+            condition()?;
+            Ok(self.commit_version(key, &token)?)
+        });
+        match result {
+            Ok(version) => Ok(SaveData {
+                key: key.to_owned(),
+                size: size as usize,
+                version,
+            }),
+            Err(err) => {
+                self.discard_staged(&token);
+                Err(err)
+            }
+        }
+    }
+
+    /// Stat of an entry read from its files, other than `stat` this never uses the index.
+    /// 
+    /// Conditional saves must check the files, the index does not contain saves of other processes.
+    fn stat_file(self: &FileStorageProvider, key: &str) -> Result<StatData, String> {
+        let meta = fs::metadata(self.internal_file_path(key)).map_err(|e| format!("File stat error: {}", e))?;
+        if !meta.is_file() {
+            return Err("Not found".to_owned());
+        }
+        let fields = sidecar::read(&self.internal_meta_path(key))?;
+        let expires = expires_field(&fields);
+        if is_expired(expires) {
+            return Err("Expired".to_owned());
+        }
+        Ok(StatData {
+            key: key.to_owned(),
+            size: meta.len() as usize,
+            modified: meta.modified().map_err(|e| format!("File stat error: {}", e))?,
+            expires,
+            metadata: user_metadata(&fields),
+            etag: etag(&meta),
+        })
+    }
+
+    /// Unique name for temp files of a save.
+    fn temp_name(self: &FileStorageProvider) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        format!("{}-{}-{}", std::process::id(), nanos, self.temp_counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Unique name which sorts in the order the names were created.
+    fn sortable_name(self: &FileStorageProvider) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        format!("{:032x}-{:016x}", nanos, self.temp_counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Lock which must be held while entries are changed, so snapshots never contain a partial change.
+    fn change_guard(self: &FileStorageProvider) -> RwLockReadGuard<'_, ()> {
+        self.snapshot_lock.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Etags of the files in the storage folder, only the `keys` if set.
+    fn scan_files(self: &FileStorageProvider, keys: Option<HashSet<String>>) -> HashMap<String, String> {
+        let mut files = HashMap::new();
+        match keys {
+            Some(keys) => {
+                for key in keys {
+                    if let Ok(meta) = fs::metadata(self.internal_file_path(&key)) {
+                        if meta.is_file() {
+                            files.insert(key, etag(&meta));
+                        }
+                    }
+                }
+            }
+            None => {
+                if let Ok(read_dir) = fs::read_dir(&self.folder) {
+                    for entry in read_dir.flatten() {
+                        if let (Ok(meta), Some(key)) = (entry.metadata(), entry.file_name().to_str()) {
+                            if meta.is_file() && !key.starts_with('.') {
+                                files.insert(key.to_owned(), etag(&meta));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// Record the current file of a key after a change made by the provider, so it is not reported by `poll_changes`.
+    /// 
+    /// The index entry of the key is updated as well.
+    fn track(self: &FileStorageProvider, key: &str) {
+        {
+            let mut known_files = self.known_files.lock().unwrap();
+            match fs::metadata(self.internal_file_path(key)) {
+                Ok(meta) if meta.is_file() => known_files.insert(key.to_owned(), etag(&meta)),
+                _ => known_files.remove(key),
+            };
+        }
+        self.update_index(key);
+    }
+
+    fn internal_snapshot_path(self: &FileStorageProvider, snapshot: &str, key: &str) -> String {
+        format!("{}/{}/{}/{}", self.folder, SNAPSHOT_FOLDER, snapshot, key)
+    }
+
+    fn internal_snapshot_meta_path(self: &FileStorageProvider, snapshot: &str, key: &str) -> String {
+        format!("{}/{}/{}/{}/{}", self.folder, SNAPSHOT_FOLDER, snapshot, META_FOLDER, key)
+    }
+
+    /// Hard link all entries into the folder, the metadata is copied because it can be changed in place.
+    fn link_entries(self: &FileStorageProvider, folder: &str) -> Result<(), String> {
+        fs::create_dir_all(format!("{}/{}", folder, META_FOLDER)).map_err(|e| format!("Could not create snapshot: {}", e))?;
+        for key in self.list("")? {
+            fs::hard_link(self.internal_file_path(&key), format!("{}/{}", folder, key)).map_err(|e| format!("Could not create snapshot: {}", e))?;
+            let meta_path = self.internal_meta_path(&key);
+            if Path::new(&meta_path).exists() {
+                fs::copy(meta_path, format!("{}/{}/{}", folder, META_FOLDER, key)).map_err(|e| format!("Could not create snapshot: {}", e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the begin of a change in the journal, returns `None` if the journal is disabled.
+    fn journal_begin(self: &FileStorageProvider, intent: Intent) -> Result<Option<u64>, String> {
+        match &self.journal {
+            Some(journal) => journal.begin(intent).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn journal_commit(self: &FileStorageProvider, id: Option<u64>) {
+        if let (Some(journal), Some(id)) = (&self.journal, id) {
+            journal.commit(id);
+        }
+    }
+
+    /// Complete or roll back a change which was not committed in the journal.
+    fn replay(self: &FileStorageProvider, intent: &Intent) {
+        match intent {
+            Intent::Save { key, temp, has_meta } => {
+                let data_temp = self.internal_temp_path(temp, "data");
+                if Path::new(&data_temp).exists() {
+                    // the entry was not replaced, discard the new data and the version which shares its file
+                    for version in self.version_entries(key) {
+                        if same_file(&self.internal_version_path(key, &version.handle, "data"), &data_temp) {
+                            self.remove_version(key, &version.handle);
+                        }
+                    }
+                    let _result = fs::remove_file(data_temp);
+                    let _result = fs::remove_file(self.internal_temp_path(temp, "meta"));
+                } else {
+                    self.finish_save(key, temp, *has_meta);
+                }
+            }
+            Intent::Delete { key, has_meta } => {
+                if !Path::new(&self.internal_file_path(key)).exists() {
+                    self.finish_delete(key, *has_meta);
+                }
+            }
+            Intent::Rename { from, to, overwrite, has_meta } => {
+                let from_path = self.internal_file_path(from);
+                if !*overwrite && Path::new(&from_path).exists() && same_file(&from_path, &self.internal_file_path(to)) {
+                    let _result = fs::remove_file(&from_path);
+                }
+                if !Path::new(&from_path).exists() {
+                    self.finish_rename(from, to, *has_meta);
+                }
+            }
+            Intent::Restore { key, has_meta } => {
+                if !Path::new(&self.internal_file_delete_path(key)).exists() {
+                    self.finish_restore(key, *has_meta);
+                }
+            }
+            Intent::Purge { key } => self.purge_entry_files(key),
+            Intent::Discard { key } => self.discard_entry_files(key),
+        }
+    }
+
+    /// Remove the data, the metadata and the version which shares the file of an entry from the storage folder.
+    fn discard_entry_files(self: &FileStorageProvider, key: &str) {
+        let path = self.internal_file_path(key);
+        for version in self.version_entries(key) {
+            if same_file(&self.internal_version_path(key, &version.handle, "data"), &path) {
+                self.remove_version(key, &version.handle);
+            }
+        }
+        let _result = fs::remove_file(&path);
+        let _result = sidecar::remove(&self.internal_meta_path(key));
+    }
+
+    /// Move the metadata and the versions of a renamed entry to the new key, the data must already be moved.
+    fn finish_rename(self: &FileStorageProvider, from: &str, to: &str, has_meta: bool) {
+        if has_meta {
+            let meta_path = self.internal_meta_path(from);
+            if Path::new(&meta_path).exists() {
+                let _result = fs::rename(meta_path, self.internal_meta_path(to));
+            }
+        } else {
+            let _result = sidecar::remove(&self.internal_meta_path(to));
+        }
+        // the versions of the old key replace the versions of a replaced entry
+        let version_folder = self.internal_version_folder(from);
+        if Path::new(&version_folder).exists() {
+            let target = self.internal_version_folder(to);
+            let _result = fs::remove_dir_all(&target);
+            let _result = fs::rename(version_folder, target);
+        }
+    }
+
+    /// Move the metadata of a restored entry back into the storage folder, the data must already be moved.
+    fn finish_restore(self: &FileStorageProvider, key: &str, has_meta: bool) {
+        if has_meta {
+            let meta_path = self.internal_meta_delete_path(key);
+            if Path::new(&meta_path).exists() {
+                let _result = fs::rename(meta_path, self.internal_meta_path(key));
+            }
+        } else {
+            let _result = sidecar::remove(&self.internal_meta_path(key));
+        }
+    }
+
+    /// Move the metadata of a saved entry in place, the data must already be moved.
+    fn finish_save(self: &FileStorageProvider, key: &str, temp: &str, has_meta: bool) {
+        if has_meta {
+            let meta_temp = self.internal_temp_path(temp, "meta");
+            if Path::new(&meta_temp).exists() {
+                let _result = fs::rename(meta_temp, self.internal_meta_path(key));
+            }
+        } else {
+            let _result = sidecar::remove(&self.internal_meta_path(key));
+        }
+    }
+
+    /// Move the metadata of a deleted entry into the delete folder, the data must already be moved.
+    fn finish_delete(self: &FileStorageProvider, key: &str, has_meta: bool) {
+        if has_meta {
+            let meta_path = self.internal_meta_path(key);
+            if Path::new(&meta_path).exists() {
+                let _result = fs::rename(meta_path, self.internal_meta_delete_path(key));
+            }
+        } else {
+            // an entry without metadata must not keep the metadata of an older deleted entry
+            let _result = sidecar::remove(&self.internal_meta_delete_path(key));
+        }
+    }
+
+    fn internal_version_folder(self: &FileStorageProvider, key: &str) -> String {
+        format!("{}/{}/{}", self.folder, VERSION_FOLDER, key)
+    }
+
+    fn internal_version_path(self: &FileStorageProvider, key: &str, version: &str, extension: &str) -> String {
+        format!("{}/{}.{}", self.internal_version_folder(key), version, extension)
+    }
+
+    /// Move a staged entry in place, returns the ID of the new version if versioning is enabled.
+    fn commit_version(self: &FileStorageProvider, key: &str, token: &str) -> Result<Option<String>, String> {
+        let data_temp = self.internal_temp_path(token, "data");
+        if !Path::new(&data_temp).exists() {
+            return Err("Staged save not found".to_owned());
+        }
+        let has_meta = Path::new(&self.internal_temp_path(token, "meta")).exists();
+        let _guard = self.change_guard();
+        // the version is linked after the journal entry, so a replay which discards the save also removes the version
+        let id = self.journal_begin(Intent::Save { key: key.to_owned(), temp: token.to_owned(), has_meta })?;
+        let version = match self.link_version(key, token, has_meta) {
+            Ok(version) => version,
+            Err(err) => {
+                self.journal_commit(id);
+                return Err(self.write_failed(err));
+            }
+        };
+        if fs::rename(data_temp, self.internal_file_path(key)).is_err() {
+            self.journal_commit(id);
+            if let Some(version) = &version {
+                self.remove_version(key, version);
+            }
+            return Err(self.write_failed("Could not save".to_owned()));
+        }
+        self.finish_save(key, token, has_meta);
+        self.journal_commit(id);
+        self.track(key);
+        sync_folder(&self.folder);
+        Ok(version)
+    }
+
+    /// Add the staged data to the versions of the key, the version shares the file with the entry.
+    fn link_version(self: &FileStorageProvider, key: &str, token: &str, has_meta: bool) -> Result<Option<String>, String> {
+        if self.config.versions.is_none() {
+            return Ok(None);
+        }
+        let version = self.sortable_name();
+        fs::create_dir_all(self.internal_version_folder(key)).map_err(|e| format!("Could not create version folder: {}", e))?;
+        fs::hard_link(self.internal_temp_path(token, "data"), self.internal_version_path(key, &version, "data"))
+            .map_err(|e| format!("Could not save version: {}", e))?;
+        if has_meta {
+            if let Err(e) = fs::copy(self.internal_temp_path(token, "meta"), self.internal_version_path(key, &version, "meta")) {
+                self.remove_version(key, &version);
+                return Err(format!("Could not save version: {}", e));
+            }
+        }
+        Ok(Some(version))
+    }
+
+    fn remove_version(self: &FileStorageProvider, key: &str, version: &str) {
+        let _result = fs::remove_file(self.internal_version_path(key, version, "data"));
+        let _result = fs::remove_file(self.internal_version_path(key, version, "meta"));
+    }
+
+    /// All versions of a key, the oldest version first.
+    fn version_entries(self: &FileStorageProvider, key: &str) -> Vec<QueuedEntry<String>> {
+        let mut entries = vec![];
+        if let Ok(read_dir) = fs::read_dir(self.internal_version_folder(key)) {
+            for entry in read_dir.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let (Ok(meta), Some(version)) = (entry.metadata(), name.strip_suffix(".data")) {
+                    if let Ok(modified) = meta.modified() {
+                        entries.push(QueuedEntry {
+                            handle: version.to_owned(),
+                            size: meta.len(),
+                            modified,
+                        });
+                    }
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.handle.cmp(&b.handle));
+        entries
+    }
+
+    /// Remove the old versions of all keys until the version retention is satisfied.
+    fn purge_versions(self: &FileStorageProvider, force: bool) {
+        let retention = match &self.config.versions {
+            Some(retention) => retention,
+            None => return,
+        };
+        let max_age = if force { retention.force_max_age } else { retention.max_age };
+        // the newest version is excluded from the selection, so it is never purged
+        let limits = Retention {
+            max_entries: retention.max_entries.map(|max| max.saturating_sub(1)),
+            ..retention.clone()
+        };
+        if let Ok(read_dir) = fs::read_dir(format!("{}/{}", self.folder, VERSION_FOLDER)) {
+            for entry in read_dir.flatten() {
+                if let Some(key) = entry.file_name().to_str() {
+                    let mut versions = self.version_entries(key);
+                    versions.pop();
+                    for version in limits.select_purge(versions, max_age) {
+                        self.remove_version(key, &version.handle);
+                    }
+                }
+            }
+        }
+    }
+
+    fn internal_meta_path(self: &FileStorageProvider, key: &str) -> String {
+        format!("{}/{}/{}", self.folder, META_FOLDER, key)
+    }
+
+    fn internal_meta_delete_path(self: &FileStorageProvider, key: &str) -> String {
+        format!("{}/{}/{}", self.delete, META_FOLDER, key)
+    }
+
+    /// Expire time of an entry, `None` if the entry never expires.
+    fn expires(self: &FileStorageProvider, key: &str) -> Option<SystemTime> {
+        sidecar::read(&self.internal_meta_path(key)).ok().and_then(|fields| expires_field(&fields))
+    }
+
+    fn is_expired(self: &FileStorageProvider, key: &str) -> bool {
+        is_expired(self.expires(key))
+    }
+
+    fn write_expires(self: &FileStorageProvider, key: &str, ttl: Option<Duration>) -> Result<(), String> {
+        let meta_path = self.internal_meta_path(key);
+        let mut fields = sidecar::read(&meta_path)?;
+        set_expires_field(&mut fields, ttl)?;
+        sidecar::write(&meta_path, &fields)
+    }
+
+    /// Write the data and metadata of an entry into temp files, returns the byte size of the data.
+    fn write_temp(self: &FileStorageProvider, temp: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<u64, String> {
+        let mut fields = sidecar::Fields::new();
+        set_expires_field(&mut fields, options.ttl)?;
+        for (name, value) in options.metadata.iter() {
+            fields.insert(format!("{}{}", USER_FIELD_PREFIX, name), value.to_owned());
+        }
+        let mut buffer = File::create(self.internal_temp_path(temp, "data")).map_err(|_e| "Could not save".to_owned())?;
+        let mut reader = ChecksumReader::new(reader);
+        let size = std::io::copy(&mut reader, &mut buffer).map_err(|_e| "Could not save".to_owned())?;
+        buffer.sync_all().map_err(|_e| "Could not save".to_owned())?;
+        if self.index.is_some() {
+            // the file keeps its etag when it is moved in place
+            let meta = buffer.metadata().map_err(|_e| "Could not save".to_owned())?;
+            fields.insert(CHECKSUM_FIELD.to_owned(), format!("{:08x}:{}", reader.finish().0, etag(&meta)));
+        }
+        sidecar::write(&self.internal_temp_path(temp, "meta"), &fields)?;
+        Ok(size)
+    }
+
+    /// Write the data of an entry into temp files, the token is used for `commit_staged`.
+    fn stage_from_reader(self: &FileStorageProvider, reader: &mut dyn Read, options: &SaveOptions) -> Result<(String, u64), String> {
+        let temp = self.temp_name();
+        self.count_write();
+        match self.write_temp(&temp, reader, options) {
+            Ok(size) => Ok((temp, size)),
+            Err(err) => {
+                self.discard_staged(&temp);
+                Err(self.write_failed(err))
+            }
+        }
+    }
+
+    /// Count a write for the error rate of the health check, starts a new window when the current one has expired.
+    fn count_write(self: &FileStorageProvider) {
+        let mut window = self.write_window.lock().unwrap();
+        if window.elapsed() >= ERROR_RATE_WINDOW {
+            self.writes.store(0, Ordering::Relaxed);
+            self.failed_writes.store(0, Ordering::Relaxed);
+            *window = Instant::now();
+        }
+        self.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a failed write for the error rate of the health check.
+    fn write_failed(self: &FileStorageProvider, err: String) -> String {
+        self.failed_writes.fetch_add(1, Ordering::Relaxed);
+        err
+    }
+
+    /// Move all expired entries into the delete folder.
+    fn delete_expired(self: &FileStorageProvider) {
+        if let Ok(read_dir) = fs::read_dir(format!("{}/{}", self.folder, META_FOLDER)) {
+            for entry in read_dir.flatten() {
+                if let Some(key) = entry.file_name().to_str() {
+                    if self.is_expired(key) {
+                        self.delete(key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// All files in the delete folder, hidden files like the write probe are not entries.
+    fn queued_entries(self: &FileStorageProvider) -> Vec<QueuedEntry<String>> {
+        let mut entries = vec![];
+        if let Ok(read_dir) = fs::read_dir(&self.delete) {
+            for entry in read_dir.flatten() {
+                if let (Ok(meta), Some(key)) = (entry.metadata(), entry.file_name().to_str()) {
+                    if meta.is_file() && !key.starts_with('.') {
+                        if let Ok(modified) = meta.modified() {
+                            entries.push(QueuedEntry {
+                                handle: key.to_owned(),
+                                size: meta.len(),
+                                modified,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    /// Remove an entry and its metadata from the delete folder.
+    fn purge_entry(self: &FileStorageProvider, key: &str) {
+        if let Ok(id) = self.journal_begin(Intent::Purge { key: key.to_owned() }) {
+            self.purge_entry_files(key);
+            self.journal_commit(id);
+        }
+    }
+
+    fn purge_entry_files(self: &FileStorageProvider, key: &str) {
+        let _delete_result = fs::remove_file(self.internal_file_delete_path(key));
+        let _delete_result = sidecar::remove(&self.internal_meta_delete_path(key));
+    }
+
+    /// Purge the delete folder until the retention limits are satisfied.
+    fn purge(self: &FileStorageProvider, retention: &Retention, max_age: Option<Duration>) {
+        for entry in retention.select_purge(self.queued_entries(), max_age) {
+            self.purge_entry(&entry.handle);
+        }
+    }
+}
+
+impl StorageProvider for FileStorageProvider {
+    fn get(self: &FileStorageProvider, key: &str) -> Result<GetData, String> {
+        OperationSpan::provider("get", &self.folder, key).entered().run(|span| {
+            let fields = sidecar::read(&self.internal_meta_path(key))?;
+            if is_expired(expires_field(&fields)) {
+                return Err("Expired".to_owned());
+            }
+            let file_result = File::open(self.internal_file_path(key));
+            if let Ok(mut file) = file_result {
+                let mut buffer = Vec::new();
+                let read_size_result = file.read_to_end(&mut buffer);
+                if let (Ok(f_size), Ok(meta)) = (read_size_result, file.metadata()) {
+                    span.record_size(f_size as u64);
+                    return Ok(GetData {
+                        key: key.to_owned(),
+                        size: f_size,
+                        data: buffer,
+                        metadata: user_metadata(&fields),
+                        etag: etag(&meta),
+                    });
+                }
+            } else {
+                // TODO: check if in deleted queue and restore if possible
+                // TODO: provide detail error to callers
+                let _f_err = file_result.err();
+                return Err("File open error".to_owned());
+            }
+            Err("Not found".to_owned())
+        })
+    }
+
+    fn save(self: &FileStorageProvider, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
+        self.save_with_options(key, raw, &SaveOptions::default())
+    }
+
+    fn save_with_options(self: &FileStorageProvider, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<SaveData, String> {
+        OperationSpan::provider("save_with_options", &self.folder, key).entered().run(|span| {
+            // the entry is written to temp files first and moved in place, readers never see a partially written entry
+            let size = raw.len();
+            span.record_size(size as u64);
+            let token = self.stage_save(key, raw, options)?;
+            match self.commit_locked(key, &token) {
+                Ok(version) => Ok(SaveData {
+                    key: key.to_owned(),
+                    size,
+                    version,
+                }),
+                Err(err) => {
+                    self.discard_staged(&token);
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    fn get_reader(self: &FileStorageProvider, key: &str) -> Result<Box<dyn Read + '_>, String> {
+        OperationSpan::provider("get_reader", &self.folder, key).entered().run(|_span| {
+            if self.is_expired(key) {
+                return Err("Expired".to_owned());
+            }
+            let file = File::open(self.internal_file_path(key)).map_err(|_e| "File open error".to_owned())?;
+            let reader: Box<dyn Read + '_> = Box::new(std::io::BufReader::new(file));
+            Ok(reader)
+        })
+    }
+
+    fn save_from_reader(self: &FileStorageProvider, key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<SaveData, String> {
+        OperationSpan::provider("save_from_reader", &self.folder, key).entered().run(|span| {
+            let (token, size) = self.stage_from_reader(reader, options)?;
+            span.record_size(size);
+            match self.commit_locked(key, &token) {
+                Ok(version) => Ok(SaveData {
+                    key: key.to_owned(),
+                    size: size as usize,
+                    version,
+                }),
+                Err(err) => {
+                    self.discard_staged(&token);
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    fn get_many(self: &FileStorageProvider, keys: &[&str]) -> Vec<Result<GetData, String>> {
+        parallel_map(keys.to_vec(), |key| self.get(key))
+    }
+
+    fn save_many(self: &FileStorageProvider, entries: Vec<(String, Vec<u8>)>) -> Vec<Result<SaveData, String>> {
+        parallel_map(entries, |(key, raw)| self.save(&key, raw))
+    }
+
+    fn delete_many(self: &FileStorageProvider, keys: &[&str]) {
+        parallel_map(keys.to_vec(), |key| self.delete(key));
+    }
+
+    fn supports_staging(self: &FileStorageProvider) -> bool {
+        true
+    }
+
+    fn stage_save(self: &FileStorageProvider, _key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<String, String> {
+        self.stage_from_reader(&mut raw.as_slice(), options).map(|(token, _size)| token)
+    }
+
+    fn stage_save_from_reader(self: &FileStorageProvider, _key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<String, String> {
+        self.stage_from_reader(reader, options).map(|(token, _size)| token)
+    }
+
+    fn commit_staged(self: &FileStorageProvider, key: &str, token: &str) -> Result<(), String> {
+        OperationSpan::provider("commit_staged", &self.folder, key).entered().run(|_span| {
+            self.commit_locked(key, token).map(|_version| ())
+        })
+    }
+
+    fn get_staged_reader(self: &FileStorageProvider, token: &str) -> Result<Box<dyn Read + '_>, String> {
+        let file = File::open(self.internal_temp_path(token, "data")).map_err(|_e| "Staged save not found".to_owned())?;
+        let reader: Box<dyn Read + '_> = Box::new(std::io::BufReader::new(file));
+        Ok(reader)
+    }
+
+    fn discard_staged(self: &FileStorageProvider, token: &str) {
+        let _result = fs::remove_file(self.internal_temp_path(token, "data"));
+        let _result = fs::remove_file(self.internal_temp_path(token, "meta"));
+    }
+
+    fn rename(self: &FileStorageProvider, old_key: &str, new_key: &str, overwrite: bool) -> Result<(), StorageError> {
+        OperationSpan::provider("rename", &self.folder, old_key).entered().run(|_span| {
+            self.stat(old_key)?;
+            if old_key == new_key {
+                return Ok(());
+            }
+            let from = self.internal_file_path(old_key);
+            let to = self.internal_file_path(new_key);
+            if !overwrite && self.stat(new_key).is_ok() {
+                return Err(StorageError::Conflict(new_key.to_owned()));
+            }
+            let has_meta = Path::new(&self.internal_meta_path(old_key)).exists();
+            let _guard = self.change_guard();
+            let id = self.journal_begin(Intent::Rename { from: old_key.to_owned(), to: new_key.to_owned(), overwrite, has_meta })?;
+            let result = if overwrite {
+                fs::rename(&from, &to)
+            } else {
+                // a hard link fails if the new key exists, other than `fs::rename` which replaces it
+                fs::hard_link(&from, &to).and_then(|_| fs::remove_file(&from))
+            };
+            if let Err(e) = result {
+                self.journal_commit(id);
+                if e.kind() == std::io::ErrorKind::AlreadyExists {
+                    return Err(StorageError::Conflict(new_key.to_owned()));
+                }
+                return Err(StorageError::Provider(format!("Could not rename: {}", e)));
+            }
+            self.finish_rename(old_key, new_key, has_meta);
+            self.journal_commit(id);
+            self.track(old_key);
+            self.track(new_key);
+            sync_folder(&self.folder);
+            Ok(())
+        })
+    }
+
+    fn get_version(self: &FileStorageProvider, key: &str, version: &str) -> Result<GetData, String> {
+        OperationSpan::provider("get_version", &self.folder, key).entered().run(|span| {
+            if !is_sortable_name(version) {
+                return Err("Version not found".to_owned());
+            }
+            let data = fs::read(self.internal_version_path(key, version, "data")).map_err(|_e| "Version not found".to_owned())?;
+            let meta = fs::metadata(self.internal_version_path(key, version, "data")).map_err(|_e| "Version not found".to_owned())?;
+            let fields = sidecar::read(&self.internal_version_path(key, version, "meta"))?;
+            span.record_size(data.len() as u64);
+            Ok(GetData {
+                key: key.to_owned(),
+                size: data.len(),
+                data,
+                metadata: user_metadata(&fields),
+                etag: etag(&meta),
+            })
+        })
+    }
+
+    fn list_versions(self: &FileStorageProvider, key: &str) -> Result<Vec<VersionData>, String> {
+        OperationSpan::provider("list_versions", &self.folder, key).entered().run(|_span| {
+            if self.config.versions.is_none() {
+                return Err("Versioning is not enabled".to_owned());
+            }
+            Ok(self
+                .version_entries(key)
+                .into_iter()
+                .map(|entry| VersionData {
+                    version: entry.handle,
+                    size: entry.size as usize,
+                    created: entry.modified,
+                })
+                .collect())
+        })
+    }
+
+    fn create_snapshot(self: &FileStorageProvider) -> Result<SnapshotId, String> {
+        OperationSpan::provider("create_snapshot", &self.folder, "").entered().run(|_span| {
+            let snapshot = self.sortable_name();
+            // the snapshot is built in the temp folder and moved in place, so it is never visible partially
+            let temp = self.internal_temp_path(&snapshot, "snapshot");
+            let _lock = self.snapshot_lock.write().unwrap_or_else(|e| e.into_inner());
+            if let Err(err) = self.link_entries(&temp) {
+                let _result = fs::remove_dir_all(&temp);
+                return Err(err);
+            }
+            fs::rename(&temp, format!("{}/{}/{}", self.folder, SNAPSHOT_FOLDER, snapshot)).map_err(|e| {
+                let _result = fs::remove_dir_all(&temp);
+                format!("Could not create snapshot: {}", e)
+            })?;
+            Ok(snapshot)
+        })
+    }
+
+    fn list_snapshots(self: &FileStorageProvider) -> Result<Vec<SnapshotData>, String> {
+        OperationSpan::provider("list_snapshots", &self.folder, "").entered().run(|_span| {
+            let entries = fs::read_dir(format!("{}/{}", self.folder, SNAPSHOT_FOLDER)).map_err(|e| format!("Could not read snapshot folder: {}", e))?;
+            let mut snapshots = vec![];
+            for entry in entries.flatten() {
+                if let (Ok(meta), Some(id)) = (entry.metadata(), entry.file_name().to_str()) {
+                    if let Ok(created) = meta.modified() {
+                        snapshots.push(SnapshotData { id: id.to_owned(), created });
+                    }
+                }
+            }
+            snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(snapshots)
+        })
+    }
+
+    fn get_snapshot_entry(self: &FileStorageProvider, snapshot: &str, key: &str) -> Result<GetData, String> {
+        OperationSpan::provider("get_snapshot_entry", &self.folder, key).entered().run(|span| {
+            let path = self.internal_snapshot_path(snapshot, key);
+            let data = fs::read(&path).map_err(|_e| "Not found in snapshot".to_owned())?;
+            let meta = fs::metadata(&path).map_err(|_e| "Not found in snapshot".to_owned())?;
+            let fields = sidecar::read(&self.internal_snapshot_meta_path(snapshot, key))?;
+            span.record_size(data.len() as u64);
+            Ok(GetData {
+                key: key.to_owned(),
+                size: data.len(),
+                data,
+                metadata: user_metadata(&fields),
+                etag: etag(&meta),
+            })
+        })
+    }
+
+    fn list_snapshot_entries(self: &FileStorageProvider, snapshot: &str, prefix: &str) -> Result<Vec<String>, String> {
+        OperationSpan::provider("list_snapshot_entries", &self.folder, prefix).entered().run(|_span| {
+            let entries = fs::read_dir(format!("{}/{}/{}", self.folder, SNAPSHOT_FOLDER, snapshot)).map_err(|_e| "Snapshot not found".to_owned())?;
+            let mut keys = vec![];
+            for entry in entries.flatten() {
+                if entry.path().is_file() {
+                    if let Some(key) = entry.file_name().to_str() {
+                        if key.starts_with(prefix) {
+                            keys.push(key.to_owned());
+                        }
+                    }
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+
+    fn delete_snapshot(self: &FileStorageProvider, snapshot: &str) -> Result<(), String> {
+        OperationSpan::provider("delete_snapshot", &self.folder, snapshot).entered().run(|_span| {
+            if snapshot.is_empty() || snapshot.contains('/') || snapshot.starts_with('.') {
+                return Err("Snapshot not found".to_owned());
+            }
+            fs::remove_dir_all(format!("{}/{}/{}", self.folder, SNAPSHOT_FOLDER, snapshot)).map_err(|_e| "Snapshot not found".to_owned())
+        })
+    }
+
+    fn restore(self: &FileStorageProvider, key: &str) -> Result<(), StorageError> {
+        OperationSpan::provider("restore", &self.folder, key).entered().run(|_span| {
+            let from = self.internal_file_delete_path(key);
+            if !Path::new(&from).is_file() {
+                return Err(StorageError::Provider("Not found in delete queue".to_owned()));
+            }
+            let has_meta = Path::new(&self.internal_meta_delete_path(key)).exists();
+            let _guard = self.change_guard();
+            let id = self.journal_begin(Intent::Restore { key: key.to_owned(), has_meta })?;
+            // a hard link fails if the key was saved again after the delete, other than `fs::rename` which replaces it
+            if let Err(e) = fs::hard_link(&from, self.internal_file_path(key)).and_then(|_| fs::remove_file(&from)) {
+                self.journal_commit(id);
+                if e.kind() == std::io::ErrorKind::AlreadyExists {
+                    return Err(StorageError::Conflict(key.to_owned()));
+                }
+                return Err(StorageError::Provider("Could not restore".to_owned()));
+            }
+            self.finish_restore(key, has_meta);
+            self.journal_commit(id);
+            self.track(key);
+            Ok(())
+        })
+    }
+
+    fn delete(self: &FileStorageProvider, key: &str) {
+        OperationSpan::provider("delete", &self.folder, key).entered().run(|_span| {
+            let from = self.internal_file_path(key).to_owned();
+            let to = self.internal_file_delete_path(key).to_owned();
+            let has_meta = Path::new(&self.internal_meta_path(key)).exists();
+            let _guard = self.change_guard();
+            if let Ok(id) = self.journal_begin(Intent::Delete { key: key.to_owned(), has_meta }) {
+                if fs::rename(from, to).is_ok() {
+                    self.finish_delete(key, has_meta);
+                }
+                self.journal_commit(id);
+                self.track(key);
+            }
+        })
     }
 
-    fn internal_file_path(self: &FileStorageProvider, key: &str) -> String {
-        return format!("{}/{}", self.folder, key.to_owned());
+    fn discard(self: &FileStorageProvider, key: &str) -> Result<(), String> {
+        OperationSpan::provider("discard", &self.folder, key).entered().run(|_span| {
+            let _guard = self.change_guard();
+            let id = self.journal_begin(Intent::Discard { key: key.to_owned() })?;
+            self.discard_entry_files(key);
+            self.journal_commit(id);
+            self.track(key);
+            sync_folder(&self.folder);
+            if Path::new(&self.internal_file_path(key)).exists() {
+                return Err("Could not discard".to_owned());
+            }
+            Ok(())
+        })
     }
 
-    fn internal_file_delete_path(self: &FileStorageProvider, key: &str) -> String {
-        return format!("{}/{}", self.delete, key.to_owned());
+    fn free(self: &FileStorageProvider) {
+        OperationSpan::provider("free", &self.folder, "").entered().run(|_span| {
+            self.delete_expired();
+            self.purge(&self.config.retention, self.config.retention.max_age);
+            self.purge_versions(false);
+        })
     }
 
-    fn delete_files_older_then(self: &FileStorageProvider, seconds: u64) {
-        for entry in fs::read_dir(self.delete.to_owned()).unwrap() {
-            if let Ok(entry_result) = entry {
-                let entry_path = entry_result.path();
-                if entry_path.is_file() {
-                    if seconds == 0 {
-                        let _delete_result = fs::remove_file(entry_path);
-                    } else {
-                        if let Ok(meta) = entry_path.metadata() {
-                            if let Ok(mod_time) = meta.modified() {
-                                let time_dif = SystemTime::now().duration_since(mod_time).expect("Current time minus file time must be positive");
-                                if time_dif.as_secs() > seconds {
-                                    let _delete_result = fs::remove_file(entry_path);
-                                }
-                            }
+    fn force_free(self: &FileStorageProvider, all: bool) {
+        OperationSpan::provider("force_free", &self.folder, "").entered().run(|_span| {
+            self.delete_expired();
+            self.purge_versions(true);
+            if all {
+                for entry in self.queued_entries() {
+                    self.purge_entry(&entry.handle);
+                }
+            } else {
+                self.purge(&self.config.retention, self.config.retention.force_max_age);
+            }
+        })
+    }
+
+    fn free_older_than(self: &FileStorageProvider, age: Duration) {
+        OperationSpan::provider("free_older_than", &self.folder, "").entered().run(|_span| {
+            let retention = Retention {
+                max_age: Some(age),
+                force_max_age: None,
+                max_bytes: None,
+                max_entries: None,
+            };
+            self.purge(&retention, retention.max_age);
+        })
+    }
+
+    fn list(self: &FileStorageProvider, prefix: &str) -> Result<Vec<String>, String> {
+        OperationSpan::provider("list", &self.folder, prefix).entered().run(|_span| {
+            if let Some(index) = &self.index {
+                return Ok(index.entries(prefix).into_iter().filter(|(_key, entry)| !is_expired(entry.expires)).map(|(key, _entry)| key).collect());
+            }
+            let entries = fs::read_dir(&self.folder).map_err(|e| format!("Could not read storage folder: {}", e))?;
+            let mut keys = vec![];
+            for entry in entries.flatten() {
+                if entry.path().is_file() {
+                    if let Some(key) = entry.file_name().to_str() {
+                        if key.starts_with(prefix) && !key.starts_with('.') && !self.is_expired(key) {
+                            keys.push(key.to_owned());
                         }
                     }
                 }
             }
-        }
+            keys.sort();
+            Ok(keys)
+        })
     }
-}
 
-impl StorageProvider for FileStorageProvider {
-    fn get(self: &FileStorageProvider, key: &str) -> Result<GetData, String> {
-        let file_result = File::open(self.internal_file_path(key));
-        if let Ok(mut file) = file_result {
-            let mut buffer = Vec::new();
-            let read_size_result = file.read_to_end(&mut buffer);
-            if let Ok(f_size) = read_size_result {
-                return Ok(GetData {
-                    key: key.to_owned(),
-                    size: f_size,
-                    data: buffer   
-                });
+    fn poll_changes(self: &FileStorageProvider) -> Result<Vec<ExternalChange>, String> {
+        OperationSpan::provider("poll_changes", &self.folder, "").entered().run(|_span| {
+            let _lock = self.snapshot_lock.write().unwrap_or_else(|e| e.into_inner());
+            #[cfg(feature = "watch")]
+            let keys = self.watcher.as_ref().and_then(|watcher| watcher.take_changed());
+            #[cfg(not(feature = "watch"))]
+            let keys: Option<HashSet<String>> = None;
+
+            let mut known_files = self.known_files.lock().unwrap();
+            let candidates: HashSet<String> = match &keys {
+                Some(keys) => keys.clone(),
+                None => known_files.keys().cloned().collect(),
+            };
+            let current = self.scan_files(keys);
+            let mut changes = vec![];
+            for key in candidates.iter().chain(current.keys().filter(|key| !candidates.contains(*key))) {
+                let kind = match (known_files.get(key), current.get(key)) {
+                    (None, Some(_)) => ChangeKind::Added,
+                    (Some(_), None) => ChangeKind::Removed,
+                    (Some(known), Some(etag)) if known != etag => ChangeKind::Modified,
+                    _ => continue,
+                };
+                let size = match kind {
+                    ChangeKind::Removed => 0,
+                    _ => fs::metadata(self.internal_file_path(key)).map(|meta| meta.len()).unwrap_or(0),
+                };
+                changes.push(ExternalChange { kind, key: key.to_owned(), size });
             }
-        } else {
-            // TODO: check if in deleted queue and restore if possible
-            // TODO: provide detail error to callers
-            let _f_err = file_result.err();
-            return Err("File open error".to_owned());
-        }
-        Err("Not found".to_owned())
+            for change in changes.iter() {
+                match current.get(&change.key) {
+                    Some(etag) => known_files.insert(change.key.to_owned(), etag.to_owned()),
+                    None => known_files.remove(&change.key),
+                };
+                self.update_index(&change.key);
+            }
+            changes.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(changes)
+        })
     }
 
-    fn save(self: &FileStorageProvider, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
-        let buffer_result = File::create(self.internal_file_path(key));
-        if let Ok(mut buffer) = buffer_result {
-            if buffer.write_all(&raw).is_ok() {
-                return Ok(SaveData {
+    fn list_deleted(self: &FileStorageProvider) -> Result<Vec<(String, u64)>, String> {
+        OperationSpan::provider("list_deleted", &self.folder, "").entered().run(|_span| {
+            let mut entries: Vec<(String, u64)> = self.queued_entries().into_iter().map(|entry| (entry.handle, entry.size)).collect();
+            entries.sort();
+            Ok(entries)
+        })
+    }
+
+    fn stat(self: &FileStorageProvider, key: &str) -> Result<StatData, String> {
+        OperationSpan::provider("stat", &self.folder, key).entered().run(|span| {
+            if let Some(index) = &self.index {
+                let entry = index.get(key).ok_or_else(|| "Not found".to_owned())?;
+                if is_expired(entry.expires) {
+                    return Err("Expired".to_owned());
+                }
+                span.record_size(entry.size);
+                return Ok(StatData {
                     key: key.to_owned(),
-                    size: raw.len(),
+                    size: entry.size as usize,
+                    modified: entry.modified,
+                    expires: entry.expires,
+                    metadata: entry.metadata,
+                    etag: entry.etag,
                 });
             }
+            let stat = self.stat_file(key)?;
+            span.record_size(stat.size as u64);
+            Ok(stat)
+        })
+    }
+
+    fn save_if_absent(self: &FileStorageProvider, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<SaveData, StorageError> {
+        OperationSpan::provider("save_if_absent", &self.folder, key).entered().run(|span| {
+            span.record_size(raw.len() as u64);
+            self.save_conditional(key, raw, options, || match self.stat_file(key) {
+                Ok(_) => Err(StorageError::Conflict(key.to_owned())),
+                Err(_) => Ok(()),
+            })
+        })
+    }
+
+    fn save_if_match(self: &FileStorageProvider, key: &str, raw: Vec<u8>, etag: &str, options: &SaveOptions) -> Result<SaveData, StorageError> {
+        OperationSpan::provider("save_if_match", &self.folder, key).entered().run(|span| {
+            span.record_size(raw.len() as u64);
+            self.save_conditional(key, raw, options, || match self.stat_file(key) {
+                Ok(stat) if stat.etag == etag => Ok(()),
+                _ => Err(StorageError::PreconditionFailed(key.to_owned())),
+            })
+        })
+    }
+
+    fn capacity(self: &FileStorageProvider) -> Result<CapacityData, String> {
+        OperationSpan::provider("capacity", &self.folder, "").entered().run(|_span| {
+            Ok(CapacityData {
+                total_bytes: fs2::total_space(&self.folder).map_err(|e| format!("Could not read capacity: {}", e))?,
+                free_bytes: fs2::available_space(&self.folder).map_err(|e| format!("Could not read capacity: {}", e))?,
+            })
+        })
+    }
+
+    fn health(self: &FileStorageProvider) -> Result<HealthData, String> {
+        OperationSpan::provider("health", &self.folder, "").entered().run(|_span| {
+            // the counters are only reset by the window, so a degraded layer keeps its error rate on every check
+            let writes = self.writes.load(Ordering::Relaxed);
+            let failed_writes = self.failed_writes.load(Ordering::Relaxed);
+            Ok(HealthData {
+                writable: verify_writable(&self.folder).is_ok() && verify_writable(&self.delete).is_ok(),
+                free_bytes: fs2::available_space(&self.folder).ok(),
+                error_rate: if writes == 0 { 0.0 } else { failed_writes.min(writes) as f64 / writes as f64 },
+            })
+        })
+    }
+
+    fn set_ttl(self: &FileStorageProvider, key: &str, ttl: Option<Duration>) -> Result<(), String> {
+        OperationSpan::provider("set_ttl", &self.folder, key).entered().run(|_span| {
+            self.stat_file(key)?;
+            let _guard = self.change_guard();
+            self.write_expires(key, ttl)?;
+            self.update_index(key);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(feature = "watch")]
+mod watch {
+    use std::{
+        collections::HashSet,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+    #[derive(Default)]
+    struct Changed {
+        keys: HashSet<String>,
+        /// Events were lost, all files must be checked.
+        rescan: bool,
+    }
+
+    /// Collects the names of all files in the storage folder which were changed.
+    pub(super) struct FolderWatcher {
+        _watcher: RecommendedWatcher,
+        changed: Arc<Mutex<Changed>>,
+    }
+
+    impl FolderWatcher {
+        pub fn new(folder: &str) -> Result<Self, String> {
+            let changed = Arc::new(Mutex::new(Changed::default()));
+            let handler_changed = changed.clone();
+            let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+                let mut changed = handler_changed.lock().unwrap();
+                match result {
+                    Ok(event) if !event.need_rescan() => {
+                        for path in event.paths {
+                            if let Some(key) = path.file_name().and_then(|name| name.to_str()) {
+                                if !key.starts_with('.') {
+                                    changed.keys.insert(key.to_owned());
+                                }
+                            }
+                        }
+                    }
+                    _ => changed.rescan = true,
+                }
+            })
+            .map_err(|e| format!("Could not watch folder `{}`: {}", folder, e))?;
+            watcher
+                .watch(Path::new(folder), RecursiveMode::NonRecursive)
+                .map_err(|e| format!("Could not watch folder `{}`: {}", folder, e))?;
+            Ok(Self { _watcher: watcher, changed })
+        }
+
+        /// Names of the changed files since the last call, `None` if all files must be checked.
+        pub fn take_changed(self: &Self) -> Option<HashSet<String>> {
+            let mut changed = self.changed.lock().unwrap();
+            let Changed { keys, rescan } = std::mem::take(&mut *changed);
+            if rescan {
+                None
+            } else {
+                Some(keys)
+            }
         }
-        Err("Could not save".to_owned())
     }
+}
 
-    fn delete(self: &FileStorageProvider, key: &str) {
-        let from = self.internal_file_path(key).to_owned();
-        let to = self.internal_file_delete_path(key).to_owned();
-        let _result = fs::rename(from, to);
+/// Removes the lock file of a key on drop.
+struct LockGuard {
+    path: String,
+    owner: String,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // a lock which was held longer than the timeout may have been taken over, the lock of the new owner is kept
+        remove_lock(&self.path, &self.owner, &self.owner);
     }
+}
 
-    fn free(self: &FileStorageProvider) {
-        self.delete_files_older_then(DAY_IN_SECONDS * 15);
+/// Owner of a lock file which is older than `LOCK_TIMEOUT`, `None` if the lock is not stale.
+fn stale_lock_owner(path: &str) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let modified = file.metadata().and_then(|meta| meta.modified()).ok()?;
+    if SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO) <= LOCK_TIMEOUT {
+        return None;
     }
+    let mut owner = String::new();
+    file.read_to_string(&mut owner).ok()?;
+    Some(owner)
+}
 
-    fn force_free(self: &FileStorageProvider, all: bool) {
-        if all {
-            self.delete_files_older_then(0);
-        } else {
-            self.delete_files_older_then(DAY_IN_SECONDS * 1);
+/// Remove the lock file if it still belongs to the `owner`.
+/// 
+/// The lock is moved to a unique hidden name before its owner is checked, a lock of another owner is moved back.
+fn remove_lock(path: &str, owner: &str, unique: &str) {
+    let moved = match Path::new(path).parent() {
+        Some(folder) => format!("{}/.{}", folder.to_string_lossy(), unique),
+        None => return,
+    };
+    if fs::rename(path, &moved).is_err() {
+        return;
+    }
+    if fs::read_to_string(&moved).map(|moved_owner| moved_owner != owner).unwrap_or(false) {
+        // a hard link fails if a new lock was created in between, other than `fs::rename` which replaces it
+        let _result = fs::hard_link(&moved, path);
+    }
+    let _result = fs::remove_file(&moved);
+}
+
+/// Every save replaces the file of an entry, so the modification time, size and file id identify a version of the entry.
+fn etag(meta: &fs::Metadata) -> String {
+    let modified = meta.modified().ok().and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_nanos()).unwrap_or(0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        format!("{:x}-{:x}-{:x}", modified, meta.len(), meta.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        format!("{:x}-{:x}", modified, meta.len())
+    }
+}
+
+/// Apply `f` to all items on multiple threads, the results are in the order of the items.
+fn parallel_map<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_BATCH_THREADS);
+    if items.len() < 2 || threads < 2 {
+        return items.into_iter().map(f).collect();
+    }
+    let chunk_size = items.len().div_ceil(threads);
+    let mut chunks: Vec<Vec<T>> = vec![];
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(chunk_size).collect());
+    }
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || chunk.into_iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("Batch thread panicked")).collect()
+    })
+}
+
+/// Persist the directory entries of a folder, a renamed file is only durable after its folder is synced.
+#[cfg(unix)]
+pub(crate) fn sync_folder(folder: &str) {
+    if let Ok(dir) = File::open(folder) {
+        let _result = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_folder(_folder: &str) {}
+
+#[cfg(unix)]
+fn same_file(a: &str, b: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &str, _b: &str) -> bool {
+    false
+}
+
+/// `true` for a name created by `sortable_name`.
+fn is_sortable_name(name: &str) -> bool {
+    match name.split_once('-') {
+        Some((nanos, counter)) => {
+            nanos.len() == 32 && counter.len() == 16 && nanos.chars().chain(counter.chars()).all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+fn verify_writable(folder: &str) -> Result<(), String> {
+    let probe = format!("{}/.write_probe", folder);
+    fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)).map_err(|e| format!("Folder `{}` is not writable: {}", folder, e))
+}
+
+/// Entries are moved into the delete folder with `fs::rename`, which only works on the same filesystem.
+#[cfg(unix)]
+fn verify_same_filesystem(storage_folder: &str, delete_folder: &str) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let storage_device = fs::metadata(storage_folder).map_err(|e| e.to_string())?.dev();
+    let delete_device = fs::metadata(delete_folder).map_err(|e| e.to_string())?.dev();
+    if storage_device != delete_device {
+        return Err(format!("Folder `{}` and `{}` are not on the same filesystem", storage_folder, delete_folder));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn verify_same_filesystem(_storage_folder: &str, _delete_folder: &str) -> Result<(), String> {
+    Ok(())
+}
+
+fn expires_field(fields: &sidecar::Fields) -> Option<SystemTime> {
+    fields
+        .get(EXPIRES_FIELD)
+        .and_then(|expires| expires.parse::<u64>().ok())
+        .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+fn set_expires_field(fields: &mut sidecar::Fields, ttl: Option<Duration>) -> Result<(), String> {
+    match ttl {
+        Some(ttl) => {
+            let expires = SystemTime::now() + ttl;
+            let millis = expires.duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_millis();
+            fields.insert(EXPIRES_FIELD.to_owned(), millis.to_string());
+        }
+        None => {
+            fields.remove(EXPIRES_FIELD);
         }
     }
+    Ok(())
+}
+
+fn is_expired(expires: Option<SystemTime>) -> bool {
+    matches!(expires, Some(expires) if expires <= SystemTime::now())
+}
+
+fn user_metadata(fields: &sidecar::Fields) -> Metadata {
+    fields
+        .iter()
+        .filter_map(|(name, value)| name.strip_prefix(USER_FIELD_PREFIX).map(|name| (name.to_owned(), value.to_owned())))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::StorageProvider;
+    use std::{io::Read, time::{Duration, SystemTime}};
 
-    use super::FileStorageProvider;
+    use crate::{checksum::crc32, retention::Retention, ChangeKind, Metadata, SaveOptions, StorageError, StorageProvider, META_CONTENT_TYPE};
+
+    use crate::journal::{Intent, Journal};
+
+    use super::{ConflictResolution, FileStorageConfig, FileStorageProvider};
 
     const FILE_STORAGE: &str = "test_fstore";
     const DELETE_STORAGE: &str = "test_fdelete";
@@ -130,7 +1662,7 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, "instance");
         let d_path = format!("{}_{}", DELETE_STORAGE, "instance");
 
-        let _file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let _file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         let attr = std::fs::metadata(f_path.to_owned()).unwrap();
         assert!(attr.is_dir());
         let attr = std::fs::metadata(d_path.to_owned()).unwrap();
@@ -143,7 +1675,7 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, "save");
         let d_path = format!("{}_{}", DELETE_STORAGE, "save");
 
-        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         let result = file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
         assert_eq!(result.key, FILE_KEY);
         clean_up(&f_path, &d_path);
@@ -154,7 +1686,7 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, "get");
         let d_path = format!("{}_{}", DELETE_STORAGE, "get");
 
-        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
         let result = file_storage.get(FILE_KEY).unwrap();
         assert_eq!(result.size, 4);
@@ -167,7 +1699,7 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, "delete");
         let d_path = format!("{}_{}", DELETE_STORAGE, "delete");
 
-        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
         file_storage.delete(FILE_KEY);
         let attr = std::fs::metadata(format!("{}/{}", d_path, FILE_KEY)).unwrap();
@@ -175,13 +1707,29 @@ mod tests {
         clean_up(&f_path, &d_path);
     }
 
+    #[test]
+    fn discard() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "discard");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "discard");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save(FILE_KEY, "old".to_owned().into_bytes()).unwrap();
+        file_storage.delete(FILE_KEY);
+        file_storage.save(FILE_KEY, "new".to_owned().into_bytes()).unwrap();
+        file_storage.discard(FILE_KEY).unwrap();
+        assert!(file_storage.stat(FILE_KEY).is_err());
+        // the entry in the delete queue is not replaced
+        assert_eq!(std::fs::read(format!("{}/{}", d_path, FILE_KEY)).unwrap(), "old".to_owned().into_bytes());
+        clean_up(&f_path, &d_path);
+    }
+
 
     #[test]
     fn free() {
         let f_path = format!("{}_{}", FILE_STORAGE, "free");
         let d_path = format!("{}_{}", DELETE_STORAGE, "free");
 
-        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
         file_storage.delete(FILE_KEY);
         file_storage.free();
@@ -196,7 +1744,7 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, "force_free");
         let d_path = format!("{}_{}", DELETE_STORAGE, "force_free");
 
-        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned());
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
         file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
         file_storage.delete(FILE_KEY);
         file_storage.force_free(true);
@@ -204,4 +1752,524 @@ mod tests {
         assert!(!exists);
         clean_up(&f_path, &d_path);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn list() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "list");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "list");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save("a1", "test".to_owned().into_bytes()).unwrap();
+        file_storage.save("a2", "test".to_owned().into_bytes()).unwrap();
+        file_storage.save("b1", "test".to_owned().into_bytes()).unwrap();
+        assert_eq!(file_storage.list("a").unwrap(), vec!["a1", "a2"]);
+        assert_eq!(file_storage.list("").unwrap().len(), 3);
+        // hidden files like a left over write probe are not entries
+        std::fs::write(format!("{}/.write_probe", f_path), "").unwrap();
+        std::fs::write(format!("{}/.write_probe", d_path), "").unwrap();
+        assert_eq!(file_storage.list("").unwrap().len(), 3);
+        assert!(file_storage.list_deleted().unwrap().is_empty());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn stat() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "stat");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "stat");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let result = file_storage.stat(FILE_KEY).unwrap();
+        assert_eq!(result.size, 4);
+        assert!(file_storage.stat("missing").is_err());
+        let usage = file_storage.usage().unwrap();
+        assert_eq!(usage.bytes, 4);
+        assert_eq!(usage.entries, 1);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn free_retention() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "free_retention");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "free_retention");
+
+        let config = FileStorageConfig {
+            retention: Retention {
+                max_entries: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        file_storage.save("a", "test".to_owned().into_bytes()).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        file_storage.save("b", "test".to_owned().into_bytes()).unwrap();
+        file_storage.delete("a");
+        file_storage.delete("b");
+        file_storage.free();
+        assert!(!std::path::Path::new(&format!("{}/{}", d_path, "a")).exists());
+        assert!(std::path::Path::new(&format!("{}/{}", d_path, "b")).exists());
+        file_storage.free_older_than(Duration::from_secs(3_600));
+        assert!(std::path::Path::new(&format!("{}/{}", d_path, "b")).exists());
+        file_storage.free_older_than(Duration::ZERO);
+        assert!(!std::path::Path::new(&format!("{}/{}", d_path, "b")).exists());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn ttl() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "ttl");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "ttl");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let options = SaveOptions {
+            ttl: Some(Duration::from_secs(3_600)),
+            ..Default::default()
+        };
+        file_storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        assert!(file_storage.stat(FILE_KEY).unwrap().expires.is_some());
+        // the ttl is persisted and available for new instances
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        assert!(file_storage.stat(FILE_KEY).unwrap().expires.is_some());
+        file_storage.set_ttl(FILE_KEY, None).unwrap();
+        assert!(file_storage.stat(FILE_KEY).unwrap().expires.is_none());
+
+        file_storage.set_ttl(FILE_KEY, Some(Duration::ZERO)).unwrap();
+        assert!(file_storage.get(FILE_KEY).is_err());
+        assert!(file_storage.list("").unwrap().is_empty());
+        file_storage.free();
+        assert!(std::path::Path::new(&format!("{}/{}", d_path, FILE_KEY)).exists());
+        assert!(!std::path::Path::new(&format!("{}/{}", f_path, FILE_KEY)).exists());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn metadata() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "metadata");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "metadata");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let mut metadata = Metadata::new();
+        metadata.insert(META_CONTENT_TYPE.to_owned(), "text/plain".to_owned());
+        metadata.insert("tag".to_owned(), "a=b".to_owned());
+        let options = SaveOptions {
+            metadata: metadata.clone(),
+            ..Default::default()
+        };
+        file_storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        file_storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().metadata, metadata);
+        assert_eq!(file_storage.stat(FILE_KEY).unwrap().metadata, metadata);
+        assert!(file_storage.get("5678").unwrap().metadata.is_empty());
+
+        let mut tags = Metadata::new();
+        tags.insert("tag".to_owned(), "a=b".to_owned());
+        assert_eq!(file_storage.list_tagged("", &tags).unwrap(), vec![FILE_KEY]);
+        // overwriting an entry replaces the metadata
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(file_storage.list_tagged("", &tags).unwrap().is_empty());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn journal_replay() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "journal_replay");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "journal_replay");
+
+        let config = FileStorageConfig {
+            journal: true,
+            versions: Some(Retention {
+                max_age: None,
+                force_max_age: None,
+                max_bytes: None,
+                max_entries: None,
+            }),
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config.clone()).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        file_storage.save("abcd", "test".to_owned().into_bytes()).unwrap();
+        drop(file_storage);
+
+        // simulate a crash after the data of a delete was moved and before the metadata was moved
+        let (journal, _pending) = Journal::open(&format!("{}/.journal/journal.log", f_path)).unwrap();
+        std::fs::write(format!("{}/.meta/{}", f_path, FILE_KEY), "meta.a=b\n").unwrap();
+        journal.begin(Intent::Delete { key: FILE_KEY.to_owned(), has_meta: true }).unwrap();
+        std::fs::rename(format!("{}/{}", f_path, FILE_KEY), format!("{}/{}", d_path, FILE_KEY)).unwrap();
+        // and a crash during the write of the temp file of a save
+        std::fs::write(format!("{}/.tmp/1.data", f_path), "te").unwrap();
+        journal.begin(Intent::Save { key: "5678".to_owned(), temp: "1".to_owned(), has_meta: false }).unwrap();
+        // after the version of the save was linked
+        std::fs::create_dir_all(format!("{}/.versions/5678", f_path)).unwrap();
+        std::fs::hard_link(format!("{}/.tmp/1.data", f_path), format!("{}/.versions/5678/{:032x}-{:016x}.data", f_path, 1, 0)).unwrap();
+        // and a crash after the data of a rename was moved and before the versions were moved
+        journal.begin(Intent::Rename { from: "abcd".to_owned(), to: "efgh".to_owned(), overwrite: true, has_meta: true }).unwrap();
+        std::fs::rename(format!("{}/abcd", f_path), format!("{}/efgh", f_path)).unwrap();
+        drop(journal);
+
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        assert!(!std::path::Path::new(&format!("{}/.meta/{}", f_path, FILE_KEY)).exists());
+        assert!(std::path::Path::new(&format!("{}/.meta/{}", d_path, FILE_KEY)).exists());
+        assert!(!std::path::Path::new(&format!("{}/.tmp/1.data", f_path)).exists());
+        assert!(file_storage.get("5678").is_err());
+        assert!(file_storage.list_versions("5678").unwrap().is_empty());
+        assert_eq!(file_storage.list_versions(FILE_KEY).unwrap().len(), 1);
+        assert_eq!(file_storage.get("efgh").unwrap().data, "test".to_owned().into_bytes());
+        assert_eq!(file_storage.list_versions("efgh").unwrap().len(), 1);
+        assert!(file_storage.list_versions("abcd").unwrap().is_empty());
+        assert_eq!(file_storage.recovery_report().replayed_operations, 3);
+        file_storage.compact_journal().unwrap();
+        assert_eq!(std::fs::metadata(format!("{}/.journal/journal.log", f_path)).unwrap().len(), 0);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn recovery() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "recovery");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "recovery");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save(FILE_KEY, "old".to_owned().into_bytes()).unwrap();
+        file_storage.delete(FILE_KEY);
+        std::thread::sleep(Duration::from_millis(20));
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        drop(file_storage);
+        std::fs::write(format!("{}/.tmp/1.data", f_path), "te").unwrap();
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let report = file_storage.recovery_report();
+        assert_eq!(report.removed_temp_files, vec!["1.data"]);
+        assert_eq!(report.resolved_conflicts, vec![(FILE_KEY.to_owned(), ConflictResolution::KeptLive)]);
+        assert!(!std::path::Path::new(&format!("{}/{}", d_path, FILE_KEY)).exists());
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 4);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn restore() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "restore");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "restore");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        file_storage.delete(FILE_KEY);
+        assert!(file_storage.get(FILE_KEY).is_err());
+        file_storage.restore(FILE_KEY).unwrap();
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 4);
+        assert!(file_storage.restore(FILE_KEY).is_err());
+
+        // a key which was saved again after the delete keeps the newer data
+        file_storage.delete(FILE_KEY);
+        file_storage.save(FILE_KEY, "newer".to_owned().into_bytes()).unwrap();
+        assert_eq!(file_storage.restore(FILE_KEY), Err(StorageError::Conflict(FILE_KEY.to_owned())));
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().data, "newer".to_owned().into_bytes());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn staged_save() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "staged_save");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "staged_save");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let token = file_storage.stage_save(FILE_KEY, "test".to_owned().into_bytes(), &SaveOptions::default()).unwrap();
+        assert!(file_storage.get(FILE_KEY).is_err());
+        file_storage.commit_staged(FILE_KEY, &token).unwrap();
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 4);
+
+        let token = file_storage.stage_save("5678", "test".to_owned().into_bytes(), &SaveOptions::default()).unwrap();
+        file_storage.discard_staged(&token);
+        assert!(file_storage.commit_staged("5678", &token).is_err());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn batch() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "batch");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "batch");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let entries: Vec<(String, Vec<u8>)> = (0..20).map(|i| (format!("k{}", i), vec![0; i])).collect();
+        let results = file_storage.save_many(entries);
+        assert!(results.iter().all(|r| r.is_ok()));
+        let results = file_storage.get_many(&["k3", "missing", "k19"]);
+        assert_eq!(results[0].as_ref().unwrap().size, 3);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().size, 19);
+        file_storage.delete_many(&["k0", "k1"]);
+        assert_eq!(file_storage.list("").unwrap().len(), 18);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn reader() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "reader");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "reader");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let data = "test".to_owned().into_bytes();
+        let result = file_storage.save_from_reader(FILE_KEY, &mut data.as_slice(), &SaveOptions::default()).unwrap();
+        assert_eq!(result.size, 4);
+        let mut read = String::new();
+        file_storage.get_reader(FILE_KEY).unwrap().read_to_string(&mut read).unwrap();
+        assert_eq!(read, "test");
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn rename() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "rename");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "rename");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let mut options = SaveOptions::default();
+        options.metadata.insert(META_CONTENT_TYPE.to_owned(), "text/plain".to_owned());
+        file_storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        file_storage.save("5678", "te".to_owned().into_bytes()).unwrap();
+
+        assert_eq!(file_storage.rename(FILE_KEY, "5678", false), Err(StorageError::Conflict("5678".to_owned())));
+        file_storage.rename(FILE_KEY, "abcd", false).unwrap();
+        assert!(file_storage.get(FILE_KEY).is_err());
+        assert_eq!(file_storage.get("abcd").unwrap().metadata, options.metadata);
+
+        file_storage.rename("abcd", "5678", true).unwrap();
+        let result = file_storage.get("5678").unwrap();
+        assert_eq!(result.size, 4);
+        assert_eq!(result.metadata, options.metadata);
+        assert_eq!(file_storage.list("").unwrap(), vec!["5678"]);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn conditional_save() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "conditional_save");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "conditional_save");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let options = SaveOptions::default();
+        file_storage.save_if_absent(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        let result = file_storage.save_if_absent(FILE_KEY, "test2".to_owned().into_bytes(), &options);
+        assert_eq!(result.err(), Some(StorageError::Conflict(FILE_KEY.to_owned())));
+
+        let etag = file_storage.get(FILE_KEY).unwrap().etag;
+        assert_eq!(file_storage.stat(FILE_KEY).unwrap().etag, etag);
+        file_storage.save_if_match(FILE_KEY, "test2".to_owned().into_bytes(), &etag, &options).unwrap();
+        let result = file_storage.save_if_match(FILE_KEY, "test3".to_owned().into_bytes(), &etag, &options);
+        assert_eq!(result.err(), Some(StorageError::PreconditionFailed(FILE_KEY.to_owned())));
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 5);
+        assert!(!std::path::Path::new(&format!("{}/.locks/{}", f_path, FILE_KEY)).exists());
+
+        // a plain save waits for the lock of a conditional save
+        let guard = file_storage.lock(FILE_KEY).unwrap();
+        std::thread::scope(|scope| {
+            let save = scope.spawn(|| file_storage.save(FILE_KEY, "test44".to_owned().into_bytes()));
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 5);
+            drop(guard);
+            save.join().unwrap().unwrap();
+        });
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().size, 6);
+
+        // the stale lock of a crashed writer is taken over
+        let lock_path = format!("{}/.locks/{}", f_path, FILE_KEY);
+        std::fs::write(&lock_path, "crashed").unwrap();
+        std::fs::File::options().write(true).open(&lock_path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(!std::path::Path::new(&lock_path).exists());
+        // a lock is only removed by its owner
+        std::fs::write(&lock_path, "owner").unwrap();
+        super::remove_lock(&lock_path, "other", "unique");
+        assert_eq!(std::fs::read_to_string(&lock_path).unwrap(), "owner");
+        super::remove_lock(&lock_path, "owner", "unique");
+        assert!(!std::path::Path::new(&lock_path).exists());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn versions() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "versions");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "versions");
+
+        let config = FileStorageConfig {
+            versions: Some(Retention {
+                max_age: None,
+                force_max_age: None,
+                max_bytes: None,
+                max_entries: Some(2),
+            }),
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        let first = file_storage.save(FILE_KEY, "test1".to_owned().into_bytes()).unwrap().version.unwrap();
+        file_storage.save(FILE_KEY, "test2".to_owned().into_bytes()).unwrap();
+        let options = SaveOptions {
+            metadata: Metadata::from([(META_CONTENT_TYPE.to_owned(), "text/plain".to_owned())]),
+            ..Default::default()
+        };
+        let last = file_storage.save_with_options(FILE_KEY, "test3".to_owned().into_bytes(), &options).unwrap().version.unwrap();
+        assert_eq!(file_storage.list_versions(FILE_KEY).unwrap().len(), 3);
+        assert_eq!(file_storage.get_version(FILE_KEY, &first).unwrap().data, "test1".to_owned().into_bytes());
+        let result = file_storage.get_version(FILE_KEY, &last).unwrap();
+        assert_eq!(result.data, "test3".to_owned().into_bytes());
+        assert_eq!(result.metadata.get(META_CONTENT_TYPE).unwrap(), "text/plain");
+        assert_eq!(file_storage.list("").unwrap(), vec![FILE_KEY]);
+
+        file_storage.free();
+        let versions = file_storage.list_versions(FILE_KEY).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].version, last);
+        assert!(file_storage.get_version(FILE_KEY, &first).is_err());
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().data, "test3".to_owned().into_bytes());
+        // the versions are moved with a renamed entry
+        file_storage.rename(FILE_KEY, "5678", false).unwrap();
+        assert_eq!(file_storage.list_versions("5678").unwrap().len(), 2);
+        assert!(file_storage.list_versions(FILE_KEY).unwrap().is_empty());
+        file_storage.rename("5678", FILE_KEY, false).unwrap();
+        // only version ids of the provider are accepted, a version can not point outside of the version folder
+        std::fs::write(format!("{}/secret.data", f_path), "secret").unwrap();
+        assert!(file_storage.get_version(FILE_KEY, "../../secret").is_err());
+        assert!(file_storage.get_version(FILE_KEY, &format!("{}/../{}", last, last)).is_err());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn snapshot() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "snapshot");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "snapshot");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        let options = SaveOptions {
+            metadata: Metadata::from([(META_CONTENT_TYPE.to_owned(), "text/plain".to_owned())]),
+            ..Default::default()
+        };
+        file_storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        file_storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        let snapshot = file_storage.create_snapshot().unwrap();
+        file_storage.save(FILE_KEY, "changed".to_owned().into_bytes()).unwrap();
+        file_storage.delete("5678");
+
+        assert_eq!(file_storage.list_snapshots().unwrap()[0].id, snapshot);
+        assert_eq!(file_storage.list_snapshot_entries(&snapshot, "").unwrap(), vec![FILE_KEY, "5678"]);
+        let result = file_storage.get_snapshot_entry(&snapshot, FILE_KEY).unwrap();
+        assert_eq!(result.data, "test".to_owned().into_bytes());
+        assert_eq!(result.metadata.get(META_CONTENT_TYPE).unwrap(), "text/plain");
+        assert!(file_storage.get_snapshot_entry(&snapshot, "5678").is_ok());
+        assert_eq!(file_storage.list("").unwrap(), vec![FILE_KEY]);
+
+        file_storage.delete_snapshot(&snapshot).unwrap();
+        assert!(file_storage.list_snapshots().unwrap().is_empty());
+        assert!(file_storage.get_snapshot_entry(&snapshot, FILE_KEY).is_err());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn external_changes() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "external_changes");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "external_changes");
+
+        let config = FileStorageConfig {
+            watch: true,
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        file_storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        assert!(file_storage.poll_changes().unwrap().is_empty());
+
+        std::fs::write(format!("{}/{}", f_path, "added"), "test").unwrap();
+        std::fs::write(format!("{}/{}", f_path, FILE_KEY), "changed").unwrap();
+        std::fs::remove_file(format!("{}/{}", f_path, "5678")).unwrap();
+        // inotify events are delivered asynchronously
+        std::thread::sleep(Duration::from_millis(100));
+        let changes: Vec<(ChangeKind, String)> = file_storage.poll_changes().unwrap().into_iter().map(|change| (change.kind, change.key)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (ChangeKind::Modified, FILE_KEY.to_owned()),
+                (ChangeKind::Removed, "5678".to_owned()),
+                (ChangeKind::Added, "added".to_owned())
+            ]
+        );
+        assert!(file_storage.poll_changes().unwrap().is_empty());
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn index() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "index");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "index");
+
+        let config = FileStorageConfig {
+            index: true,
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config.clone()).unwrap();
+        let options = SaveOptions {
+            metadata: Metadata::from([("tag".to_owned(), "a".to_owned())]),
+            ..Default::default()
+        };
+        file_storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        file_storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        let stat = file_storage.stat(FILE_KEY).unwrap();
+        assert_eq!((stat.size, stat.metadata.get("tag").map(|tag| tag.as_str())), (4, Some("a")));
+        assert_eq!(file_storage.index_entry(FILE_KEY).unwrap().checksum, crc32(b"test"));
+        file_storage.delete("5678");
+        assert_eq!(file_storage.list("").unwrap(), vec![FILE_KEY.to_owned()]);
+        drop(file_storage);
+
+        // a lost index is rebuilt and files added while the provider was stopped are indexed
+        std::fs::remove_file(format!("{}/.index/index.log", f_path)).unwrap();
+        std::fs::write(format!("{}/{}", f_path, "added"), "changed").unwrap();
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        assert_eq!(file_storage.list("").unwrap(), vec![FILE_KEY.to_owned(), "added".to_owned()]);
+        assert_eq!(file_storage.index_entry("added").unwrap().checksum, crc32(b"changed"));
+        assert_eq!(file_storage.stat(FILE_KEY).unwrap().metadata.get("tag").map(|tag| tag.as_str()), Some("a"));
+        assert_eq!(file_storage.rebuild_index().unwrap(), 2);
+        clean_up(&f_path, &d_path);
+    }
+
+    #[test]
+    fn index_conditional_save() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "index_conditional_save");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "index_conditional_save");
+
+        let config = FileStorageConfig {
+            index: true,
+            ..Default::default()
+        };
+        let file_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config.clone()).unwrap();
+        file_storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        let old_etag = file_storage.stat("5678").unwrap().etag;
+
+        // saves of another process are not in the index of this provider
+        let other_storage = FileStorageProvider::with_config(f_path.to_owned(), d_path.to_owned(), config).unwrap();
+        other_storage.save(FILE_KEY, "other".to_owned().into_bytes()).unwrap();
+        other_storage.save("5678", "other".to_owned().into_bytes()).unwrap();
+        let result = file_storage.save_if_absent(FILE_KEY, "test".to_owned().into_bytes(), &SaveOptions::default());
+        assert!(matches!(result, Err(StorageError::Conflict(_))));
+        let result = file_storage.save_if_match("5678", "test".to_owned().into_bytes(), &old_etag, &SaveOptions::default());
+        assert!(matches!(result, Err(StorageError::PreconditionFailed(_))));
+        assert_eq!(file_storage.get(FILE_KEY).unwrap().data, "other".to_owned().into_bytes());
+        assert_eq!(file_storage.get("5678").unwrap().data, "other".to_owned().into_bytes());
+        clean_up(&f_path, &d_path);
+    }
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn get_mapped() {
+        let f_path = format!("{}_{}", FILE_STORAGE, "get_mapped");
+        let d_path = format!("{}_{}", DELETE_STORAGE, "get_mapped");
+
+        let file_storage = FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap();
+        file_storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let mapped = file_storage.get_mapped(FILE_KEY).unwrap();
+        // an overwrite or delete does not change the mapped data
+        file_storage.save(FILE_KEY, "changed".to_owned().into_bytes()).unwrap();
+        file_storage.delete(FILE_KEY);
+        file_storage.force_free(true);
+        assert_eq!((&mapped[..], mapped.size), (&b"test"[..], 4));
+        assert!(file_storage.get_mapped(FILE_KEY).is_err());
+        file_storage.save("empty", vec![]).unwrap();
+        assert!(file_storage.get_mapped("empty").unwrap().is_empty());
+        clean_up(&f_path, &d_path);
+    }
+}
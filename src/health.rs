@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{HealthData, StorageError};
+
+/// Settings of the circuit breaker of every layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Count of failed operations in a row after which a layer is degraded.
+    pub failure_threshold: u32,
+    /// Time a degraded layer is skipped, afterwards operations are let through again to test if the layer recovered.
+    pub retry_after: Duration,
+    /// A health check with a higher error rate of the provider degrades the layer.
+    pub max_error_rate: f64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            retry_after: Duration::from_secs(30),
+            max_error_rate: 0.5,
+        }
+    }
+}
+
+/// Health state of a layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerStatus {
+    Healthy,
+    /// The layer had repeated failures, `find` and `save` route around it until it recovers.
+    Degraded,
+}
+
+/// Health of a layer in the report of the storage manager.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerHealth {
+    pub layer: String,
+    pub status: LayerStatus,
+    /// Count of operations on the layer which failed in a row.
+    pub consecutive_failures: u32,
+    /// Health reported by the provider, `None` if the provider does not support health checks or the check failed.
+    pub provider: Option<HealthData>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    failures: u32,
+    /// Time the layer was degraded, `None` while the layer is healthy.
+    opened: Option<Instant>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    config: CircuitBreakerConfig,
+    circuits: HashMap<String, Circuit>,
+    failover: HashMap<String, String>,
+}
+
+/// Keeps track of the failures on all layers and degrades layers with repeated failures.
+#[derive(Default)]
+pub(crate) struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn set_config(self: &Self, config: CircuitBreakerConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    pub fn set_failover(self: &Self, layer: &str, failover: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        match failover {
+            Some(failover) => state.failover.insert(layer.to_owned(), failover),
+            None => state.failover.remove(layer),
+        };
+    }
+
+    pub fn remove_layer(self: &Self, layer: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.circuits.remove(layer);
+        state.failover.remove(layer);
+    }
+
+    /// `false` while the layer is degraded and the retry time has not passed.
+    pub fn allows(self: &Self, layer: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        Self::allows_locked(&state, layer)
+    }
+
+    /// Layer which should receive a save for `layer`, the failover layer if `layer` is degraded.
+    ///
+    /// Returns `StorageError::Unavailable` if the layer is degraded and no usable failover layer is set.
+    pub fn route(self: &Self, layer: &str) -> Result<String, StorageError> {
+        let state = self.state.lock().unwrap();
+        if Self::allows_locked(&state, layer) {
+            return Ok(layer.to_owned());
+        }
+        match state.failover.get(layer) {
+            Some(failover) if Self::allows_locked(&state, failover) => Ok(failover.to_owned()),
+            _ => Err(StorageError::Unavailable(layer.to_owned())),
+        }
+    }
+
+    pub fn succeeded(self: &Self, layer: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(circuit) = state.circuits.get_mut(layer) {
+            circuit.failures = 0;
+            circuit.opened = None;
+        }
+    }
+
+    pub fn failed(self: &Self, layer: &str) {
+        let mut state = self.state.lock().unwrap();
+        let threshold = state.config.failure_threshold;
+        let circuit = state.circuits.entry(layer.to_owned()).or_default();
+        circuit.failures += 1;
+        // a failure after the retry time degrades the layer again
+        if circuit.opened.is_some() || circuit.failures >= threshold {
+            circuit.opened = Some(Instant::now());
+        }
+    }
+
+    /// Apply the result of a health check of the provider.
+    pub fn checked(self: &Self, layer: &str, health: &HealthData) {
+        let max_error_rate = self.state.lock().unwrap().config.max_error_rate;
+        if health.writable && health.error_rate <= max_error_rate {
+            self.succeeded(layer);
+        } else {
+            let mut state = self.state.lock().unwrap();
+            state.circuits.entry(layer.to_owned()).or_default().opened = Some(Instant::now());
+        }
+    }
+
+    pub fn status(self: &Self, layer: &str) -> (LayerStatus, u32) {
+        let state = self.state.lock().unwrap();
+        match state.circuits.get(layer) {
+            Some(Circuit { failures, opened: Some(_) }) => (LayerStatus::Degraded, *failures),
+            Some(Circuit { failures, opened: None }) => (LayerStatus::Healthy, *failures),
+            None => (LayerStatus::Healthy, 0),
+        }
+    }
+
+    fn allows_locked(state: &BreakerState, layer: &str) -> bool {
+        match state.circuits.get(layer).and_then(|circuit| circuit.opened) {
+            Some(opened) => opened.elapsed() >= state.config.retry_after,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{HealthData, StorageError};
+
+    use super::{CircuitBreaker, CircuitBreakerConfig, LayerStatus};
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = CircuitBreaker::default();
+        breaker.set_config(CircuitBreakerConfig {
+            failure_threshold: 2,
+            retry_after: Duration::from_millis(50),
+            max_error_rate: 0.5,
+        });
+        breaker.failed("layer1");
+        assert!(breaker.allows("layer1"));
+        breaker.failed("layer1");
+        assert_eq!(breaker.status("layer1"), (LayerStatus::Degraded, 2));
+        assert!(matches!(breaker.route("layer1"), Err(StorageError::Unavailable(_))));
+        breaker.set_failover("layer1", Some("layer2".to_owned()));
+        assert_eq!(breaker.route("layer1").unwrap(), "layer2");
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allows("layer1"));
+        breaker.failed("layer1");
+        assert!(!breaker.allows("layer1"));
+        breaker.checked("layer1", &HealthData { writable: true, free_bytes: None, error_rate: 0.0 });
+        assert_eq!(breaker.status("layer1"), (LayerStatus::Healthy, 0));
+    }
+}
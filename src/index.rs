@@ -0,0 +1,201 @@
+//! Persistent index of the entries of a `FileStorageProvider`.
+//!
+//! Every change appends a record to the index log, the last record of a key wins.
+//! The log is compacted when the provider starts, a partially written last record is ignored.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{sidecar, Metadata};
+
+/// Indexed state of an entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexEntry {
+    /// Byte size of the entry.
+    pub size: u64,
+    /// Time of the last save.
+    pub modified: SystemTime,
+    /// Time after which the entry expires, `None` if the entry never expires.
+    pub expires: Option<SystemTime>,
+    /// CRC32 checksum of the data.
+    pub checksum: u32,
+    /// Metadata of the entry.
+    pub metadata: Metadata,
+    /// Etag of the entry file, used to detect changes made outside of the provider.
+    pub etag: String,
+}
+
+struct IndexState {
+    entries: BTreeMap<String, IndexEntry>,
+    file: File,
+}
+
+pub(crate) struct Index {
+    path: String,
+    state: Mutex<IndexState>,
+}
+
+impl Index {
+    /// Open the index log, a missing log is created empty.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => parse_log(&content),
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Could not read index: {}", e)),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("Could not open index: {}", e))?;
+        Ok(Self {
+            path: path.to_owned(),
+            state: Mutex::new(IndexState { entries, file }),
+        })
+    }
+
+    pub fn get(self: &Self, key: &str) -> Option<IndexEntry> {
+        self.state.lock().unwrap().entries.get(key).cloned()
+    }
+
+    /// All entries with a key which starts with the prefix, sorted by key.
+    pub fn entries(self: &Self, prefix: &str) -> Vec<(String, IndexEntry)> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .range(prefix.to_owned()..)
+            .take_while(|(key, _entry)| key.starts_with(prefix))
+            .map(|(key, entry)| (key.to_owned(), entry.clone()))
+            .collect()
+    }
+
+    /// Etags of all entries.
+    pub fn etags(self: &Self) -> HashMap<String, String> {
+        self.state.lock().unwrap().entries.iter().map(|(key, entry)| (key.to_owned(), entry.etag.to_owned())).collect()
+    }
+
+    pub fn put(self: &Self, key: &str, entry: IndexEntry) {
+        let mut state = self.state.lock().unwrap();
+        let _result = state.file.write_all(format_put(key, &entry).as_bytes());
+        state.entries.insert(key.to_owned(), entry);
+    }
+
+    pub fn remove(self: &Self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.remove(key).is_some() {
+            let _result = state.file.write_all(format!("D\t{}\n", sidecar::escape(key)).as_bytes());
+        }
+    }
+
+    /// Replace the log with one record for every entry.
+    pub fn replace(self: &Self, entries: BTreeMap<String, IndexEntry>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let temp = format!("{}.tmp", self.path);
+        let content: String = entries.iter().map(|(key, entry)| format_put(key, entry)).collect();
+        let mut file = File::create(&temp).map_err(|e| format!("Could not write index: {}", e))?;
+        file.write_all(content.as_bytes()).and_then(|_| file.sync_all()).map_err(|e| format!("Could not write index: {}", e))?;
+        fs::rename(&temp, &self.path).map_err(|e| format!("Could not write index: {}", e))?;
+        state.file = OpenOptions::new().append(true).open(&self.path).map_err(|e| format!("Could not open index: {}", e))?;
+        state.entries = entries;
+        Ok(())
+    }
+
+    /// Write the current entries into a new log without the replaced records.
+    pub fn compact(self: &Self) -> Result<(), String> {
+        let entries = self.state.lock().unwrap().entries.clone();
+        self.replace(entries)
+    }
+}
+
+fn format_put(key: &str, entry: &IndexEntry) -> String {
+    let modified = entry.modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let expires = entry.expires.and_then(|expires| expires.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_millis().to_string()).unwrap_or_default();
+    let mut line = format!(
+        "P\t{}\t{}\t{}\t{}\t{:08x}\t{}",
+        sidecar::escape(key),
+        entry.size,
+        modified,
+        expires,
+        entry.checksum,
+        sidecar::escape(&entry.etag)
+    );
+    for (name, value) in entry.metadata.iter() {
+        line.push('\t');
+        line.push_str(&sidecar::escape(name));
+        line.push('\t');
+        line.push_str(&sidecar::escape(value));
+    }
+    line.push('\n');
+    line
+}
+
+fn parse_put(fields: &[&str]) -> Option<IndexEntry> {
+    let size = fields.first()?.parse().ok()?;
+    let modified_nanos: u128 = fields.get(1)?.parse().ok()?;
+    let modified = UNIX_EPOCH + Duration::new((modified_nanos / 1_000_000_000) as u64, (modified_nanos % 1_000_000_000) as u32);
+    let expires = match *fields.get(2)? {
+        "" => None,
+        millis => Some(UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?)),
+    };
+    let checksum = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+    let etag = sidecar::unescape(fields.get(4)?);
+    let pairs = fields[5..].chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    let metadata = pairs.map(|pair| (sidecar::unescape(pair[0]), sidecar::unescape(pair[1]))).collect();
+    Some(IndexEntry { size, modified, expires, checksum, metadata, etag })
+}
+
+fn parse_log(content: &str) -> BTreeMap<String, IndexEntry> {
+    let mut entries = BTreeMap::new();
+    // a record without line break was not written completely
+    let complete = match content.rfind('\n') {
+        Some(end) => &content[..end],
+        None => "",
+    };
+    for line in complete.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["P", key, rest @ ..] => {
+                if let Some(entry) = parse_put(rest) {
+                    entries.insert(sidecar::unescape(key), entry);
+                }
+            }
+            ["D", key] => {
+                entries.remove(&sidecar::unescape(key));
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::Metadata;
+
+    use super::{format_put, parse_log, IndexEntry};
+
+    #[test]
+    fn log() {
+        let entry = IndexEntry {
+            size: 4,
+            modified: UNIX_EPOCH + Duration::new(1_700_000_000, 123),
+            expires: None,
+            checksum: 0xabcd,
+            metadata: Metadata::from([("tag".to_owned(), "a\tb".to_owned())]),
+            etag: "1-4-2".to_owned(),
+        };
+        let mut content = format_put("1234", &entry);
+        content.push_str(&format_put("5678", &entry));
+        content.push_str("D\t5678\n");
+        content.push_str("D\t1234");
+        let entries = parse_log(&content);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries.get("1234"), Some(&entry));
+    }
+}
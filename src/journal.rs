@@ -0,0 +1,213 @@
+//! Write-ahead journal of the `FileStorageProvider`.
+//!
+//! Every operation writes a `begin` record before the first change on disk and a `commit` record after the last change.
+//! Operations without a `commit` record are completed or rolled back on the next start.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use crate::{filestorage::sync_folder, sidecar};
+
+/// Count of committed records after which the journal is compacted.
+const COMPACT_THRESHOLD: usize = 1_024;
+
+/// Operation recorded in the journal.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Intent {
+    /// Move a completely written temp file to the entry.
+    Save { key: String, temp: String, has_meta: bool },
+    /// Move an entry into the delete folder.
+    Delete { key: String, has_meta: bool },
+    /// Change the key of an entry, without `overwrite` the entry is linked to the new key before the old key is removed.
+    Rename { from: String, to: String, overwrite: bool, has_meta: bool },
+    /// Move an entry from the delete folder back into the storage folder.
+    Restore { key: String, has_meta: bool },
+    /// Remove an entry from the delete folder.
+    Purge { key: String },
+    /// Remove an entry from the storage folder without moving it into the delete folder.
+    Discard { key: String },
+}
+
+impl Intent {
+    fn format(self: &Self, id: u64) -> String {
+        match self {
+            Intent::Save { key, temp, has_meta } => format!("B\t{}\tsave\t{}\t{}\t{}\n", id, temp, *has_meta as u8, sidecar::escape(key)),
+            Intent::Delete { key, has_meta } => format!("B\t{}\tdelete\t{}\t{}\n", id, *has_meta as u8, sidecar::escape(key)),
+            Intent::Rename { from, to, overwrite, has_meta } => format!(
+                "B\t{}\trename\t{}\t{}\t{}\t{}\n",
+                id,
+                *overwrite as u8,
+                *has_meta as u8,
+                sidecar::escape(from),
+                sidecar::escape(to)
+            ),
+            Intent::Restore { key, has_meta } => format!("B\t{}\trestore\t{}\t{}\n", id, *has_meta as u8, sidecar::escape(key)),
+            Intent::Purge { key } => format!("B\t{}\tpurge\t{}\n", id, sidecar::escape(key)),
+            Intent::Discard { key } => format!("B\t{}\tdiscard\t{}\n", id, sidecar::escape(key)),
+        }
+    }
+
+    fn parse(fields: &[&str]) -> Option<Intent> {
+        match fields {
+            ["save", temp, has_meta, key] => Some(Intent::Save {
+                key: sidecar::unescape(key),
+                temp: temp.to_string(),
+                has_meta: *has_meta == "1",
+            }),
+            ["delete", has_meta, key] => Some(Intent::Delete {
+                key: sidecar::unescape(key),
+                has_meta: *has_meta == "1",
+            }),
+            ["rename", overwrite, has_meta, from, to] => Some(Intent::Rename {
+                from: sidecar::unescape(from),
+                to: sidecar::unescape(to),
+                overwrite: *overwrite == "1",
+                has_meta: *has_meta == "1",
+            }),
+            ["restore", has_meta, key] => Some(Intent::Restore {
+                key: sidecar::unescape(key),
+                has_meta: *has_meta == "1",
+            }),
+            ["purge", key] => Some(Intent::Purge { key: sidecar::unescape(key) }),
+            ["discard", key] => Some(Intent::Discard { key: sidecar::unescape(key) }),
+            _ => None,
+        }
+    }
+}
+
+struct JournalState {
+    file: File,
+    next_id: u64,
+    committed: usize,
+    pending: HashMap<u64, Intent>,
+}
+
+pub(crate) struct Journal {
+    path: String,
+    state: Mutex<JournalState>,
+}
+
+impl Journal {
+    /// Open the journal and return all operations which were not committed.
+    /// 
+    /// The returned operations stay in the journal until they are committed.
+    pub fn open(path: &str) -> Result<(Journal, Vec<(u64, Intent)>), String> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Could not read journal: {}", e)),
+        };
+        let mut pending: Vec<(u64, Intent)> = vec![];
+        let mut next_id = 0;
+        for line in content.lines() {
+            let fields: Vec<&str> = line.splitn(6, '\t').collect();
+            let id = match fields.get(1).and_then(|id| id.parse::<u64>().ok()) {
+                Some(id) => id,
+                // incomplete record of a crash during the write
+                None => continue,
+            };
+            next_id = next_id.max(id + 1);
+            match fields[0] {
+                "B" => {
+                    let intent_fields: Vec<&str> = line.splitn(3, '\t').nth(2).unwrap_or("").split('\t').collect();
+                    if let Some(intent) = Intent::parse(&intent_fields) {
+                        pending.push((id, intent));
+                    }
+                }
+                "C" => pending.retain(|p| p.0 != id),
+                _ => {}
+            }
+        }
+        let journal = Journal {
+            path: path.to_owned(),
+            state: Mutex::new(JournalState {
+                file: OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("Could not open journal: {}", e))?,
+                next_id,
+                committed: 0,
+                pending: pending.iter().cloned().collect(),
+            }),
+        };
+        journal.compact()?;
+        Ok((journal, pending))
+    }
+
+    /// Record the begin of an operation, the record is synced to disk before the call returns.
+    pub fn begin(self: &Self, intent: Intent) -> Result<u64, String> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let record = intent.format(id);
+        state.file.write_all(record.as_bytes()).and_then(|_| state.file.sync_data()).map_err(|e| format!("Could not write journal: {}", e))?;
+        state.pending.insert(id, intent);
+        Ok(id)
+    }
+
+    /// Record the successful end of an operation.
+    pub fn commit(self: &Self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.file.write_all(format!("C\t{}\n", id).as_bytes()).is_ok() {
+            state.pending.remove(&id);
+            state.committed += 1;
+        }
+        if state.committed >= COMPACT_THRESHOLD {
+            let _result = self.compact_locked(&mut state);
+        }
+    }
+
+    /// Rewrite the journal with only the operations which are still in progress.
+    pub fn compact(self: &Self) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        self.compact_locked(&mut state)
+    }
+
+    fn compact_locked(self: &Self, state: &mut JournalState) -> Result<(), String> {
+        let temp_path = format!("{}.compact", self.path);
+        let mut content = String::new();
+        let mut pending: Vec<(&u64, &Intent)> = state.pending.iter().collect();
+        pending.sort_by_key(|p| *p.0);
+        for (id, intent) in pending {
+            content.push_str(&intent.format(*id));
+        }
+        // the compacted journal must be on disk before it replaces the journal, otherwise a crash could lose pending records
+        let mut file = File::create(&temp_path).map_err(|e| format!("Could not compact journal: {}", e))?;
+        file.write_all(content.as_bytes()).and_then(|_| file.sync_all()).map_err(|e| format!("Could not compact journal: {}", e))?;
+        fs::rename(&temp_path, &self.path).map_err(|e| format!("Could not compact journal: {}", e))?;
+        if let Some(folder) = Path::new(&self.path).parent() {
+            sync_folder(&folder.to_string_lossy());
+        }
+        state.file = OpenOptions::new().append(true).open(&self.path).map_err(|e| format!("Could not open journal: {}", e))?;
+        state.committed = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Intent, Journal};
+
+    #[test]
+    fn pending_intents() {
+        let path = "test_journal_pending";
+        {
+            let (journal, pending) = Journal::open(path).unwrap();
+            assert!(pending.is_empty());
+            let save = journal.begin(Intent::Save { key: "a\tb".to_owned(), temp: "1".to_owned(), has_meta: true }).unwrap();
+            let delete = journal.begin(Intent::Delete { key: "c".to_owned(), has_meta: false }).unwrap();
+            journal.commit(delete);
+            journal.begin(Intent::Purge { key: "d".to_owned() }).unwrap();
+            journal.commit(save);
+            journal.compact().unwrap();
+        }
+        let (journal, pending) = Journal::open(path).unwrap();
+        assert_eq!(pending, vec![(2, Intent::Purge { key: "d".to_owned() })]);
+        journal.commit(2);
+        let (_journal, pending) = Journal::open(path).unwrap();
+        assert!(pending.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
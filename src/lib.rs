@@ -1,6 +1,36 @@
+use std::{collections::BTreeMap, fmt, io::{Cursor, Read}, time::{Duration, SystemTime}};
+
+use quota::Quota;
+
+pub mod archive;
+pub mod assembly;
+pub mod backup;
+pub mod capacity;
+pub mod checksum;
+pub mod events;
 pub mod filestorage;
+pub mod health;
+pub mod index;
+mod journal;
+pub mod metrics;
 pub mod policy;
+pub mod quota;
+pub mod retention;
+pub mod segmentstorage;
+mod sidecar;
 pub mod storage_manager;
+mod trace;
+pub mod transaction;
+
+/// Key/value metadata stored with an entry.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Metadata name for the content type of an entry.
+pub const META_CONTENT_TYPE: &str = "content_type";
+/// Metadata name for the client which has saved an entry.
+pub const META_ORIGIN_CLIENT: &str = "origin_client";
+/// Metadata name for the package id of an entry.
+pub const META_PACKAGE_ID: &str = "package_id";
 
 /// Successful result on the storage provider `get` function.
 pub struct GetData {
@@ -9,7 +39,11 @@ pub struct GetData {
     /// Byte size of the entry.
     pub size: usize,
     /// Raw data of the entry.
-    pub data: Vec<u8>
+    pub data: Vec<u8>,
+    /// Metadata of the entry.
+    pub metadata: Metadata,
+    /// Changes on every save of the entry, used for `save_if_match`.
+    pub etag: String,
 }
 
 /// Successful result on the storage provider save function.
@@ -18,6 +52,181 @@ pub struct SaveData {
     pub key: String,
     /// Byte size of the entry.
     pub size: usize,
+    /// ID of the version created by the save, `None` if the storage provider does not keep versions.
+    pub version: Option<String>,
+}
+
+/// Version of an entry returned by the storage provider `list_versions` function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionData {
+    /// ID of the version, used for `get_version`.
+    pub version: String,
+    /// Byte size of the version.
+    pub size: usize,
+    /// Time the version was saved.
+    pub created: SystemTime,
+}
+
+/// ID of a point-in-time snapshot of a storage provider.
+pub type SnapshotId = String;
+
+/// Snapshot returned by the storage provider `list_snapshots` function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotData {
+    /// ID of the snapshot.
+    pub id: SnapshotId,
+    /// Time the snapshot was created.
+    pub created: SystemTime,
+}
+
+/// Successful result on the storage provider `stat` function.
+pub struct StatData {
+    /// Key of the entry.
+    pub key: String,
+    /// Byte size of the entry.
+    pub size: usize,
+    /// Last modification time of the entry.
+    pub modified: SystemTime,
+    /// Time after which the entry expires, `None` if the entry never expires.
+    pub expires: Option<SystemTime>,
+    /// Metadata of the entry.
+    pub metadata: Metadata,
+    /// Changes on every save of the entry, used for `save_if_match`.
+    pub etag: String,
+}
+
+/// Optional settings for the storage provider `save_with_options` function.
+#[derive(Clone, Debug, Default)]
+pub struct SaveOptions {
+    /// Time to live of the entry, expired entries are no longer visible and queued for deletion on `free`.
+    pub ttl: Option<Duration>,
+    /// Metadata stored with the entry, replaces the metadata of an overwritten entry.
+    pub metadata: Metadata,
+}
+
+/// Kind of a change made outside of a storage provider.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Change of an entry which was not made through the storage provider, e.g. a file copied into the storage folder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalChange {
+    pub kind: ChangeKind,
+    /// Key of the entry.
+    pub key: String,
+    /// Byte size of the entry, `0` if the entry was removed.
+    pub size: u64,
+}
+
+/// Total usage of the stored entries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageUsage {
+    /// Total byte size of all entries.
+    pub bytes: u64,
+    /// Count of all entries.
+    pub entries: u64,
+}
+
+/// Size and free space of the filesystem or device of a storage provider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapacityData {
+    /// Total byte size.
+    pub total_bytes: u64,
+    /// Bytes which can be used by the storage provider.
+    pub free_bytes: u64,
+}
+
+/// Health of a storage provider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthData {
+    /// A probe entry could be written and removed.
+    pub writable: bool,
+    /// Free bytes of the underlying storage, `None` if unknown.
+    pub free_bytes: Option<u64>,
+    /// Share of failed writes of the recent writes, between `0` and `1`.
+    pub error_rate: f64,
+}
+
+/// Error returned by the storage manager.
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    /// No storage provider is registered for the layer.
+    LayerNotFound(String),
+    /// The key was not found in any storage provider.
+    NotFound(String),
+    /// The save would exceed the quota of the layer or of the client.
+    QuotaExceeded {
+        layer: String,
+        client: Option<String>,
+        usage: StorageUsage,
+        limit: Quota,
+    },
+    /// The key already exists and must not be overwritten.
+    Conflict(String),
+    /// The entry was changed or removed since the etag was read.
+    PreconditionFailed(String),
+    /// The checksum of a copied, imported or assembled entry does not match the checksum of its source.
+    ChecksumMismatch(String),
+    /// A transaction could not be applied or reverted.
+    Transaction(String),
+    /// The layer is degraded after repeated failures and no failover layer is available.
+    Unavailable(String),
+    /// The free space of the layer is below the critical watermark.
+    InsufficientSpace(String),
+    /// The key can not be used as the name of an entry, e.g. a key of an imported archive which contains a path.
+    InvalidKey(String),
+    /// Error reported by the storage provider.
+    Provider(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::LayerNotFound(layer) => write!(f, "Storage provider layer `{}` not found", layer),
+            StorageError::NotFound(key) => write!(f, "Requested key: `{}` not found in any storage provider.", key),
+            StorageError::QuotaExceeded { layer, client: Some(client), .. } => write!(f, "Quota of client `{}` exceeded on layer `{}`", client, layer),
+            StorageError::QuotaExceeded { layer, client: None, .. } => write!(f, "Quota exceeded on layer `{}`", layer),
+            StorageError::Conflict(key) => write!(f, "Key `{}` already exists", key),
+            StorageError::PreconditionFailed(key) => write!(f, "Key `{}` was changed by another writer", key),
+            StorageError::ChecksumMismatch(key) => write!(f, "Checksum mismatch for `{}`", key),
+            StorageError::Transaction(message) => write!(f, "{}", message),
+            StorageError::Unavailable(layer) => write!(f, "Storage provider layer `{}` is degraded", layer),
+            StorageError::InsufficientSpace(layer) => write!(f, "Free space of storage provider layer `{}` is below the critical watermark", layer),
+            StorageError::InvalidKey(key) => write!(f, "Key `{}` is not a valid entry name", key),
+            StorageError::Provider(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl StorageError {
+    /// Short name of the error variant, e.g. for metrics.
+    pub fn kind(self: &Self) -> &'static str {
+        match self {
+            StorageError::LayerNotFound(_) => "layer_not_found",
+            StorageError::NotFound(_) => "not_found",
+            StorageError::QuotaExceeded { .. } => "quota_exceeded",
+            StorageError::Conflict(_) => "conflict",
+            StorageError::PreconditionFailed(_) => "precondition_failed",
+            StorageError::ChecksumMismatch(_) => "checksum_mismatch",
+            StorageError::Transaction(_) => "transaction",
+            StorageError::Unavailable(_) => "unavailable",
+            StorageError::InsufficientSpace(_) => "insufficient_space",
+            StorageError::InvalidKey(_) => "invalid_key",
+            StorageError::Provider(_) => "provider",
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<String> for StorageError {
+    fn from(message: String) -> Self {
+        StorageError::Provider(message)
+    }
 }
 
 /// This `trait`must be implemented to use a `struct` as a storage provider.
@@ -26,10 +235,192 @@ pub trait StorageProvider {
     fn get(self: &Self, key: &str) -> Result<GetData, String>;
     /// Save data to the storage provider.
     fn save(self: &Self, key: &str, raw: Vec<u8>) -> Result<SaveData, String>;
+    /// Save data with additional options to the storage provider.
+    fn save_with_options(self: &Self, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<SaveData, String> {
+        if options.ttl.is_some() {
+            return Err("Time to live is not supported by the storage provider".to_owned());
+        }
+        if !options.metadata.is_empty() {
+            return Err("Metadata is not supported by the storage provider".to_owned());
+        }
+        self.save(key, raw)
+    }
+    /// Save data only if the key does not exist.
+    /// 
+    /// Returns `StorageError::Conflict` if the key exists.
+    fn save_if_absent(self: &Self, _key: &str, _raw: Vec<u8>, _options: &SaveOptions) -> Result<SaveData, StorageError> {
+        Err(StorageError::Provider("Conditional saves are not supported by the storage provider".to_owned()))
+    }
+    /// Save data only if the entry still has the `etag`.
+    /// 
+    /// Returns `StorageError::PreconditionFailed` if the entry was changed or removed.
+    fn save_if_match(self: &Self, _key: &str, _raw: Vec<u8>, _etag: &str, _options: &SaveOptions) -> Result<SaveData, StorageError> {
+        Err(StorageError::Provider("Conditional saves are not supported by the storage provider".to_owned()))
+    }
+    /// Set or remove the time to live of an entry, the ttl starts at the time of the call.
+    fn set_ttl(self: &Self, _key: &str, _ttl: Option<Duration>) -> Result<(), String> {
+        Err("Time to live is not supported by the storage provider".to_owned())
+    }
     /// Queue an entry for deletion in the storage provider.
     fn delete(self: &Self, key: &str);
+    /// Remove an entry immediately without queueing it for deletion, e.g. to roll back the save of a new key.
+    fn discard(self: &Self, _key: &str) -> Result<(), String> {
+        Err("Discard is not supported by the storage provider".to_owned())
+    }
+    /// Open a reader for the data of an entry.
+    fn get_reader(self: &Self, key: &str) -> Result<Box<dyn Read + '_>, String> {
+        Ok(Box::new(Cursor::new(self.get(key)?.data)))
+    }
+    /// Save the data of a reader to the storage provider.
+    fn save_from_reader(self: &Self, key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<SaveData, String> {
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw).map_err(|e| format!("Could not read data: {}", e))?;
+        self.save_with_options(key, raw, options)
+    }
+    /// Get multiple entries, the results are in the order of the keys.
+    fn get_many(self: &Self, keys: &[&str]) -> Vec<Result<GetData, String>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+    /// Save multiple entries, the results are in the order of the entries.
+    fn save_many(self: &Self, entries: Vec<(String, Vec<u8>)>) -> Vec<Result<SaveData, String>> {
+        entries.into_iter().map(|(key, raw)| self.save(&key, raw)).collect()
+    }
+    /// Queue multiple entries for deletion.
+    fn delete_many(self: &Self, keys: &[&str]) {
+        for key in keys {
+            self.delete(key);
+        }
+    }
+    /// Change the key of an entry without rewriting the data.
+    /// 
+    /// Returns `StorageError::Conflict` if `new_key` exists and `overwrite` is `false`.
+    /// The default implementation copies the entry and is not atomic.
+    fn rename(self: &Self, old_key: &str, new_key: &str, overwrite: bool) -> Result<(), StorageError> {
+        if !overwrite && self.stat(new_key).is_ok() {
+            return Err(StorageError::Conflict(new_key.to_owned()));
+        }
+        let stat = self.stat(old_key)?;
+        let options = SaveOptions {
+            ttl: stat.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO)),
+            metadata: stat.metadata,
+        };
+        let mut reader = self.get_reader(old_key)?;
+        self.save_from_reader(new_key, &mut reader, &options)?;
+        drop(reader);
+        self.delete(old_key);
+        Ok(())
+    }
+    /// Get the data of a version of an entry.
+    fn get_version(self: &Self, _key: &str, _version: &str) -> Result<GetData, String> {
+        Err("Versioning is not supported by the storage provider".to_owned())
+    }
+    /// List the kept versions of an entry, the oldest version first.
+    fn list_versions(self: &Self, _key: &str) -> Result<Vec<VersionData>, String> {
+        Err("Versioning is not supported by the storage provider".to_owned())
+    }
+    /// Capture a read-only view of all entries.
+    fn create_snapshot(self: &Self) -> Result<SnapshotId, String> {
+        Err("Snapshots are not supported by the storage provider".to_owned())
+    }
+    /// List all snapshots, the oldest snapshot first.
+    fn list_snapshots(self: &Self) -> Result<Vec<SnapshotData>, String> {
+        Err("Snapshots are not supported by the storage provider".to_owned())
+    }
+    /// Get the data of an entry at the time of the snapshot.
+    fn get_snapshot_entry(self: &Self, _snapshot: &str, _key: &str) -> Result<GetData, String> {
+        Err("Snapshots are not supported by the storage provider".to_owned())
+    }
+    /// List the keys of all entries in the snapshot which start with the prefix.
+    fn list_snapshot_entries(self: &Self, _snapshot: &str, _prefix: &str) -> Result<Vec<String>, String> {
+        Err("Snapshots are not supported by the storage provider".to_owned())
+    }
+    /// Remove a snapshot, the entries of the provider are not changed.
+    fn delete_snapshot(self: &Self, _snapshot: &str) -> Result<(), String> {
+        Err("Snapshots are not supported by the storage provider".to_owned())
+    }
+    /// Restore an entry which is queued for deletion.
+    /// 
+    /// Returns `StorageError::Conflict` if the key was saved again after the delete.
+    fn restore(self: &Self, _key: &str) -> Result<(), StorageError> {
+        Err(StorageError::Provider("Restore is not supported by the storage provider".to_owned()))
+    }
+    /// Returns `true` if the provider can stage saves which are made visible with `commit_staged`.
+    fn supports_staging(self: &Self) -> bool {
+        false
+    }
+    /// Write an entry without making it visible, returns a token for `commit_staged` or `discard_staged`.
+    fn stage_save(self: &Self, _key: &str, _raw: Vec<u8>, _options: &SaveOptions) -> Result<String, String> {
+        Err("Staging is not supported by the storage provider".to_owned())
+    }
+    /// Write the data of a reader without making it visible, returns a token for `commit_staged` or `discard_staged`.
+    fn stage_save_from_reader(self: &Self, key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<String, String> {
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw).map_err(|e| format!("Could not read data: {}", e))?;
+        self.stage_save(key, raw, options)
+    }
+    /// Open a reader for the data of a staged entry, e.g. to verify the data before it is committed.
+    fn get_staged_reader(self: &Self, _token: &str) -> Result<Box<dyn Read + '_>, String> {
+        Err("Staging is not supported by the storage provider".to_owned())
+    }
+    /// Make a staged entry visible under the key.
+    fn commit_staged(self: &Self, _key: &str, _token: &str) -> Result<(), String> {
+        Err("Staging is not supported by the storage provider".to_owned())
+    }
+    /// Remove a staged entry.
+    fn discard_staged(self: &Self, _token: &str) {}
     /// Execute free.
     fn free(self: &Self);
     /// Executes force free.
     fn force_free(self: &Self, all: bool);
+    /// Purge all entries in the delete queue which are older than `age`.
+    /// 
+    /// The default implementation purges nothing, a provider which does not track the age can not tell which entries are old enough.
+    fn free_older_than(self: &Self, _age: Duration) {}
+    /// List the keys of all entries which start with the prefix.
+    fn list(self: &Self, _prefix: &str) -> Result<Vec<String>, String> {
+        Err("Listing is not supported by the storage provider".to_owned())
+    }
+    /// Changes of the entries which were made outside of the provider since the last poll.
+    fn poll_changes(self: &Self) -> Result<Vec<ExternalChange>, String> {
+        Ok(vec![])
+    }
+    /// List the keys and byte sizes of all entries in the delete queue.
+    fn list_deleted(self: &Self) -> Result<Vec<(String, u64)>, String> {
+        Err("Listing the delete queue is not supported by the storage provider".to_owned())
+    }
+    /// Get the size and modification time of an entry without reading the data.
+    fn stat(self: &Self, _key: &str) -> Result<StatData, String> {
+        Err("Stat is not supported by the storage provider".to_owned())
+    }
+    /// List the keys of all entries which start with the prefix and have all the `tags` in their metadata.
+    fn list_tagged(self: &Self, prefix: &str, tags: &Metadata) -> Result<Vec<String>, String> {
+        let mut keys = vec![];
+        for key in self.list(prefix)? {
+            if let Ok(stat) = self.stat(&key) {
+                if tags.iter().all(|(name, value)| stat.metadata.get(name) == Some(value)) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+    /// Get the total usage of all entries, entries queued for deletion are not included.
+    fn usage(self: &Self) -> Result<StorageUsage, String> {
+        let mut usage = StorageUsage::default();
+        for key in self.list("")? {
+            if let Ok(stat) = self.stat(&key) {
+                usage.bytes += stat.size as u64;
+                usage.entries += 1;
+            }
+        }
+        Ok(usage)
+    }
+    /// Get the size and the free space of the underlying storage.
+    fn capacity(self: &Self) -> Result<CapacityData, String> {
+        Err("Capacity is not supported by the storage provider".to_owned())
+    }
+    /// Check if the provider can write, its free space and the rate of failed writes since the last check.
+    fn health(self: &Self) -> Result<HealthData, String> {
+        Err("Health checks are not supported by the storage provider".to_owned())
+    }
 }
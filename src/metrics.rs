@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::StorageError;
+
+/// Upper bounds in seconds of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Result of a storage operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// A read returned the requested key.
+    Hit,
+    /// A read did not find the requested key.
+    Miss,
+    /// The operation failed, the value is the kind of the `StorageError`.
+    Error(&'static str),
+}
+
+/// Result of a measured operation.
+pub(crate) trait MeasuredOutcome {
+    fn outcome(self: &Self) -> Outcome;
+}
+
+impl<T> MeasuredOutcome for Result<T, StorageError> {
+    fn outcome(self: &Self) -> Outcome {
+        match self {
+            Ok(_) => Outcome::Success,
+            Err(err) => Outcome::Error(err.kind()),
+        }
+    }
+}
+
+impl MeasuredOutcome for () {
+    fn outcome(self: &Self) -> Outcome {
+        Outcome::Success
+    }
+}
+
+/// Direction of transferred bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Read,
+    Written,
+}
+
+/// Receives the metrics of all storage operations of a `StorageManager`.
+pub trait StorageMetrics: Send + Sync {
+    /// Record a finished operation on a layer, e.g. `get`, `save`, `delete` or `free`.
+    fn record_operation(self: &Self, layer: &str, operation: &str, duration: Duration, outcome: Outcome);
+    /// Record bytes read from or written to a layer.
+    fn record_bytes(self: &Self, layer: &str, transfer: Transfer, bytes: u64);
+}
+
+/// Counters and latency histogram of an operation on a layer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperationStats {
+    /// Count of all calls.
+    pub count: u64,
+    /// Count of reads which returned data.
+    pub hits: u64,
+    /// Count of reads for keys which were not found.
+    pub misses: u64,
+    /// Count of failed calls by error kind.
+    pub errors: BTreeMap<String, u64>,
+    /// Sum of the durations of all calls.
+    pub total_duration: Duration,
+    /// Count of calls per bucket of `LATENCY_BUCKETS`, the last value counts the calls slower than the last bucket.
+    pub buckets: Vec<u64>,
+}
+
+/// Transferred bytes of a layer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ByteStats {
+    pub read: u64,
+    pub written: u64,
+}
+
+/// Keeps all metrics in memory.
+///
+/// # Example
+/// ```
+/// use std::{sync::Arc, time::Duration};
+/// use dispnet_storage::metrics::{InMemoryMetrics, Outcome, StorageMetrics};
+///
+/// let metrics = Arc::new(InMemoryMetrics::default());
+/// metrics.record_operation("layer1", "get", Duration::from_millis(2), Outcome::Miss);
+/// assert_eq!(metrics.operation("layer1", "get").unwrap().misses, 1);
+/// ```
+#[derive(Default)]
+pub struct InMemoryMetrics {
+    operations: Mutex<BTreeMap<(String, String), OperationStats>>,
+    bytes: Mutex<BTreeMap<String, ByteStats>>,
+}
+
+impl InMemoryMetrics {
+    /// Statistics of an operation on a layer, `None` if the operation was never called.
+    pub fn operation(self: &Self, layer: &str, operation: &str) -> Option<OperationStats> {
+        self.operations.lock().unwrap().get(&(layer.to_owned(), operation.to_owned())).cloned()
+    }
+
+    /// Transferred bytes of a layer.
+    pub fn bytes(self: &Self, layer: &str) -> ByteStats {
+        self.bytes.lock().unwrap().get(layer).cloned().unwrap_or_default()
+    }
+
+    /// All metrics in the Prometheus text exposition format.
+    #[cfg(feature = "prometheus")]
+    pub fn to_prometheus(self: &Self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let operations = self.operations.lock().unwrap();
+        let _result = writeln!(out, "# HELP dispnet_storage_operations_total Count of storage operations.");
+        let _result = writeln!(out, "# TYPE dispnet_storage_operations_total counter");
+        for ((layer, operation), stats) in operations.iter() {
+            let _result = writeln!(out, "dispnet_storage_operations_total{{{}}} {}", labels(layer, operation), stats.count);
+        }
+        let _result = writeln!(out, "# HELP dispnet_storage_lookups_total Count of reads by result.");
+        let _result = writeln!(out, "# TYPE dispnet_storage_lookups_total counter");
+        for ((layer, operation), stats) in operations.iter().filter(|(_, stats)| stats.hits + stats.misses > 0) {
+            let _result = writeln!(out, "dispnet_storage_lookups_total{{{},result=\"hit\"}} {}", labels(layer, operation), stats.hits);
+            let _result = writeln!(out, "dispnet_storage_lookups_total{{{},result=\"miss\"}} {}", labels(layer, operation), stats.misses);
+        }
+        let _result = writeln!(out, "# HELP dispnet_storage_errors_total Count of failed storage operations by error kind.");
+        let _result = writeln!(out, "# TYPE dispnet_storage_errors_total counter");
+        for ((layer, operation), stats) in operations.iter() {
+            for (kind, count) in stats.errors.iter() {
+                let _result = writeln!(out, "dispnet_storage_errors_total{{{},kind=\"{}\"}} {}", labels(layer, operation), escape(kind), count);
+            }
+        }
+        let _result = writeln!(out, "# HELP dispnet_storage_operation_duration_seconds Latency of storage operations.");
+        let _result = writeln!(out, "# TYPE dispnet_storage_operation_duration_seconds histogram");
+        for ((layer, operation), stats) in operations.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets.iter()) {
+                cumulative += count;
+                let _result = writeln!(out, "dispnet_storage_operation_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels(layer, operation), bound, cumulative);
+            }
+            let _result = writeln!(out, "dispnet_storage_operation_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels(layer, operation), stats.count);
+            let _result = writeln!(out, "dispnet_storage_operation_duration_seconds_sum{{{}}} {}", labels(layer, operation), stats.total_duration.as_secs_f64());
+            let _result = writeln!(out, "dispnet_storage_operation_duration_seconds_count{{{}}} {}", labels(layer, operation), stats.count);
+        }
+        let _result = writeln!(out, "# HELP dispnet_storage_bytes_total Bytes transferred from and to the storage layers.");
+        let _result = writeln!(out, "# TYPE dispnet_storage_bytes_total counter");
+        for (layer, bytes) in self.bytes.lock().unwrap().iter() {
+            let _result = writeln!(out, "dispnet_storage_bytes_total{{layer=\"{}\",direction=\"read\"}} {}", escape(layer), bytes.read);
+            let _result = writeln!(out, "dispnet_storage_bytes_total{{layer=\"{}\",direction=\"written\"}} {}", escape(layer), bytes.written);
+        }
+        out
+    }
+}
+
+impl StorageMetrics for InMemoryMetrics {
+    fn record_operation(self: &Self, layer: &str, operation: &str, duration: Duration, outcome: Outcome) {
+        let mut operations = self.operations.lock().unwrap();
+        let stats = operations.entry((layer.to_owned(), operation.to_owned())).or_default();
+        if stats.buckets.is_empty() {
+            stats.buckets = vec![0; LATENCY_BUCKETS.len() + 1];
+        }
+        stats.count += 1;
+        stats.total_duration += duration;
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| duration.as_secs_f64() <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        stats.buckets[bucket] += 1;
+        match outcome {
+            Outcome::Success => {}
+            Outcome::Hit => stats.hits += 1,
+            Outcome::Miss => stats.misses += 1,
+            Outcome::Error(kind) => *stats.errors.entry(kind.to_owned()).or_default() += 1,
+        }
+    }
+
+    fn record_bytes(self: &Self, layer: &str, transfer: Transfer, bytes: u64) {
+        let mut all_bytes = self.bytes.lock().unwrap();
+        let stats = all_bytes.entry(layer.to_owned()).or_default();
+        match transfer {
+            Transfer::Read => stats.read += bytes,
+            Transfer::Written => stats.written += bytes,
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn labels(layer: &str, operation: &str) -> String {
+    format!("layer=\"{}\",operation=\"{}\"", escape(layer), escape(operation))
+}
+
+/// Escape a Prometheus label value.
+#[cfg(feature = "prometheus")]
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{InMemoryMetrics, Outcome, StorageMetrics, Transfer};
+
+    #[test]
+    fn in_memory() {
+        let metrics = InMemoryMetrics::default();
+        metrics.record_operation("layer1", "get", Duration::from_micros(50), Outcome::Hit);
+        metrics.record_operation("layer1", "get", Duration::from_secs(10), Outcome::Miss);
+        metrics.record_operation("layer1", "save", Duration::from_millis(3), Outcome::Error("quota_exceeded"));
+        metrics.record_bytes("layer1", Transfer::Written, 4);
+        let get = metrics.operation("layer1", "get").unwrap();
+        assert_eq!((get.count, get.hits, get.misses), (2, 1, 1));
+        assert_eq!(get.buckets[0], 1);
+        assert_eq!(*get.buckets.last().unwrap(), 1);
+        assert_eq!(metrics.operation("layer1", "save").unwrap().errors.get("quota_exceeded"), Some(&1));
+        assert_eq!(metrics.bytes("layer1").written, 4);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus() {
+        let metrics = InMemoryMetrics::default();
+        metrics.record_operation("layer1", "get", Duration::from_millis(2), Outcome::Success);
+        let text = metrics.to_prometheus();
+        assert!(text.contains("dispnet_storage_operations_total{layer=\"layer1\",operation=\"get\"} 1"));
+        assert!(text.contains("dispnet_storage_operation_duration_seconds_bucket{layer=\"layer1\",operation=\"get\",le=\"0.005\"} 1"));
+    }
+}
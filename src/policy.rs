@@ -1,5 +1,7 @@
 use dispnet_shared::Package;
 
+use crate::trace::PolicySpan;
+
 /// Trigger events for policies.
 #[derive(Debug, PartialEq)]
 pub enum PolicyTrigger {
@@ -187,25 +189,33 @@ impl PolicyManager {
 
     /// Validate incoming packages. Only returns `false` if any policy has failed.
     pub fn validate_incoming(self: &Self, package: &Package, client: &str) -> bool {
-        for policy in self.incoming_policies.iter() {
-            if !policy.validate(package, client) {
-                return false;
+        PolicySpan::new("validate_incoming", client, &package.package_id).run(|span| {
+            for policy in self.incoming_policies.iter() {
+                if !policy.validate(package, client) {
+                    span.decided(Some(&policy.name), "rejected");
+                    return false;
+                }
             }
-        }
-        true
+            span.decided(None, "accepted");
+            true
+        })
     }
 
     /// Resolve the layer name which should be used for the package. Returns `Err` if no policy matches the conditions for the package.
     pub fn resolve_layer(self: &Self, package: &Package, client: &str) -> Result<String, ()> {
-        for policy in self.layer_policies.iter() {
-            // on a successful validation we switch to the provided layer
-            if policy.validate(package, client) {
-                if let PolicyType::Layer(layer) = &policy.policy_type {
-                    return Ok(layer.success_layer_key.to_owned());
+        PolicySpan::new("resolve_layer", client, &package.package_id).run(|span| {
+            for policy in self.layer_policies.iter() {
+                // on a successful validation we switch to the provided layer
+                if policy.validate(package, client) {
+                    if let PolicyType::Layer(layer) = &policy.policy_type {
+                        span.decided(Some(&policy.name), &layer.success_layer_key);
+                        return Ok(layer.success_layer_key.to_owned());
+                    }
                 }
             }
-        }
-        Err(())
+            span.decided(None, "unresolved");
+            Err(())
+        })
     }
 
     /// Validation based on trigger events. Only returns `false` if any policy has failed.
@@ -216,22 +226,26 @@ impl PolicyManager {
         package: &Package,
         client: &str,
     ) -> bool {
-        for policy in self.trigger_policies.iter().filter(|x| {
-            if let PolicyType::Trigger(trigger_policy) = &x.policy_type {
-                if trigger_policy.get_validation_conditions.contains(trigger)
-                    && trigger_policy.layer == source_layer
-                {
-                    return true;
+        PolicySpan::new("validate_trigger", client, &package.package_id).run(|span| {
+            for policy in self.trigger_policies.iter().filter(|x| {
+                if let PolicyType::Trigger(trigger_policy) = &x.policy_type {
+                    if trigger_policy.get_validation_conditions.contains(trigger)
+                        && trigger_policy.layer == source_layer
+                    {
+                        return true;
+                    }
+                }
+                false
+            }) {
+                // every trigger policy filtered by `PolicyTrigger`
+                if !policy.validate(package, client) {
+                    span.decided(Some(&policy.name), "rejected");
+                    return false;
                 }
             }
-            false
-        }) {
-            // every trigger policy filtered by `PolicyTrigger`
-            if !policy.validate(package, client) {
-                return false;
-            }
-        }
-        true
+            span.decided(None, "accepted");
+            true
+        })
     }
 }
 
@@ -0,0 +1,268 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{StorageError, StorageUsage};
+
+/// Limits for a layer or a client on a layer, `None` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Quota {
+    /// Maximum total byte size of all entries.
+    pub max_bytes: Option<u64>,
+    /// Maximum count of entries.
+    pub max_entries: Option<u64>,
+}
+
+impl Quota {
+    fn allows(self: &Self, usage: &StorageUsage) -> bool {
+        self.max_bytes.filter(|max| usage.bytes > *max).is_none() && self.max_entries.filter(|max| usage.entries > *max).is_none()
+    }
+}
+
+/// Current usage compared to the configured limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotaUsage {
+    /// Current usage.
+    pub usage: StorageUsage,
+    /// Configured limit, `None` if no quota is set.
+    pub limit: Option<Quota>,
+}
+
+/// Usage change of a single save, calculated before the entry is written.
+pub(crate) struct Reservation {
+    layer: String,
+    key: String,
+    client: Option<String>,
+    previous: Option<(Option<String>, u64)>,
+    size: u64,
+}
+
+#[derive(Default)]
+struct LayerUsage {
+    quota: Option<Quota>,
+    usage: StorageUsage,
+    client_quotas: HashMap<String, Quota>,
+    client_usage: HashMap<String, StorageUsage>,
+    /// Owner client and size of every entry saved by a client.
+    owners: HashMap<String, (String, u64)>,
+}
+
+/// Keeps track of the usage on all layers and enforces the quotas.
+#[derive(Default)]
+pub(crate) struct QuotaTracker {
+    layers: Mutex<HashMap<String, LayerUsage>>,
+}
+
+impl QuotaTracker {
+    /// Start tracking a layer with the usage reported by the provider.
+    /// 
+    /// `owners` are the key, owner client and size of the stored entries which were saved by a client.
+    pub fn track_layer(self: &Self, layer: &str, usage: StorageUsage, owners: Vec<(String, String, u64)>) {
+        let mut layers = self.layers.lock().unwrap();
+        let layer_usage = layers.entry(layer.to_owned()).or_default();
+        layer_usage.usage = usage;
+        layer_usage.client_usage.clear();
+        layer_usage.owners.clear();
+        for (key, client, size) in owners {
+            add_entry(layer_usage.client_usage.entry(client.to_owned()).or_default(), size);
+            layer_usage.owners.insert(key, (client, size));
+        }
+    }
+
+    /// Stop tracking a layer.
+    pub fn remove_layer(self: &Self, layer: &str) {
+        self.layers.lock().unwrap().remove(layer);
+    }
+
+    pub fn set_layer_quota(self: &Self, layer: &str, quota: Option<Quota>) {
+        let mut layers = self.layers.lock().unwrap();
+        layers.entry(layer.to_owned()).or_default().quota = quota;
+    }
+
+    pub fn set_client_quota(self: &Self, layer: &str, client: &str, quota: Option<Quota>) {
+        let mut layers = self.layers.lock().unwrap();
+        let layer_usage = layers.entry(layer.to_owned()).or_default();
+        match quota {
+            Some(quota) => {
+                layer_usage.client_quotas.insert(client.to_owned(), quota);
+            }
+            None => {
+                layer_usage.client_quotas.remove(client);
+            }
+        }
+    }
+
+    pub fn layer_usage(self: &Self, layer: &str) -> Option<QuotaUsage> {
+        let layers = self.layers.lock().unwrap();
+        layers.get(layer).map(|layer_usage| QuotaUsage {
+            usage: layer_usage.usage.clone(),
+            limit: layer_usage.quota.clone(),
+        })
+    }
+
+    pub fn client_usage(self: &Self, layer: &str, client: &str) -> Option<QuotaUsage> {
+        let layers = self.layers.lock().unwrap();
+        layers.get(layer).map(|layer_usage| QuotaUsage {
+            usage: layer_usage.client_usage.get(client).cloned().unwrap_or_default(),
+            limit: layer_usage.client_quotas.get(client).cloned(),
+        })
+    }
+
+    /// Validate the quotas for a save and reserve the usage.
+    /// 
+    /// `previous_size` is the size of the entry which will be overwritten.
+    pub fn reserve(
+        self: &Self,
+        layer: &str,
+        client: Option<&str>,
+        key: &str,
+        previous_size: Option<u64>,
+        size: u64,
+    ) -> Result<Reservation, StorageError> {
+        let mut layers = self.layers.lock().unwrap();
+        let layer_usage = layers.entry(layer.to_owned()).or_default();
+        let previous = previous_size.map(|previous_size| {
+            (layer_usage.owners.get(key).map(|owner| owner.0.to_owned()), previous_size)
+        });
+
+        let mut usage = layer_usage.usage.clone();
+        apply(&mut usage, previous.as_ref().map(|p| p.1), size);
+        if let Some(quota) = &layer_usage.quota {
+            if !quota.allows(&usage) {
+                return Err(StorageError::QuotaExceeded {
+                    layer: layer.to_owned(),
+                    client: None,
+                    usage: layer_usage.usage.clone(),
+                    limit: quota.clone(),
+                });
+            }
+        }
+
+        let mut client_usage = None;
+        if let Some(client) = client {
+            let mut usage = layer_usage.client_usage.get(client).cloned().unwrap_or_default();
+            let previous_size = previous.as_ref().and_then(|p| match &p.0 {
+                Some(owner) if owner == client => Some(p.1),
+                _ => None,
+            });
+            apply(&mut usage, previous_size, size);
+            if let Some(quota) = layer_usage.client_quotas.get(client) {
+                if !quota.allows(&usage) {
+                    return Err(StorageError::QuotaExceeded {
+                        layer: layer.to_owned(),
+                        client: Some(client.to_owned()),
+                        usage: layer_usage.client_usage.get(client).cloned().unwrap_or_default(),
+                        limit: quota.clone(),
+                    });
+                }
+            }
+            client_usage = Some(usage);
+        }
+
+        // reserve the usage, a failed save must call `release`
+        layer_usage.usage = usage;
+        let reservation = Reservation {
+            layer: layer.to_owned(),
+            key: key.to_owned(),
+            client: client.map(|c| c.to_owned()),
+            previous,
+            size,
+        };
+        move_ownership(layer_usage, &reservation, client_usage);
+        Ok(reservation)
+    }
+
+    /// Revert a reservation after the save has failed.
+    pub fn release(self: &Self, reservation: Reservation) {
+        let mut layers = self.layers.lock().unwrap();
+        if let Some(layer_usage) = layers.get_mut(&reservation.layer) {
+            remove_entry(&mut layer_usage.usage, reservation.size);
+            if let Some((_, previous_size)) = reservation.previous {
+                add_entry(&mut layer_usage.usage, previous_size);
+            }
+            if let Some(client) = &reservation.client {
+                if let Some(usage) = layer_usage.client_usage.get_mut(client) {
+                    remove_entry(usage, reservation.size);
+                }
+                layer_usage.owners.remove(&reservation.key);
+            }
+            if let Some((Some(owner), previous_size)) = reservation.previous {
+                add_entry(layer_usage.client_usage.entry(owner.to_owned()).or_default(), previous_size);
+                layer_usage.owners.insert(reservation.key, (owner, previous_size));
+            }
+        }
+    }
+
+    /// Account an entry which was removed from a layer.
+    pub fn removed(self: &Self, layer: &str, key: &str, size: u64) {
+        let mut layers = self.layers.lock().unwrap();
+        if let Some(layer_usage) = layers.get_mut(layer) {
+            remove_entry(&mut layer_usage.usage, size);
+            if let Some((owner, owned_size)) = layer_usage.owners.remove(key) {
+                if let Some(usage) = layer_usage.client_usage.get_mut(&owner) {
+                    remove_entry(usage, owned_size);
+                }
+            }
+        }
+    }
+
+    /// Account an entry which was renamed, `overwritten_size` is the size of a replaced entry with the new key.
+    pub fn renamed(self: &Self, layer: &str, old_key: &str, new_key: &str, overwritten_size: Option<u64>) {
+        if let Some(size) = overwritten_size {
+            self.removed(layer, new_key, size);
+        }
+        let mut layers = self.layers.lock().unwrap();
+        if let Some(layer_usage) = layers.get_mut(layer) {
+            if let Some(owner) = layer_usage.owners.remove(old_key) {
+                layer_usage.owners.insert(new_key.to_owned(), owner);
+            }
+        }
+    }
+
+    /// Replace the layer usage with the usage reported by the provider and drop all client entries which no longer exist.
+    pub fn refresh(self: &Self, layer: &str, usage: StorageUsage, exists: impl Fn(&str) -> bool) {
+        let mut layers = self.layers.lock().unwrap();
+        if let Some(layer_usage) = layers.get_mut(layer) {
+            layer_usage.usage = usage;
+            let removed: Vec<String> = layer_usage.owners.keys().filter(|key| !exists(key)).cloned().collect();
+            for key in removed {
+                if let Some((owner, owned_size)) = layer_usage.owners.remove(&key) {
+                    if let Some(usage) = layer_usage.client_usage.get_mut(&owner) {
+                        remove_entry(usage, owned_size);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn apply(usage: &mut StorageUsage, previous_size: Option<u64>, size: u64) {
+    if let Some(previous_size) = previous_size {
+        remove_entry(usage, previous_size);
+    }
+    add_entry(usage, size);
+}
+
+fn add_entry(usage: &mut StorageUsage, size: u64) {
+    usage.bytes += size;
+    usage.entries += 1;
+}
+
+fn remove_entry(usage: &mut StorageUsage, size: u64) {
+    usage.bytes = usage.bytes.saturating_sub(size);
+    usage.entries = usage.entries.saturating_sub(1);
+}
+
+fn move_ownership(layer_usage: &mut LayerUsage, reservation: &Reservation, client_usage: Option<StorageUsage>) {
+    // the previous owner loses the overwritten entry
+    if let Some((Some(owner), previous_size)) = &reservation.previous {
+        if reservation.client.as_deref() != Some(owner.as_str()) {
+            if let Some(usage) = layer_usage.client_usage.get_mut(owner) {
+                remove_entry(usage, *previous_size);
+            }
+        }
+        layer_usage.owners.remove(&reservation.key);
+    }
+    if let (Some(client), Some(usage)) = (&reservation.client, client_usage) {
+        layer_usage.client_usage.insert(client.to_owned(), usage);
+        layer_usage.owners.insert(reservation.key.to_owned(), (client.to_owned(), reservation.size));
+    }
+}
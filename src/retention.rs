@@ -0,0 +1,115 @@
+use std::time::{Duration, SystemTime};
+
+const DAY_IN_SECONDS: u64 = 86_400;
+
+/// Limits for entries in the delete queue of a storage provider.
+/// 
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use dispnet_storage::retention::Retention;
+/// 
+/// let retention = Retention {
+///     max_age: Some(Duration::from_secs(3_600)),
+///     max_bytes: Some(1_024 * 1_024),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retention {
+    /// Entries older than this are purged on `free`.
+    pub max_age: Option<Duration>,
+    /// Entries older than this are purged on `force_free` if not all entries should be purged.
+    pub force_max_age: Option<Duration>,
+    /// Maximum total byte size of the delete queue, the oldest entries are purged first.
+    pub max_bytes: Option<u64>,
+    /// Maximum count of entries in the delete queue, the oldest entries are purged first.
+    pub max_entries: Option<usize>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_age: Some(Duration::from_secs(DAY_IN_SECONDS * 15)),
+            force_max_age: Some(Duration::from_secs(DAY_IN_SECONDS)),
+            max_bytes: None,
+            max_entries: None,
+        }
+    }
+}
+
+/// Entry in the delete queue of a storage provider.
+pub struct QueuedEntry<T> {
+    /// Provider specific handle of the entry.
+    pub handle: T,
+    /// Byte size of the entry.
+    pub size: u64,
+    /// Time used to calculate the age of the entry.
+    pub modified: SystemTime,
+}
+
+impl Retention {
+    /// Select the entries which must be purged to satisfy all limits.
+    /// 
+    /// Entries older than `max_age` are always selected, afterwards the oldest entries are selected until the size and count limits are satisfied.
+    pub fn select_purge<T>(self: &Self, entries: Vec<QueuedEntry<T>>, max_age: Option<Duration>) -> Vec<QueuedEntry<T>> {
+        let now = SystemTime::now();
+        let (mut purge, mut keep): (Vec<QueuedEntry<T>>, Vec<QueuedEntry<T>>) = entries.into_iter().partition(|entry| {
+            match max_age {
+                Some(max_age) => now.duration_since(entry.modified).map(|age| age > max_age).unwrap_or(false),
+                None => false,
+            }
+        });
+        keep.sort_by_key(|entry| entry.modified);
+        let mut bytes: u64 = keep.iter().map(|entry| entry.size).sum();
+        let mut count = keep.len();
+        let mut keep = keep.into_iter();
+        while self.max_bytes.filter(|max| bytes > *max).is_some() || self.max_entries.filter(|max| count > *max).is_some() {
+            match keep.next() {
+                Some(entry) => {
+                    bytes -= entry.size;
+                    count -= 1;
+                    purge.push(entry);
+                }
+                None => break,
+            }
+        }
+        purge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{QueuedEntry, Retention};
+
+    fn entry(handle: u32, size: u64, age_seconds: u64) -> QueuedEntry<u32> {
+        QueuedEntry {
+            handle,
+            size,
+            modified: SystemTime::now() - Duration::from_secs(age_seconds),
+        }
+    }
+
+    #[test]
+    fn select_by_age() {
+        let retention = Retention::default();
+        let entries = vec![entry(1, 1, 10), entry(2, 1, 3_600 * 24 * 16)];
+        let purge = retention.select_purge(entries, retention.max_age);
+        assert_eq!(purge.iter().map(|e| e.handle).collect::<Vec<u32>>(), vec![2]);
+    }
+
+    #[test]
+    fn select_oldest_first() {
+        let retention = Retention {
+            max_age: None,
+            max_bytes: Some(10),
+            max_entries: Some(3),
+            ..Default::default()
+        };
+        let entries = vec![entry(1, 4, 30), entry(2, 4, 10), entry(3, 4, 20), entry(4, 1, 5), entry(5, 1, 1)];
+        let purge = retention.select_purge(entries, retention.max_age);
+        assert_eq!(purge.iter().map(|e| e.handle).collect::<Vec<u32>>(), vec![1, 3]);
+    }
+}
@@ -0,0 +1,754 @@
+//! Storage provider which appends all entries into large segment files.
+//!
+//! Every change appends a record to the active segment and an in-memory index keeps the location of the data of every entry.
+//! Deleted entries stay in the segments as tombstones until `free` purges them and compacts the segments.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    retention::{QueuedEntry, Retention},
+    sidecar, trace::OperationSpan, CapacityData, GetData, Metadata, SaveData, SaveOptions, StatData, StorageError, StorageProvider, StorageUsage,
+};
+
+/// File extension of the segment files.
+const SEGMENT_EXTENSION: &str = "seg";
+/// Byte size of the record header: kind, time, expire time, key length, metadata length and data length.
+const HEADER_SIZE: u64 = 33;
+/// Byte size of the CRC32 checksum at the end of every record.
+const TRAILER_SIZE: u64 = 4;
+/// Upper limit for the key and metadata of a record, larger values are treated as a damaged record.
+const MAX_KEY_META_SIZE: u64 = 1024 * 1024;
+
+/// Saved entry.
+const RECORD_PUT: u8 = 1;
+/// Entry in the delete queue, written by the compaction.
+const RECORD_DELETED: u8 = 2;
+/// Moves an entry into the delete queue.
+const RECORD_TOMBSTONE: u8 = 3;
+/// Moves an entry from the delete queue back.
+const RECORD_RESTORE: u8 = 4;
+/// Removes an entry from the delete queue.
+const RECORD_PURGE: u8 = 5;
+
+/// Configuration of a `SegmentStorageProvider`.
+#[derive(Clone, Debug)]
+pub struct SegmentStorageConfig {
+    /// Retention of the entries in the delete queue.
+    pub retention: Retention,
+    /// A new segment is started when a record would grow the active segment beyond this byte size.
+    pub max_segment_bytes: u64,
+    /// `free` compacts the segments when the share of bytes used by replaced, deleted and purged records is above this value.
+    pub compact_ratio: f64,
+}
+
+impl Default for SegmentStorageConfig {
+    fn default() -> Self {
+        Self {
+            retention: Retention::default(),
+            max_segment_bytes: 64 * 1024 * 1024,
+            compact_ratio: 0.5,
+        }
+    }
+}
+
+/// Location of the data of an entry.
+#[derive(Clone)]
+struct Entry {
+    segment: u64,
+    /// Offset of the record in the segment.
+    offset: u64,
+    /// Byte size of the whole record.
+    record_size: u64,
+    /// Offset of the data in the segment.
+    data_offset: u64,
+    size: u64,
+    /// Time of the save, for entries in the delete queue the time of the delete.
+    time: SystemTime,
+    expires: Option<SystemTime>,
+    metadata: Metadata,
+}
+
+impl Entry {
+    fn etag(self: &Self) -> String {
+        format!("{:x}-{:x}", self.segment, self.offset)
+    }
+}
+
+/// Header fields of a record.
+struct Record<'a> {
+    kind: u8,
+    key: &'a str,
+    time: SystemTime,
+    expires: Option<SystemTime>,
+    metadata: &'a Metadata,
+}
+
+#[derive(Default)]
+struct Entries {
+    live: BTreeMap<String, Entry>,
+    deleted: BTreeMap<String, Entry>,
+}
+
+impl Entries {
+    /// Apply a record read from a segment.
+    fn apply(self: &mut Self, kind: u8, key: String, entry: Entry) {
+        match kind {
+            RECORD_PUT => {
+                self.live.insert(key, entry);
+            }
+            RECORD_DELETED => {
+                self.deleted.insert(key, entry);
+            }
+            RECORD_TOMBSTONE => {
+                if let Some(live) = self.live.remove(&key) {
+                    self.deleted.insert(key, Entry { time: entry.time, ..live });
+                }
+            }
+            // a key which was saved again after the delete keeps the newer entry
+            RECORD_RESTORE if !self.live.contains_key(&key) => {
+                if let Some(deleted) = self.deleted.remove(&key) {
+                    self.live.insert(key, Entry { time: entry.time, ..deleted });
+                }
+            }
+            RECORD_RESTORE => {}
+            _ => {
+                self.deleted.remove(&key);
+            }
+        }
+    }
+
+    /// Byte size of all records which are still needed.
+    fn used_bytes(self: &Self) -> u64 {
+        self.live.values().chain(self.deleted.values()).map(|entry| entry.record_size).sum()
+    }
+}
+
+struct SegmentState {
+    entries: Entries,
+    /// ID of the segment which receives new records.
+    active: u64,
+    writer: File,
+    /// Byte size of the active segment.
+    active_size: u64,
+    /// Byte size of all segments.
+    total_bytes: u64,
+}
+
+/// Storage provider which keeps many small entries in a few large segment files.
+///
+/// Saves, deletes and restores append a record to the active segment, reads use the offset of the data from the in-memory index.
+/// The index is rebuilt from the segments on startup, an incomplete record at the end of a segment is cut off.
+pub struct SegmentStorageProvider {
+    folder: String,
+    config: SegmentStorageConfig,
+    /// Reads hold a read lock, appends and the compaction hold the write lock.
+    state: RwLock<SegmentState>,
+}
+
+impl SegmentStorageProvider {
+    pub fn new(folder: String) -> Result<Self, String> {
+        Self::with_config(folder, SegmentStorageConfig::default())
+    }
+
+    /// Create a provider with a custom configuration and load the index from the segments in the folder.
+    pub fn with_config(folder: String, config: SegmentStorageConfig) -> Result<Self, String> {
+        fs::create_dir_all(&folder).map_err(|e| format!("Could not create folder `{}`: {}", folder, e))?;
+        let ids = segment_ids(&folder)?;
+        let mut entries = Entries::default();
+        let mut total_bytes = 0;
+        for (index, id) in ids.iter().enumerate() {
+            let path = segment_path(&folder, *id);
+            let valid_size = replay_segment(&path, *id, &mut entries)?;
+            let size = fs::metadata(&path).map_err(|e| format!("Could not read segment: {}", e))?.len();
+            if valid_size < size && index == ids.len() - 1 {
+                // the last record of the active segment was not written completely
+                let file = OpenOptions::new().write(true).open(&path).map_err(|e| format!("Could not open segment: {}", e))?;
+                file.set_len(valid_size).map_err(|e| format!("Could not repair segment: {}", e))?;
+                total_bytes += valid_size;
+            } else {
+                total_bytes += size;
+            }
+        }
+        let active = ids.last().copied().unwrap_or(1);
+        let path = segment_path(&folder, active);
+        let writer = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| format!("Could not open segment: {}", e))?;
+        let active_size = writer.metadata().map_err(|e| format!("Could not open segment: {}", e))?.len();
+        Ok(Self {
+            folder,
+            config,
+            state: RwLock::new(SegmentState {
+                entries,
+                active,
+                writer,
+                active_size,
+                total_bytes,
+            }),
+        })
+    }
+
+    /// Rewrite all live and deleted entries into new segments and remove the old segments.
+    pub fn compact(self: &SegmentStorageProvider) -> Result<(), String> {
+        OperationSpan::provider("compact", &self.folder, "").entered().run(|_span| {
+            let mut state = self.state.write().unwrap();
+            self.compact_locked(&mut state)
+        })
+    }
+
+    /// Count of the segment files.
+    pub fn segment_count(self: &SegmentStorageProvider) -> Result<usize, String> {
+        Ok(segment_ids(&self.folder)?.len())
+    }
+
+    fn compact_locked(self: &SegmentStorageProvider, state: &mut SegmentState) -> Result<(), String> {
+        let old_ids = segment_ids(&self.folder)?;
+        self.start_segment(state, state.active + 1)?;
+        let live = state.entries.live.clone();
+        let deleted = state.entries.deleted.clone();
+        // the old segments are only removed after all entries were copied, until then a restart replays both
+        for (kind, entries) in [(RECORD_PUT, live), (RECORD_DELETED, deleted)] {
+            for (key, entry) in entries {
+                let mut data = self.open_data(&entry)?;
+                let record = Record {
+                    kind,
+                    key: &key,
+                    time: entry.time,
+                    expires: entry.expires,
+                    metadata: &entry.metadata,
+                };
+                let copy = self.append(state, &record, &mut data, entry.size)?;
+                match kind {
+                    RECORD_PUT => state.entries.live.insert(key, copy),
+                    _ => state.entries.deleted.insert(key, copy),
+                };
+            }
+        }
+        for id in old_ids {
+            fs::remove_file(segment_path(&self.folder, id)).map_err(|e| format!("Could not remove segment: {}", e))?;
+        }
+        state.total_bytes = state.entries.used_bytes();
+        Ok(())
+    }
+
+    fn start_segment(self: &SegmentStorageProvider, state: &mut SegmentState, id: u64) -> Result<(), String> {
+        state.writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.folder, id))
+            .map_err(|e| format!("Could not create segment: {}", e))?;
+        state.active = id;
+        state.active_size = 0;
+        Ok(())
+    }
+
+    /// Append a record with `size` bytes of data from the reader to the active segment.
+    fn append(self: &SegmentStorageProvider, state: &mut SegmentState, record: &Record, data: &mut dyn Read, size: u64) -> Result<Entry, String> {
+        let metadata = sidecar::format(record.metadata);
+        let key_meta_size = (record.key.len() + metadata.len()) as u64;
+        if key_meta_size > MAX_KEY_META_SIZE {
+            return Err("Key and metadata are too large".to_owned());
+        }
+        let record_size = HEADER_SIZE + key_meta_size + size + TRAILER_SIZE;
+        if state.active_size > 0 && state.active_size + record_size > self.config.max_segment_bytes {
+            self.start_segment(state, state.active + 1)?;
+        }
+        let time = millis(Some(record.time));
+        let expires = millis(record.expires);
+        let mut head = Vec::with_capacity((HEADER_SIZE + key_meta_size) as usize);
+        head.push(record.kind);
+        head.extend_from_slice(&time.to_le_bytes());
+        head.extend_from_slice(&expires.to_le_bytes());
+        head.extend_from_slice(&(record.key.len() as u32).to_le_bytes());
+        head.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        head.extend_from_slice(&size.to_le_bytes());
+        head.extend_from_slice(record.key.as_bytes());
+        head.extend_from_slice(metadata.as_bytes());
+
+        let offset = state.active_size;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&head);
+        let result = state
+            .writer
+            .write_all(&head)
+            .and_then(|_| copy_hashed(data, &mut state.writer, &mut hasher, size))
+            .and_then(|_| state.writer.write_all(&hasher.finalize().to_le_bytes()))
+            .and_then(|_| state.writer.sync_data());
+        if let Err(e) = result {
+            // cut off the incomplete record, otherwise all following records would be lost on the next startup
+            let _result = state.writer.set_len(offset);
+            return Err(format!("Could not write segment: {}", e));
+        }
+        state.active_size += record_size;
+        state.total_bytes += record_size;
+        Ok(Entry {
+            segment: state.active,
+            offset,
+            record_size,
+            data_offset: offset + HEADER_SIZE + key_meta_size,
+            size,
+            time: from_millis(time).unwrap_or(UNIX_EPOCH),
+            expires: from_millis(expires),
+            metadata: record.metadata.clone(),
+        })
+    }
+
+    /// Append a record without data.
+    fn append_marker(self: &SegmentStorageProvider, state: &mut SegmentState, kind: u8, key: &str) -> Result<Entry, String> {
+        let record = Record {
+            kind,
+            key,
+            time: SystemTime::now(),
+            expires: None,
+            metadata: &Metadata::new(),
+        };
+        self.append(state, &record, &mut std::io::empty(), 0)
+    }
+
+    /// Open a reader for the data of an entry, the reader stays valid if the segment is removed by a compaction.
+    fn open_data(self: &SegmentStorageProvider, entry: &Entry) -> Result<std::io::Take<File>, String> {
+        let mut file = File::open(segment_path(&self.folder, entry.segment)).map_err(|e| format!("Could not open segment: {}", e))?;
+        file.seek(SeekFrom::Start(entry.data_offset)).map_err(|e| format!("Could not read segment: {}", e))?;
+        Ok(file.take(entry.size))
+    }
+
+    fn delete_locked(self: &SegmentStorageProvider, state: &mut SegmentState, key: &str) {
+        if state.entries.live.contains_key(key) {
+            if let Ok(tombstone) = self.append_marker(state, RECORD_TOMBSTONE, key) {
+                state.entries.apply(RECORD_TOMBSTONE, key.to_owned(), tombstone);
+            }
+        }
+    }
+
+    fn delete_expired(self: &SegmentStorageProvider, state: &mut SegmentState) {
+        let expired: Vec<String> = state.entries.live.iter().filter(|(_key, entry)| is_expired(entry.expires)).map(|(key, _entry)| key.to_owned()).collect();
+        for key in expired {
+            self.delete_locked(state, &key);
+        }
+    }
+
+    fn purge(self: &SegmentStorageProvider, state: &mut SegmentState, retention: &Retention, max_age: Option<Duration>) {
+        let queued = state
+            .entries
+            .deleted
+            .iter()
+            .map(|(key, entry)| QueuedEntry {
+                handle: key.to_owned(),
+                size: entry.size,
+                modified: entry.time,
+            })
+            .collect();
+        for entry in retention.select_purge(queued, max_age) {
+            self.purge_entry(state, &entry.handle);
+        }
+    }
+
+    fn purge_entry(self: &SegmentStorageProvider, state: &mut SegmentState, key: &str) {
+        if let Ok(purge) = self.append_marker(state, RECORD_PURGE, key) {
+            state.entries.apply(RECORD_PURGE, key.to_owned(), purge);
+        }
+    }
+
+    /// Compact the segments if the share of unused bytes is above `limit`.
+    fn compact_above(self: &SegmentStorageProvider, state: &mut SegmentState, limit: f64) {
+        let unused = state.total_bytes.saturating_sub(state.entries.used_bytes());
+        if unused > 0 && unused as f64 / state.total_bytes as f64 > limit {
+            let _result = self.compact_locked(state);
+        }
+    }
+}
+
+impl StorageProvider for SegmentStorageProvider {
+    fn get(self: &SegmentStorageProvider, key: &str) -> Result<GetData, String> {
+        OperationSpan::provider("get", &self.folder, key).entered().run(|span| {
+            let state = self.state.read().unwrap();
+            let entry = state.entries.live.get(key).ok_or_else(|| "Not found".to_owned())?;
+            if is_expired(entry.expires) {
+                return Err("Expired".to_owned());
+            }
+            let mut data = Vec::with_capacity(entry.size as usize);
+            self.open_data(entry)?.read_to_end(&mut data).map_err(|e| format!("Could not read segment: {}", e))?;
+            if data.len() as u64 != entry.size {
+                return Err("Segment is truncated".to_owned());
+            }
+            span.record_size(entry.size);
+            Ok(GetData {
+                key: key.to_owned(),
+                size: data.len(),
+                data,
+                metadata: entry.metadata.clone(),
+                etag: entry.etag(),
+            })
+        })
+    }
+
+    fn save(self: &SegmentStorageProvider, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
+        self.save_with_options(key, raw, &SaveOptions::default())
+    }
+
+    fn save_with_options(self: &SegmentStorageProvider, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<SaveData, String> {
+        OperationSpan::provider("save", &self.folder, key).entered().run(|span| {
+            let now = SystemTime::now();
+            let record = Record {
+                kind: RECORD_PUT,
+                key,
+                time: now,
+                expires: options.ttl.map(|ttl| now + ttl),
+                metadata: &options.metadata,
+            };
+            let mut state = self.state.write().unwrap();
+            let entry = self.append(&mut state, &record, &mut raw.as_slice(), raw.len() as u64)?;
+            state.entries.apply(RECORD_PUT, key.to_owned(), entry);
+            span.record_size(raw.len() as u64);
+            Ok(SaveData {
+                key: key.to_owned(),
+                size: raw.len(),
+                version: None,
+            })
+        })
+    }
+
+    fn get_reader(self: &SegmentStorageProvider, key: &str) -> Result<Box<dyn Read + '_>, String> {
+        OperationSpan::provider("get_reader", &self.folder, key).entered().run(|_span| {
+            let state = self.state.read().unwrap();
+            let entry = state.entries.live.get(key).filter(|entry| !is_expired(entry.expires)).ok_or_else(|| "Not found".to_owned())?;
+            let reader: Box<dyn Read + '_> = Box::new(BufReader::new(self.open_data(entry)?));
+            Ok(reader)
+        })
+    }
+
+    fn restore(self: &SegmentStorageProvider, key: &str) -> Result<(), StorageError> {
+        OperationSpan::provider("restore", &self.folder, key).entered().run(|_span| {
+            let mut state = self.state.write().unwrap();
+            if !state.entries.deleted.contains_key(key) {
+                return Err(StorageError::Provider("Not found in delete queue".to_owned()));
+            }
+            if state.entries.live.contains_key(key) {
+                return Err(StorageError::Conflict(key.to_owned()));
+            }
+            let restore = self.append_marker(&mut state, RECORD_RESTORE, key)?;
+            state.entries.apply(RECORD_RESTORE, key.to_owned(), restore);
+            Ok(())
+        })
+    }
+
+    fn delete(self: &SegmentStorageProvider, key: &str) {
+        OperationSpan::provider("delete", &self.folder, key).entered().run(|_span| {
+            let mut state = self.state.write().unwrap();
+            self.delete_locked(&mut state, key);
+        })
+    }
+
+    fn free(self: &SegmentStorageProvider) {
+        OperationSpan::provider("free", &self.folder, "").entered().run(|_span| {
+            let mut state = self.state.write().unwrap();
+            self.delete_expired(&mut state);
+            self.purge(&mut state, &self.config.retention, self.config.retention.max_age);
+            self.compact_above(&mut state, self.config.compact_ratio);
+        })
+    }
+
+    fn force_free(self: &SegmentStorageProvider, all: bool) {
+        OperationSpan::provider("force_free", &self.folder, "").entered().run(|_span| {
+            let mut state = self.state.write().unwrap();
+            self.delete_expired(&mut state);
+            if all {
+                let keys: Vec<String> = state.entries.deleted.keys().cloned().collect();
+                for key in keys {
+                    self.purge_entry(&mut state, &key);
+                }
+            } else {
+                self.purge(&mut state, &self.config.retention, self.config.retention.force_max_age);
+            }
+            self.compact_above(&mut state, 0.0);
+        })
+    }
+
+    fn free_older_than(self: &SegmentStorageProvider, age: Duration) {
+        OperationSpan::provider("free_older_than", &self.folder, "").entered().run(|_span| {
+            let retention = Retention {
+                max_age: Some(age),
+                force_max_age: None,
+                max_bytes: None,
+                max_entries: None,
+            };
+            let mut state = self.state.write().unwrap();
+            self.purge(&mut state, &retention, retention.max_age);
+            self.compact_above(&mut state, self.config.compact_ratio);
+        })
+    }
+
+    fn list(self: &SegmentStorageProvider, prefix: &str) -> Result<Vec<String>, String> {
+        OperationSpan::provider("list", &self.folder, prefix).entered().run(|_span| {
+            let state = self.state.read().unwrap();
+            Ok(state
+                .entries
+                .live
+                .range(prefix.to_owned()..)
+                .take_while(|(key, _entry)| key.starts_with(prefix))
+                .filter(|(_key, entry)| !is_expired(entry.expires))
+                .map(|(key, _entry)| key.to_owned())
+                .collect())
+        })
+    }
+
+    fn list_deleted(self: &SegmentStorageProvider) -> Result<Vec<(String, u64)>, String> {
+        OperationSpan::provider("list_deleted", &self.folder, "").entered().run(|_span| {
+            let state = self.state.read().unwrap();
+            Ok(state.entries.deleted.iter().map(|(key, entry)| (key.to_owned(), entry.size)).collect())
+        })
+    }
+
+    fn stat(self: &SegmentStorageProvider, key: &str) -> Result<StatData, String> {
+        OperationSpan::provider("stat", &self.folder, key).entered().run(|span| {
+            let state = self.state.read().unwrap();
+            let entry = state.entries.live.get(key).ok_or_else(|| "Not found".to_owned())?;
+            if is_expired(entry.expires) {
+                return Err("Expired".to_owned());
+            }
+            span.record_size(entry.size);
+            Ok(StatData {
+                key: key.to_owned(),
+                size: entry.size as usize,
+                modified: entry.time,
+                expires: entry.expires,
+                metadata: entry.metadata.clone(),
+                etag: entry.etag(),
+            })
+        })
+    }
+
+    fn usage(self: &SegmentStorageProvider) -> Result<StorageUsage, String> {
+        OperationSpan::provider("usage", &self.folder, "").entered().run(|_span| {
+            let state = self.state.read().unwrap();
+            let mut usage = StorageUsage::default();
+            for entry in state.entries.live.values().filter(|entry| !is_expired(entry.expires)) {
+                usage.bytes += entry.size;
+                usage.entries += 1;
+            }
+            Ok(usage)
+        })
+    }
+
+    fn capacity(self: &SegmentStorageProvider) -> Result<CapacityData, String> {
+        OperationSpan::provider("capacity", &self.folder, "").entered().run(|_span| {
+            Ok(CapacityData {
+                total_bytes: fs2::total_space(&self.folder).map_err(|e| format!("Could not read capacity: {}", e))?,
+                free_bytes: fs2::available_space(&self.folder).map_err(|e| format!("Could not read capacity: {}", e))?,
+            })
+        })
+    }
+}
+
+fn segment_path(folder: &str, id: u64) -> String {
+    format!("{}/{:08}.{}", folder, id, SEGMENT_EXTENSION)
+}
+
+/// IDs of all segments in the folder, sorted ascending.
+fn segment_ids(folder: &str) -> Result<Vec<u64>, String> {
+    let read_dir = fs::read_dir(folder).map_err(|e| format!("Could not read storage folder: {}", e))?;
+    let mut ids: Vec<u64> = read_dir
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_owned();
+            name.strip_suffix(&format!(".{}", SEGMENT_EXTENSION))?.parse().ok()
+        })
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Apply all complete records of a segment, returns the byte size of the complete records.
+fn replay_segment(path: &str, id: u64, entries: &mut Entries) -> Result<u64, String> {
+    let file = File::open(path).map_err(|e| format!("Could not open segment: {}", e))?;
+    let mut reader = BufReader::new(file);
+    let mut offset = 0;
+    while let Some((kind, key, entry)) = read_record(&mut reader, id, offset) {
+        offset += entry.record_size;
+        entries.apply(kind, key, entry);
+    }
+    Ok(offset)
+}
+
+/// Read the record at the current position, `None` at the end of the segment or if the record is incomplete or damaged.
+fn read_record(reader: &mut dyn Read, segment: u64, offset: u64) -> Option<(u8, String, Entry)> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    reader.read_exact(&mut header).ok()?;
+    let kind = header[0];
+    let time = u64::from_le_bytes(header[1..9].try_into().ok()?);
+    let expires = u64::from_le_bytes(header[9..17].try_into().ok()?);
+    let key_size = u32::from_le_bytes(header[17..21].try_into().ok()?) as u64;
+    let meta_size = u32::from_le_bytes(header[21..25].try_into().ok()?) as u64;
+    let size = u64::from_le_bytes(header[25..33].try_into().ok()?);
+    if !(RECORD_PUT..=RECORD_PURGE).contains(&kind) || key_size + meta_size > MAX_KEY_META_SIZE {
+        return None;
+    }
+    let mut key_meta = vec![0u8; (key_size + meta_size) as usize];
+    reader.read_exact(&mut key_meta).ok()?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header);
+    hasher.update(&key_meta);
+    copy_hashed(reader, &mut std::io::sink(), &mut hasher, size).ok()?;
+    let mut checksum = [0u8; TRAILER_SIZE as usize];
+    reader.read_exact(&mut checksum).ok()?;
+    if u32::from_le_bytes(checksum) != hasher.finalize() {
+        return None;
+    }
+    let key = String::from_utf8(key_meta[..key_size as usize].to_vec()).ok()?;
+    let metadata = sidecar::parse(std::str::from_utf8(&key_meta[key_size as usize..]).ok()?);
+    let entry = Entry {
+        segment,
+        offset,
+        record_size: HEADER_SIZE + key_size + meta_size + size + TRAILER_SIZE,
+        data_offset: offset + HEADER_SIZE + key_size + meta_size,
+        size,
+        time: from_millis(time).unwrap_or(UNIX_EPOCH),
+        expires: from_millis(expires),
+        metadata,
+    };
+    Some((kind, key, entry))
+}
+
+/// Copy `size` bytes from the reader into the writer and the hasher.
+fn copy_hashed(reader: &mut dyn Read, writer: &mut dyn Write, hasher: &mut crc32fast::Hasher, size: u64) -> std::io::Result<()> {
+    let mut buffer = vec![0u8; size.min(64 * 1024) as usize];
+    let mut remaining = size;
+    while remaining > 0 {
+        let chunk = remaining.min(buffer.len() as u64) as usize;
+        reader.read_exact(&mut buffer[..chunk])?;
+        hasher.update(&buffer[..chunk]);
+        writer.write_all(&buffer[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    Ok(())
+}
+
+/// Milliseconds since the unix epoch, `0` for `None`.
+fn millis(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn from_millis(millis: u64) -> Option<SystemTime> {
+    match millis {
+        0 => None,
+        millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+    }
+}
+
+fn is_expired(expires: Option<SystemTime>) -> bool {
+    matches!(expires, Some(expires) if expires <= SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::{Read, Write}, time::Duration};
+
+    use crate::{retention::Retention, Metadata, SaveOptions, StorageError, StorageProvider};
+
+    use super::{SegmentStorageConfig, SegmentStorageProvider};
+
+    const SEGMENT_STORAGE: &str = "test_sstore";
+    const FILE_KEY: &str = "1234";
+
+    fn clean_up(path: &str) {
+        let attr = std::fs::metadata(path).unwrap();
+        if attr.is_dir() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn save_get_delete() {
+        let path = format!("{}_{}", SEGMENT_STORAGE, "save_get_delete");
+
+        let storage = SegmentStorageProvider::new(path.to_owned()).unwrap();
+        let options = SaveOptions {
+            metadata: Metadata::from([("tag".to_owned(), "a".to_owned())]),
+            ..Default::default()
+        };
+        storage.save_with_options(FILE_KEY, "test".to_owned().into_bytes(), &options).unwrap();
+        storage.save("5678", "first".to_owned().into_bytes()).unwrap();
+        storage.save("5678", "second".to_owned().into_bytes()).unwrap();
+        let data = storage.get(FILE_KEY).unwrap();
+        assert_eq!((data.data, data.metadata.get("tag").map(|tag| tag.as_str())), ("test".to_owned().into_bytes(), Some("a")));
+        let mut content = String::new();
+        storage.get_reader("5678").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "second");
+        assert_eq!(storage.list("").unwrap(), vec![FILE_KEY.to_owned(), "5678".to_owned()]);
+
+        storage.delete(FILE_KEY);
+        assert!(storage.get(FILE_KEY).is_err());
+        assert_eq!(storage.list_deleted().unwrap(), vec![(FILE_KEY.to_owned(), 4)]);
+        storage.restore(FILE_KEY).unwrap();
+        assert_eq!(storage.get(FILE_KEY).unwrap().size, 4);
+        storage.delete("5678");
+        storage.save("5678", "third".to_owned().into_bytes()).unwrap();
+        assert_eq!(storage.restore("5678"), Err(StorageError::Conflict("5678".to_owned())));
+        assert_eq!(storage.get("5678").unwrap().data, "third".to_owned().into_bytes());
+        storage.delete("5678");
+        drop(storage);
+
+        // the index is rebuilt from the segments
+        let storage = SegmentStorageProvider::new(path.to_owned()).unwrap();
+        assert_eq!(storage.list("").unwrap(), vec![FILE_KEY.to_owned()]);
+        assert_eq!(storage.stat(FILE_KEY).unwrap().metadata.get("tag").map(|tag| tag.as_str()), Some("a"));
+        assert_eq!(storage.list_deleted().unwrap(), vec![("5678".to_owned(), 5)]);
+        clean_up(&path);
+    }
+
+    #[test]
+    fn incomplete_record() {
+        let path = format!("{}_{}", SEGMENT_STORAGE, "incomplete_record");
+
+        let storage = SegmentStorageProvider::new(path.to_owned()).unwrap();
+        storage.save(FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        drop(storage);
+        let mut segment = OpenOptions::new().append(true).open(format!("{}/00000001.seg", path)).unwrap();
+        segment.write_all(&[1, 0, 0, 0]).unwrap();
+        drop(segment);
+
+        let storage = SegmentStorageProvider::new(path.to_owned()).unwrap();
+        storage.save("5678", "test".to_owned().into_bytes()).unwrap();
+        drop(storage);
+        let storage = SegmentStorageProvider::new(path.to_owned()).unwrap();
+        assert_eq!(storage.list("").unwrap(), vec![FILE_KEY.to_owned(), "5678".to_owned()]);
+        clean_up(&path);
+    }
+
+    #[test]
+    fn compaction() {
+        let path = format!("{}_{}", SEGMENT_STORAGE, "compaction");
+
+        let config = SegmentStorageConfig {
+            retention: Retention {
+                max_age: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            max_segment_bytes: 100,
+            ..Default::default()
+        };
+        let storage = SegmentStorageProvider::with_config(path.to_owned(), config.clone()).unwrap();
+        for index in 0..10 {
+            storage.save(&format!("key{}", index), vec![index as u8; 40]).unwrap();
+        }
+        assert_eq!(storage.segment_count().unwrap(), 10);
+        for index in 0..8 {
+            storage.delete(&format!("key{}", index));
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        storage.free();
+        assert!(storage.list_deleted().unwrap().is_empty());
+        assert_eq!(storage.segment_count().unwrap(), 2);
+        assert_eq!(storage.get("key9").unwrap().data, vec![9; 40]);
+        drop(storage);
+
+        let storage = SegmentStorageProvider::with_config(path.to_owned(), config).unwrap();
+        assert_eq!(storage.list("").unwrap(), vec!["key8".to_owned(), "key9".to_owned()]);
+        assert_eq!(storage.get("key8").unwrap().data, vec![8; 40]);
+        clean_up(&path);
+    }
+}
@@ -0,0 +1,102 @@
+//! Simple `name=value` text format used for entry metadata files.
+//!
+//! Every field is written on its own line, `\`, `=`, tabs and line breaks in names and values are escaped.
+
+use std::{collections::BTreeMap, fs, io::ErrorKind};
+
+pub(crate) type Fields = BTreeMap<String, String>;
+
+/// Read all fields of a sidecar file, a missing file has no fields.
+pub(crate) fn read(path: &str) -> Result<Fields, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse(&content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Fields::new()),
+        Err(e) => Err(format!("Could not read metadata: {}", e)),
+    }
+}
+
+/// Write all fields to a sidecar file, the file is removed if there are no fields.
+pub(crate) fn write(path: &str, fields: &Fields) -> Result<(), String> {
+    if fields.is_empty() {
+        return remove(path);
+    }
+    fs::write(path, format(fields)).map_err(|e| format!("Could not write metadata: {}", e))
+}
+
+/// Remove a sidecar file, a missing file is not an error.
+pub(crate) fn remove(path: &str) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Could not remove metadata: {}", e)),
+    }
+}
+
+pub(crate) fn format(fields: &Fields) -> String {
+    let mut content = String::new();
+    for (name, value) in fields.iter() {
+        content.push_str(&escape(name));
+        content.push('=');
+        content.push_str(&escape(value));
+        content.push('\n');
+    }
+    content
+}
+
+pub(crate) fn parse(content: &str) -> Fields {
+    let mut fields = Fields::new();
+    for line in content.lines() {
+        if let Some((name, value)) = line.split_once('=') {
+            fields.insert(unescape(name), unescape(value));
+        }
+    }
+    fields
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '=' => escaped.push_str("\\e"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('e') => unescaped.push('='),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse, Fields};
+
+    #[test]
+    fn format_and_parse() {
+        let mut fields = Fields::new();
+        fields.insert("a=b".to_owned(), "line\nbreak \\ end".to_owned());
+        fields.insert("empty".to_owned(), "".to_owned());
+        assert_eq!(parse(&format(&fields)), fields);
+    }
+}
@@ -1,6 +1,37 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    sync::{mpsc::Receiver, Arc},
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::{StorageProvider, GetData, SaveData};
+use dispnet_shared::Package;
+
+use crate::{
+    archive::{ArchiveEntry, ArchiveReader, ArchiveWriter, ImportMode, ImportReport},
+    assembly::{self, ChecksumValidation, PackageReader},
+    backup::{self, BackupId, BackupReport, ManifestRecord},
+    checksum::{crc32_reader, ChecksumReader},
+    events::{EventFeed, StorageEvent, StorageEventKind},
+    capacity::{CapacityLevel, CapacityMonitor, Watermarks},
+    health::{CircuitBreaker, CircuitBreakerConfig, LayerHealth},
+    metrics::{MeasuredOutcome, Outcome, StorageMetrics, Transfer},
+    quota::{Quota, QuotaTracker, QuotaUsage},
+    trace::{OperationSpan, TraceOutcome},
+    transaction::Transaction,
+    CapacityData, ChangeKind, ExternalChange, GetData, Metadata, SaveData, SaveOptions, SnapshotData, SnapshotId, StatData, StorageError, StorageProvider, VersionData, META_ORIGIN_CLIENT,
+};
+
+/// Result for every key of a bulk operation.
+pub type BulkResult = Vec<(String, Result<SaveData, StorageError>)>;
+
+/// Selects the entries of a layer for bulk operations.
+pub enum EntryFilter {
+    /// All entries with a key which starts with the prefix.
+    Prefix(String),
+    /// All entries for which the predicate returns `true`.
+    Predicate(fn(stat: &StatData) -> bool),
+}
 
 /// Manage all storage providers.
 /// 
@@ -12,17 +43,39 @@ use crate::{StorageProvider, GetData, SaveData};
 /// ```
 pub struct StorageManager {
     storage_providers:  HashMap<String, Box<dyn StorageProvider>>,
+    pub(crate) quotas: QuotaTracker,
+    pub(crate) events: EventFeed,
+    breaker: CircuitBreaker,
+    capacity: CapacityMonitor,
+    metrics: Option<Arc<dyn StorageMetrics>>,
 }
 
 impl StorageManager {
     pub fn new() -> Self {
         Self {
             storage_providers: HashMap::new(),
+            quotas: QuotaTracker::default(),
+            events: EventFeed::default(),
+            breaker: CircuitBreaker::default(),
+            capacity: CapacityMonitor::default(),
+            metrics: None,
         }
     }
 
     /// Add a storage provider instance to the manager
     pub fn add_storage_provider(self: &mut Self, layer_key: String, provider: Box<dyn StorageProvider>) {
+        // the owner clients are persisted in the metadata, so the client usage survives a restart
+        let owners = provider
+            .list("")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|key| provider.stat(&key).ok())
+            .filter_map(|stat| {
+                let client = stat.metadata.get(META_ORIGIN_CLIENT)?.to_owned();
+                Some((stat.key, client, stat.size as u64))
+            })
+            .collect();
+        self.quotas.track_layer(&layer_key, provider.usage().unwrap_or_default(), owners);
         self.storage_providers.insert(layer_key, provider);
     }
 
@@ -30,6 +83,9 @@ impl StorageManager {
     pub fn remove_storage_provider(self: &mut Self, layer_key: &str) {
         if self.storage_providers.contains_key(layer_key) {
             self.storage_providers.remove(layer_key);
+            self.quotas.remove_layer(layer_key);
+            self.breaker.remove_layer(layer_key);
+            self.capacity.set_watermarks(layer_key, None);
         }
     }
 
@@ -44,66 +100,1209 @@ impl StorageManager {
         keys
     }
 
+    /// Set the quota of a layer, `None` removes the quota.
+    pub fn set_layer_quota(self: &Self, layer_key: &str, quota: Option<Quota>) {
+        self.quotas.set_layer_quota(layer_key, quota);
+    }
+
+    /// Set the quota of a client on a layer, `None` removes the quota.
+    /// 
+    /// Only entries saved with `save_for_client` count towards the client quota.
+    pub fn set_client_quota(self: &Self, layer_key: &str, client: &str, quota: Option<Quota>) {
+        self.quotas.set_client_quota(layer_key, client, quota);
+    }
+
+    /// Get the current usage and the quota of a layer.
+    pub fn get_layer_usage(self: &Self, layer_key: &str) -> Result<QuotaUsage, StorageError> {
+        self.quotas.layer_usage(layer_key).ok_or_else(|| StorageError::LayerNotFound(layer_key.to_owned()))
+    }
+
+    /// Get the current usage and the quota of a client on a layer.
+    pub fn get_client_usage(self: &Self, layer_key: &str, client: &str) -> Result<QuotaUsage, StorageError> {
+        self.quotas.client_usage(layer_key, client).ok_or_else(|| StorageError::LayerNotFound(layer_key.to_owned()))
+    }
+
+    /// Record the metrics of all storage operations.
+    pub fn set_metrics(self: &mut Self, metrics: Arc<dyn StorageMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Set or remove the free space watermarks of a layer.
+    /// 
+    /// Below the low watermark the delete queue of the layer is purged with `force_free`, below the critical watermark the whole
+    /// delete queue is purged and saves are refused with `StorageError::InsufficientSpace`. An event is emitted whenever the layer crosses a watermark.
+    pub fn set_watermarks(self: &Self, layer_key: &str, watermarks: Option<Watermarks>) {
+        self.capacity.set_watermarks(layer_key, watermarks);
+    }
+
+    /// Get the size and the free space of the storage of a layer.
+    pub fn capacity(self: &Self, layer_key: &str) -> Result<CapacityData, StorageError> {
+        self.measure("capacity", layer_key, "", |_span| Ok(self.provider(layer_key)?.capacity()?))
+    }
+
+    /// Check the free space of all layers with watermarks, returns the level of every checked layer sorted by layer.
+    /// 
+    /// Saves check the free space of their layer, call this periodically to purge layers which are only read.
+    pub fn check_capacity(self: &Self) -> Vec<(String, CapacityLevel)> {
+        let mut levels: Vec<(String, CapacityLevel)> = self
+            .storage_providers
+            .iter()
+            .filter(|layer| self.capacity.watermarks(layer.0).is_some())
+            .map(|layer| (layer.0.to_owned(), self.check_layer_capacity(layer.0, layer.1.as_ref())))
+            .collect();
+        levels.sort_by(|a, b| a.0.cmp(&b.0));
+        levels
+    }
+
+    /// Configure when layers are degraded after failures and when they are tried again.
+    pub fn set_circuit_breaker(self: &Self, config: CircuitBreakerConfig) {
+        self.breaker.set_config(config);
+    }
+
+    /// Set or remove the layer which receives the saves for a degraded layer.
+    /// 
+    /// Entries saved to the failover layer are found with `find`. Conditional and batch saves, copies, imports, restores, package assemblies
+    /// and transactions do not fail over, they return `StorageError::Unavailable` while the layer is degraded.
+    pub fn set_failover_layer(self: &Self, layer_key: &str, failover_layer_key: Option<&str>) {
+        self.breaker.set_failover(layer_key, failover_layer_key.map(|layer| layer.to_owned()));
+    }
+
+    /// Check the health of all layers, the layers are sorted by name.
+    /// 
+    /// A layer which is not writable or has a high error rate is degraded, a degraded layer which passes the check recovers.
+    pub fn health_report(self: &Self) -> Vec<LayerHealth> {
+        let mut layers: Vec<&String> = self.storage_providers.keys().collect();
+        layers.sort();
+        layers
+            .into_iter()
+            .map(|layer| {
+                let health = self.storage_providers[layer].health().ok();
+                if let Some(health) = &health {
+                    self.breaker.checked(layer, health);
+                }
+                let (status, consecutive_failures) = self.breaker.status(layer);
+                LayerHealth {
+                    layer: layer.to_owned(),
+                    status,
+                    consecutive_failures,
+                    provider: health,
+                }
+            })
+            .collect()
+    }
+
+    /// Receive all events of the manager, the channel is removed when the receiver is dropped.
+    pub fn subscribe(self: &Self) -> Receiver<StorageEvent> {
+        self.events.subscribe()
+    }
+
+    /// Call the callback for all events of the manager.
+    pub fn on_event(self: &Self, callback: impl Fn(&StorageEvent) + Send + Sync + 'static) {
+        self.events.on_event(Arc::new(callback));
+    }
+
+    /// Append all events to a log file, so consumers can resume with `events_since` after a restart.
+    /// 
+    /// Every event is synced to disk before it is delivered. The sequence numbers continue after the last event in the log,
+    /// events which were emitted before the log was enabled are not logged.
+    pub fn enable_event_log(self: &Self, path: &str) -> Result<(), StorageError> {
+        Ok(self.events.open_log(path)?)
+    }
+
+    /// All logged events with a sequence number greater than `sequence`.
+    pub fn events_since(self: &Self, sequence: u64) -> Result<Vec<StorageEvent>, StorageError> {
+        Ok(self.events.events_since(sequence)?)
+    }
+
+    /// Sequence number of the last event.
+    pub fn last_event_sequence(self: &Self) -> u64 {
+        self.events.last_sequence()
+    }
+
     /// Get data from a storage layer with a key.
-    pub fn get(self: &Self, layer_key: &str, key: &str) -> Result<GetData, String> {
-        if self.storage_providers.contains_key(layer_key) {
-            return self.storage_providers.get(layer_key).unwrap().get(key);
-        }
-        Err("Storage provider layer not found for `get` action".to_owned())
+    pub fn get(self: &Self, layer_key: &str, key: &str) -> Result<GetData, StorageError> {
+        let provider = self.provider(layer_key)?;
+        self.get_measured(layer_key, provider, key)
+    }
+
+    /// Get the size, expire time and metadata of an entry in a storage layer.
+    pub fn stat(self: &Self, layer_key: &str, key: &str) -> Result<StatData, StorageError> {
+        self.measure("stat", layer_key, key, |_span| {
+            Ok(self.provider(layer_key)?.stat(key)?)
+        })
+    }
+
+    /// Get the data of a version of an entry in a storage layer.
+    pub fn get_version(self: &Self, layer_key: &str, key: &str, version: &str) -> Result<GetData, StorageError> {
+        self.measure("get_version", layer_key, key, |_span| {
+            Ok(self.provider(layer_key)?.get_version(key, version)?)
+        })
+    }
+
+    /// List the kept versions of an entry in a storage layer, the oldest version first.
+    pub fn list_versions(self: &Self, layer_key: &str, key: &str) -> Result<Vec<VersionData>, StorageError> {
+        self.measure("list_versions", layer_key, key, |_span| {
+            Ok(self.provider(layer_key)?.list_versions(key)?)
+        })
+    }
+
+    /// Capture a read-only view of all entries in a storage layer, e.g. before a risky migration.
+    pub fn snapshot(self: &Self, layer_key: &str) -> Result<SnapshotId, StorageError> {
+        self.measure("snapshot", layer_key, "", |_span| {
+            Ok(self.provider(layer_key)?.create_snapshot()?)
+        })
+    }
+
+    /// List all snapshots of a storage layer, the oldest snapshot first.
+    pub fn list_snapshots(self: &Self, layer_key: &str) -> Result<Vec<SnapshotData>, StorageError> {
+        self.measure("list_snapshots", layer_key, "", |_span| {
+            Ok(self.provider(layer_key)?.list_snapshots()?)
+        })
+    }
+
+    /// Get the data of an entry at the time of the snapshot.
+    pub fn get_snapshot_entry(self: &Self, layer_key: &str, snapshot: &str, key: &str) -> Result<GetData, StorageError> {
+        self.measure("get_snapshot_entry", layer_key, key, |_span| {
+            Ok(self.provider(layer_key)?.get_snapshot_entry(snapshot, key)?)
+        })
+    }
+
+    /// List the keys of all entries in the snapshot which start with the prefix.
+    pub fn list_snapshot_entries(self: &Self, layer_key: &str, snapshot: &str, prefix: &str) -> Result<Vec<String>, StorageError> {
+        self.measure("list_snapshot_entries", layer_key, prefix, |_span| {
+            Ok(self.provider(layer_key)?.list_snapshot_entries(snapshot, prefix)?)
+        })
+    }
+
+    /// Remove a snapshot of a storage layer.
+    pub fn delete_snapshot(self: &Self, layer_key: &str, snapshot: &str) -> Result<(), StorageError> {
+        self.measure("delete_snapshot", layer_key, snapshot, |_span| {
+            Ok(self.provider(layer_key)?.delete_snapshot(snapshot)?)
+        })
+    }
+
+    /// List the keys of all entries in a storage layer which start with the prefix.
+    pub fn list(self: &Self, layer_key: &str, prefix: &str) -> Result<Vec<String>, StorageError> {
+        self.measure("list", layer_key, prefix, |_span| {
+            Ok(self.provider(layer_key)?.list(prefix)?)
+        })
+    }
+
+    /// List the keys of all entries in a storage layer which start with the prefix and have all the `tags` in their metadata.
+    pub fn list_tagged(self: &Self, layer_key: &str, prefix: &str, tags: &Metadata) -> Result<Vec<String>, StorageError> {
+        self.measure("list_tagged", layer_key, prefix, |_span| {
+            Ok(self.provider(layer_key)?.list_tagged(prefix, tags)?)
+        })
     }
 
     /// Find the first data entry for the key in any storage provider.
-    pub fn find(self: &Self, key: &str) -> Result<GetData, String> {
-        for layer in self.storage_providers.iter() {
-            if let Ok(result) = layer.1.get(key) {
+    pub fn find(self: &Self, key: &str) -> Result<GetData, StorageError> {
+        // degraded layers are skipped until they recover
+        for layer in self.storage_providers.iter().filter(|layer| self.breaker.allows(layer.0)) {
+            if let Ok(result) = self.get_measured(layer.0, layer.1.as_ref(), key) {
                 return Ok(result);
             }
         }
-        Err(format!("Requested key: `{}` not found in any storage provider.", key))
+        Err(StorageError::NotFound(key.to_owned()))
     }
 
     /// Save data to the storage layer.
-    pub fn save(self: &Self, layer_key: &str, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
-        if self.storage_providers.contains_key(layer_key) {
-            return self.storage_providers.get(layer_key).unwrap().save(key, raw);
-        }
-        Err("Storage provider layer not found for `save` action".to_owned())
+    /// 
+    /// Returns `StorageError::QuotaExceeded` if the save would exceed the layer quota.
+    pub fn save(self: &Self, layer_key: &str, key: &str, raw: Vec<u8>) -> Result<SaveData, StorageError> {
+        self.save_internal(layer_key, None, key, raw, &SaveOptions::default())
+    }
+
+    /// Save data with additional options like a time to live to the storage layer.
+    pub fn save_with_options(self: &Self, layer_key: &str, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<SaveData, StorageError> {
+        self.save_internal(layer_key, None, key, raw, options)
+    }
+
+    /// Save data to the storage layer only if the key does not exist.
+    /// 
+    /// Returns `StorageError::Conflict` if the key exists.
+    pub fn save_if_absent(self: &Self, layer_key: &str, key: &str, raw: Vec<u8>) -> Result<SaveData, StorageError> {
+        self.measure("save_if_absent", layer_key, key, |span| {
+            span.record_size(raw.len() as u64);
+            let provider = self.writable_provider(layer_key)?;
+            self.ensure_capacity(layer_key, provider)?;
+            let reservation = self.quotas.reserve(layer_key, None, key, None, raw.len() as u64)?;
+            let result = provider.save_if_absent(key, raw, &SaveOptions::default());
+            self.track_write(layer_key, &result);
+            match result {
+                Ok(result) => {
+                    self.record_bytes(layer_key, Transfer::Written, result.size as u64);
+                    self.events.emit(StorageEventKind::Saved, layer_key, key, result.size as u64);
+                    Ok(result)
+                }
+                Err(err) => {
+                    self.quotas.release(reservation);
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    /// Save data to the storage layer only if the entry still has the `etag` returned by `get` or `stat`.
+    /// 
+    /// Returns `StorageError::PreconditionFailed` if the entry was changed or removed.
+    pub fn save_if_match(self: &Self, layer_key: &str, key: &str, raw: Vec<u8>, etag: &str) -> Result<SaveData, StorageError> {
+        self.measure("save_if_match", layer_key, key, |span| {
+            span.record_size(raw.len() as u64);
+            let provider = self.writable_provider(layer_key)?;
+            self.ensure_capacity(layer_key, provider)?;
+            let previous_size = provider.stat(key).ok().map(|stat| stat.size as u64);
+            let reservation = self.quotas.reserve(layer_key, None, key, previous_size, raw.len() as u64)?;
+            let result = provider.save_if_match(key, raw, etag, &SaveOptions::default());
+            self.track_write(layer_key, &result);
+            match result {
+                Ok(result) => {
+                    self.record_bytes(layer_key, Transfer::Written, result.size as u64);
+                    self.events.emit(StorageEventKind::Saved, layer_key, key, result.size as u64);
+                    Ok(result)
+                }
+                Err(err) => {
+                    self.quotas.release(reservation);
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    /// Set or remove the time to live of an entry in the storage layer.
+    pub fn set_ttl(self: &Self, layer_key: &str, key: &str, ttl: Option<Duration>) -> Result<(), StorageError> {
+        self.measure("set_ttl", layer_key, key, |_span| {
+            Ok(self.provider(layer_key)?.set_ttl(key, ttl)?)
+        })
+    }
+
+    /// Save data to the storage layer on behalf of a client, the client is stored as `origin_client` in the metadata.
+    /// 
+    /// Returns `StorageError::QuotaExceeded` if the save would exceed the layer or the client quota.
+    pub fn save_for_client(self: &Self, layer_key: &str, client: &str, key: &str, raw: Vec<u8>) -> Result<SaveData, StorageError> {
+        let mut options = SaveOptions::default();
+        options.metadata.insert(META_ORIGIN_CLIENT.to_owned(), client.to_owned());
+        self.save_internal(layer_key, Some(client), key, raw, &options)
+    }
+
+    /// Get multiple entries from a storage layer, the results are in the order of the keys.
+    pub fn get_many(self: &Self, layer_key: &str, keys: &[&str]) -> Result<Vec<Result<GetData, StorageError>>, StorageError> {
+        self.measure("get_many", layer_key, "", |_span| {
+            let provider = self.provider(layer_key)?;
+            let results = provider.get_many(keys);
+            for data in results.iter().flatten() {
+                self.record_bytes(layer_key, Transfer::Read, data.size as u64);
+            }
+            Ok(results.into_iter().map(|result| result.map_err(StorageError::Provider)).collect())
+        })
+    }
+
+    /// Save multiple entries to a storage layer, the results are in the order of the entries.
+    /// 
+    /// Entries which would exceed the layer quota are rejected with `StorageError::QuotaExceeded`.
+    pub fn save_many(self: &Self, layer_key: &str, entries: Vec<(String, Vec<u8>)>) -> Result<Vec<Result<SaveData, StorageError>>, StorageError> {
+        self.measure("save_many", layer_key, "", |_span| {
+            let provider = self.writable_provider(layer_key)?;
+            self.ensure_capacity(layer_key, provider)?;
+            let mut results: Vec<Option<Result<SaveData, StorageError>>> = vec![];
+            let mut reservations = vec![];
+            let mut accepted = vec![];
+            for (key, raw) in entries {
+                let previous_size = provider.stat(&key).ok().map(|stat| stat.size as u64);
+                match self.quotas.reserve(layer_key, None, &key, previous_size, raw.len() as u64) {
+                    Ok(reservation) => {
+                        results.push(None);
+                        reservations.push(reservation);
+                        accepted.push((key, raw));
+                    }
+                    Err(err) => results.push(Some(Err(err))),
+                }
+            }
+            let mut saved = provider.save_many(accepted).into_iter().zip(reservations);
+            Ok(results
+                .into_iter()
+                .map(|result| match result {
+                    Some(result) => result,
+                    None => {
+                        let (result, reservation) = saved.next().expect("Provider must return a result for every entry");
+                        let result = result.map_err(StorageError::Provider);
+                        self.track_write(layer_key, &result);
+                        match &result {
+                            Ok(saved) => {
+                                self.record_bytes(layer_key, Transfer::Written, saved.size as u64);
+                                self.events.emit(StorageEventKind::Saved, layer_key, &saved.key, saved.size as u64);
+                            }
+                            Err(_) => self.quotas.release(reservation),
+                        }
+                        result
+                    }
+                })
+                .collect())
+        })
+    }
+
+    /// Queue multiple entries for deletion in a specific layer, the results are in the order of the keys.
+    /// 
+    /// Returns `StorageError::NotFound` for keys which do not exist in the layer.
+    pub fn delete_many(self: &Self, layer_key: &str, keys: &[&str]) -> Result<Vec<Result<(), StorageError>>, StorageError> {
+        self.measure("delete_many", layer_key, "", |_span| {
+            let provider = self.provider(layer_key)?;
+            let before: Vec<Option<u64>> = keys.iter().map(|key| provider.stat(key).ok().map(|stat| stat.size as u64)).collect();
+            provider.delete_many(keys);
+            Ok(keys
+                .iter()
+                .zip(before)
+                .map(|(key, size)| match size {
+                    None => Err(StorageError::NotFound(key.to_string())),
+                    Some(_) if provider.stat(key).is_ok() => Err(StorageError::Provider(format!("Could not delete `{}`", key))),
+                    Some(size) => {
+                        self.quotas.removed(layer_key, key, size);
+                        self.events.emit(StorageEventKind::Deleted, layer_key, key, size);
+                        Ok(())
+                    }
+                })
+                .collect())
+        })
     }
 
     /// Queue an entry for deletion in a specific layer.
     pub fn delete(self: &Self, layer_key: &str, key: &str) {
-        if self.storage_providers.contains_key(layer_key) {
-            return self.storage_providers.get(layer_key).unwrap().delete(key);
+        if let Some(provider) = self.storage_providers.get(layer_key) {
+            self.delete_internal(layer_key, provider.as_ref(), key);
         }
     }
 
     /// Queue for deletion all entires which match the key on any layer.
     pub fn delete_all(self: &Self, key: &str) {
         for layer in self.storage_providers.iter() {
-            layer.1.delete(key)
+            self.delete_internal(layer.0, layer.1.as_ref(), key);
         }
     }
 
     /// Execute free on all layers.
     pub fn free(self: &Self) {
         for layer in self.storage_providers.iter() {
-            layer.1.free();
+            self.free_layer(layer.0, layer.1.as_ref(), |provider| provider.free());
         }
     }
 
     /// Executes force free on all layers.
     pub fn force_free(self: &Self, all: bool) {
         for layer in self.storage_providers.iter() {
-            layer.1.force_free(all);
+            self.free_layer(layer.0, layer.1.as_ref(), |provider| provider.force_free(all));
+        }
+    }
+
+    /// Purge all entries in the delete queues which are older than `age` on all layers, layers whose provider does not track the age are not purged.
+    pub fn free_older_than(self: &Self, age: Duration) {
+        for layer in self.storage_providers.iter() {
+            self.free_layer(layer.0, layer.1.as_ref(), |provider| provider.free_older_than(age));
+        }
+    }
+
+    /// Detect changes which were made outside of the storage providers, e.g. files copied into a storage folder.
+    /// 
+    /// The usage of the changed layers is refreshed and an event is emitted for every change. Returns the changes with their layer.
+    pub fn poll_external_changes(self: &Self) -> Result<Vec<(String, ExternalChange)>, StorageError> {
+        OperationSpan::layer("poll_external_changes", "", "").run(|_span| {
+            let mut changes = vec![];
+            for layer in self.storage_providers.iter() {
+                let layer_changes = layer.1.poll_changes()?;
+                if layer_changes.is_empty() {
+                    continue;
+                }
+                self.refresh_usage(layer.0, layer.1.as_ref());
+                for change in layer_changes {
+                    let kind = match change.kind {
+                        ChangeKind::Added | ChangeKind::Modified => StorageEventKind::Saved,
+                        ChangeKind::Removed => StorageEventKind::Deleted,
+                    };
+                    self.events.emit(kind, layer.0, &change.key, change.size);
+                    changes.push((layer.0.to_owned(), change));
+                }
+            }
+            Ok(changes)
+        })
+    }
+
+    /// Restore an entry of a layer which is queued for deletion.
+    pub fn restore(self: &Self, layer_key: &str, key: &str) -> Result<(), StorageError> {
+        self.measure("restore", layer_key, key, |_span| {
+            let provider = self.provider(layer_key)?;
+            provider.restore(key)?;
+            self.refresh_usage(layer_key, provider);
+            let size = provider.stat(key).map(|stat| stat.size as u64).unwrap_or(0);
+            self.events.emit(StorageEventKind::Restored, layer_key, key, size);
+            Ok(())
+        })
+    }
+
+    /// Change the key of an entry in a storage layer.
+    /// 
+    /// Returns `StorageError::Conflict` if `new_key` exists and `overwrite` is `false`.
+    pub fn rename(self: &Self, layer_key: &str, old_key: &str, new_key: &str, overwrite: bool) -> Result<(), StorageError> {
+        self.measure("rename", layer_key, old_key, |_span| {
+            let provider = self.provider(layer_key)?;
+            let overwritten_size = if old_key == new_key {
+                None
+            } else {
+                provider.stat(new_key).ok().map(|stat| stat.size as u64)
+            };
+            provider.rename(old_key, new_key, overwrite)?;
+            self.quotas.renamed(layer_key, old_key, new_key, overwritten_size);
+            if old_key != new_key {
+                let size = provider.stat(new_key).map(|stat| stat.size as u64).unwrap_or(0);
+                self.events.emit(StorageEventKind::Deleted, layer_key, old_key, size);
+                self.events.emit(StorageEventKind::Saved, layer_key, new_key, size);
+            }
+            Ok(())
+        })
+    }
+
+    /// Copy an entry with its metadata and remaining time to live from one layer to another.
+    /// 
+    /// The copy is streamed and verified with a checksum, on a mismatch the copy is removed and `StorageError::ChecksumMismatch` is returned.
+    pub fn copy(self: &Self, src_layer_key: &str, dst_layer_key: &str, key: &str) -> Result<SaveData, StorageError> {
+        self.measure("copy", src_layer_key, key, |span| self.copy_entry(src_layer_key, dst_layer_key, key, span))
+    }
+
+    fn copy_entry(self: &Self, src_layer_key: &str, dst_layer_key: &str, key: &str, span: &OperationSpan) -> Result<SaveData, StorageError> {
+        let src = self.provider(src_layer_key)?;
+        let dst = self.writable_provider(dst_layer_key)?;
+        if src_layer_key == dst_layer_key {
+            return Err(StorageError::Provider("Source and destination layer must be different".to_owned()));
+        }
+        self.ensure_capacity(dst_layer_key, dst)?;
+        let stat = src.stat(key)?;
+        span.record_size(stat.size as u64);
+        let options = SaveOptions {
+            ttl: stat.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO)),
+            metadata: stat.metadata,
+        };
+        let previous_size = dst.stat(key).ok().map(|stat| stat.size as u64);
+        let reservation = self.quotas.reserve(dst_layer_key, None, key, previous_size, stat.size as u64)?;
+
+        // the copy is verified before it replaces the entry in the destination
+        let mut reader = ChecksumReader::new(src.get_reader(key)?);
+        let written = Unverified::write(dst, key, &mut reader, &options);
+        let source_checksum = reader.finish();
+        let result = match written {
+            Ok(unverified) => {
+                let copy_checksum = unverified.reader(dst).and_then(|mut copy| crc32_reader(&mut copy).map_err(|e| e.to_string()));
+                if copy_checksum.as_ref() == Ok(&source_checksum) {
+                    unverified.commit(dst, key, &options)
+                } else {
+                    unverified.discard(dst);
+                    Err(StorageError::ChecksumMismatch(key.to_owned()))
+                }
+            }
+            Err(err) => Err(StorageError::Provider(err)),
+        };
+        self.track_write(dst_layer_key, &result);
+        if let Err(err) = result {
+            self.quotas.release(reservation);
+            return Err(err);
         }
+        self.record_bytes(src_layer_key, Transfer::Read, source_checksum.1);
+        self.record_bytes(dst_layer_key, Transfer::Written, source_checksum.1);
+        self.events.emit(StorageEventKind::Saved, dst_layer_key, key, source_checksum.1);
+        Ok(SaveData {
+            key: key.to_owned(),
+            size: source_checksum.1 as usize,
+            version: None,
+        })
+    }
+
+    /// Write all entries of a layer with their metadata and checksums into a portable archive, returns the count of exported entries.
+    /// 
+    /// Entries which are changed during the export can be missing in the archive, export a snapshot for a consistent archive.
+    pub fn export(self: &Self, layer_key: &str, writer: &mut dyn Write) -> Result<u64, StorageError> {
+        self.measure("export", layer_key, "", |_span| {
+            let provider = self.provider(layer_key)?;
+            let mut archive = ArchiveWriter::new(writer).map_err(|e| StorageError::Provider(format!("Could not write archive: {}", e)))?;
+            let mut entries = 0;
+            for key in provider.list("")? {
+                // the entry was deleted or expired after it was listed
+                let stat = match provider.stat(&key) {
+                    Ok(stat) => stat,
+                    Err(_) => continue,
+                };
+                let entry = ArchiveEntry {
+                    key,
+                    size: stat.size as u64,
+                    expires: stat.expires,
+                    metadata: stat.metadata,
+                };
+                let mut reader = provider.get_reader(&entry.key)?;
+                archive.add(&entry, &mut reader).map_err(|e| StorageError::Provider(format!("Could not write archive: {}", e)))?;
+                self.record_bytes(layer_key, Transfer::Read, entry.size);
+                entries += 1;
+            }
+            archive.finish().map_err(|e| StorageError::Provider(format!("Could not write archive: {}", e)))?;
+            Ok(entries)
+        })
     }
+
+    /// Import all entries of an archive into a layer.
+    /// 
+    /// Entries whose checksum does not match the archive are removed and reported as failed, after the import all imported entries are read again and verified.
+    /// Returns `Err` if the archive can not be read, entries imported before the error are kept.
+    pub fn import(self: &Self, layer_key: &str, reader: &mut dyn Read, mode: ImportMode) -> Result<ImportReport, StorageError> {
+        self.measure("import", layer_key, "", |_span| {
+            let provider = self.writable_provider(layer_key)?;
+            let archive_error = |e: std::io::Error| StorageError::Provider(format!("Could not read archive: {}", e));
+            let mut archive = ArchiveReader::new(reader).map_err(archive_error)?;
+            let mut report = ImportReport::default();
+            let mut imported = vec![];
+            while let Some(entry) = archive.next_entry().map_err(archive_error)? {
+                // keys are used as file names, a key must not leave the folder of the layer or hit a hidden folder
+                if !is_valid_key(&entry.key) {
+                    let mut data = archive.entry_data(&entry);
+                    std::io::copy(&mut data, &mut std::io::sink()).map_err(archive_error)?;
+                    archive.read_checksum().map_err(archive_error)?;
+                    report.failed.push((entry.key.to_owned(), StorageError::InvalidKey(entry.key)));
+                    continue;
+                }
+                let previous_size = provider.stat(&entry.key).ok().map(|stat| stat.size as u64);
+                let key = match (previous_size, mode) {
+                    (Some(_), ImportMode::Skip) => None,
+                    (Some(_), ImportMode::Rename) => Some(free_key(provider, &entry.key)),
+                    _ => Some(entry.key.to_owned()),
+                };
+                let key = match key {
+                    Some(key) => key,
+                    None => {
+                        let mut data = archive.entry_data(&entry);
+                        std::io::copy(&mut data, &mut std::io::sink()).map_err(archive_error)?;
+                        archive.read_checksum().map_err(archive_error)?;
+                        report.skipped.push(entry.key);
+                        continue;
+                    }
+                };
+                let previous_size = if key == entry.key { previous_size } else { None };
+                let options = SaveOptions {
+                    ttl: entry.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO)),
+                    metadata: entry.metadata.clone(),
+                };
+                let reserved = self.ensure_capacity(layer_key, provider).and_then(|_| self.quotas.reserve(layer_key, None, &key, previous_size, entry.size));
+                let reservation = match reserved {
+                    Ok(reservation) => reservation,
+                    Err(err) => {
+                        let mut data = archive.entry_data(&entry);
+                        std::io::copy(&mut data, &mut std::io::sink()).map_err(archive_error)?;
+                        archive.read_checksum().map_err(archive_error)?;
+                        report.failed.push((key, err));
+                        continue;
+                    }
+                };
+                // an existing entry is only replaced by verified data
+                let mut data = archive.entry_data(&entry);
+                let written = Unverified::write(provider, &key, &mut data, &options);
+                // the rest of the data must be consumed, so the next entry can be read
+                let consumed = std::io::copy(&mut data, &mut std::io::sink()).map_err(archive_error);
+                let (checksum, size) = data.finish();
+                let stored_checksum = match consumed.and_then(|_| archive.read_checksum().map_err(archive_error)) {
+                    Ok(stored_checksum) => stored_checksum,
+                    Err(err) => {
+                        if let Ok(unverified) = written {
+                            unverified.discard(provider);
+                        }
+                        self.quotas.release(reservation);
+                        return Err(err);
+                    }
+                };
+                let result = match written {
+                    Ok(unverified) if size != entry.size || checksum != stored_checksum => {
+                        unverified.discard(provider);
+                        Err(StorageError::ChecksumMismatch(entry.key.to_owned()))
+                    }
+                    Ok(unverified) => unverified.commit(provider, &key, &options),
+                    Err(err) => Err(StorageError::Provider(err)),
+                };
+                self.track_write(layer_key, &result);
+                if let Err(err) = result {
+                    self.quotas.release(reservation);
+                    report.failed.push((key, err));
+                    continue;
+                }
+                if key != entry.key {
+                    report.renamed.push((entry.key, key.to_owned()));
+                }
+                imported.push((key, checksum, reservation));
+            }
+            for (key, checksum, reservation) in imported {
+                let stored = provider.get_reader(&key).and_then(|mut stored| crc32_reader(&mut stored).map_err(|e| e.to_string()));
+                match stored {
+                    Ok((stored, size)) if stored == checksum => {
+                        self.record_bytes(layer_key, Transfer::Written, size);
+                        self.events.emit(StorageEventKind::Saved, layer_key, &key, size);
+                        report.imported.push(key);
+                    }
+                    _ => {
+                        provider.delete(&key);
+                        self.quotas.release(reservation);
+                        report.failed.push((key.to_owned(), StorageError::ChecksumMismatch(key)));
+                    }
+                }
+            }
+            Ok(report)
+        })
+    }
+
+    /// Copy all entries of a layer which changed since the backup `since` into the provider and record a manifest of the layer.
+    /// 
+    /// With `since` `None` the latest backup in the provider is used, the first backup copies all entries.
+    /// Unchanged entries reference the data of the older backup, so backups must not be removed from the provider.
+    pub fn backup(self: &Self, src_layer_key: &str, dst: &dyn StorageProvider, since: Option<&str>) -> Result<BackupReport, StorageError> {
+        self.measure("backup", src_layer_key, "", |_span| {
+            let src = self.provider(src_layer_key)?;
+            let base = match since {
+                Some(since) => Some(since.to_owned()),
+                None => backup::list_backups(dst)?.pop(),
+            };
+            let previous: HashMap<String, ManifestRecord> = match &base {
+                Some(base) => backup::read_manifest(dst, base)?.into_iter().map(|record| (record.key.to_owned(), record)).collect(),
+                None => HashMap::new(),
+            };
+            let mut report = BackupReport {
+                id: backup::new_backup_id(),
+                copied: vec![],
+                unchanged: 0,
+            };
+            let mut records = vec![];
+            for key in src.list("")? {
+                // the entry was deleted or expired after it was listed
+                let stat = match src.stat(&key) {
+                    Ok(stat) => stat,
+                    Err(_) => continue,
+                };
+                if let Some(record) = previous.get(&key).filter(|record| record.etag == stat.etag) {
+                    records.push(ManifestRecord {
+                        expires: stat.expires,
+                        ..record.clone()
+                    });
+                    report.unchanged += 1;
+                    continue;
+                }
+                let options = SaveOptions {
+                    ttl: None,
+                    metadata: stat.metadata,
+                };
+                let mut reader = ChecksumReader::new(src.get_reader(&key)?);
+                dst.save_from_reader(&backup::data_key(&report.id, &key), &mut reader, &options)?;
+                let (checksum, size) = reader.finish();
+                self.record_bytes(src_layer_key, Transfer::Read, size);
+                records.push(ManifestRecord {
+                    key: key.to_owned(),
+                    data_backup: report.id.to_owned(),
+                    etag: stat.etag,
+                    size,
+                    checksum,
+                    expires: stat.expires,
+                });
+                report.copied.push(key);
+            }
+            // the manifest is written last, a backup which did not finish is never listed
+            backup::write_manifest(dst, &report.id, &records)?;
+            Ok(report)
+        })
+    }
+
+    /// IDs of all backups in the provider, the oldest backup first.
+    pub fn list_backups(self: &Self, provider: &dyn StorageProvider) -> Result<Vec<BackupId>, StorageError> {
+        OperationSpan::layer("list_backups", "", "").run(|_span| {
+            backup::list_backups(provider)
+        })
+    }
+
+    /// Restore all entries of a backup into a layer, returns the result for every key of the backup.
+    /// 
+    /// Entries with the same key are overwritten, other entries of the layer are not changed. Entries which expired since the backup are not restored.
+    pub fn restore_backup(self: &Self, src: &dyn StorageProvider, backup: &str, dst_layer_key: &str) -> Result<BulkResult, StorageError> {
+        self.measure("restore_backup", dst_layer_key, "", |_span| {
+            let dst = self.writable_provider(dst_layer_key)?;
+            let mut results = vec![];
+            for record in backup::read_manifest(src, backup)? {
+                if matches!(record.expires, Some(expires) if expires <= SystemTime::now()) {
+                    continue;
+                }
+                let result = self.restore_record(src, dst_layer_key, dst, &record);
+                results.push((record.key, result));
+            }
+            Ok(results)
+        })
+    }
+
+    fn restore_record(
+        self: &Self,
+        src: &dyn StorageProvider,
+        dst_layer_key: &str,
+        dst: &dyn StorageProvider,
+        record: &ManifestRecord,
+    ) -> Result<SaveData, StorageError> {
+        if !is_valid_key(&record.key) {
+            return Err(StorageError::InvalidKey(record.key.to_owned()));
+        }
+        let data_key = backup::data_key(&record.data_backup, &record.key);
+        let options = SaveOptions {
+            ttl: record.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO)),
+            metadata: src.stat(&data_key).map_err(|_e| StorageError::NotFound(data_key.to_owned()))?.metadata,
+        };
+        self.ensure_capacity(dst_layer_key, dst)?;
+        let previous_size = dst.stat(&record.key).ok().map(|stat| stat.size as u64);
+        let reservation = self.quotas.reserve(dst_layer_key, None, &record.key, previous_size, record.size)?;
+        // an existing entry is only replaced by verified data
+        let mut reader = ChecksumReader::new(src.get_reader(&data_key)?);
+        let written = Unverified::write(dst, &record.key, &mut reader, &options);
+        let result = match written {
+            Ok(unverified) if reader.finish() != (record.checksum, record.size) => {
+                unverified.discard(dst);
+                Err(StorageError::ChecksumMismatch(record.key.to_owned()))
+            }
+            Ok(unverified) => unverified.commit(dst, &record.key, &options),
+            Err(err) => Err(StorageError::Provider(err)),
+        };
+        self.track_write(dst_layer_key, &result);
+        match result {
+            Ok(()) => {
+                self.record_bytes(dst_layer_key, Transfer::Written, record.size);
+                self.events.emit(StorageEventKind::Saved, dst_layer_key, &record.key, record.size);
+                Ok(SaveData {
+                    key: record.key.to_owned(),
+                    size: record.size as usize,
+                    version: None,
+                })
+            }
+            Err(err) => {
+                self.quotas.release(reservation);
+                Err(err)
+            }
+        }
+    }
+
+    /// Move an entry from one layer to another.
+    /// 
+    /// The source is only deleted after the verified copy is durable in the destination layer.
+    pub fn move_entry(self: &Self, src_layer_key: &str, dst_layer_key: &str, key: &str) -> Result<SaveData, StorageError> {
+        self.measure("move_entry", src_layer_key, key, |span| {
+            let result = self.copy_entry(src_layer_key, dst_layer_key, key, span)?;
+            self.remove_entry(src_layer_key, self.provider(src_layer_key)?, key)?;
+            Ok(result)
+        })
+    }
+
+    /// Copy all entries which match the filter from one layer to another, returns the result for every selected key.
+    pub fn copy_filtered(
+        self: &Self,
+        src_layer_key: &str,
+        dst_layer_key: &str,
+        filter: &EntryFilter,
+    ) -> Result<BulkResult, StorageError> {
+        self.measure("copy_filtered", src_layer_key, "", |_span| {
+            Ok(self
+                .filter_keys(src_layer_key, filter)?
+                .into_iter()
+                .map(|key| {
+                    let result = self.copy(src_layer_key, dst_layer_key, &key);
+                    (key, result)
+                })
+                .collect())
+        })
+    }
+
+    /// Move all entries which match the filter from one layer to another, returns the result for every selected key.
+    pub fn move_filtered(
+        self: &Self,
+        src_layer_key: &str,
+        dst_layer_key: &str,
+        filter: &EntryFilter,
+    ) -> Result<BulkResult, StorageError> {
+        self.measure("move_filtered", src_layer_key, "", |_span| {
+            Ok(self
+                .filter_keys(src_layer_key, filter)?
+                .into_iter()
+                .map(|key| {
+                    let result = self.move_entry(src_layer_key, dst_layer_key, &key);
+                    (key, result)
+                })
+                .collect())
+        })
+    }
+
+    /// Begin a transaction to save and delete entries on multiple layers together.
+    pub fn begin(self: &Self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Save a single part of a package, the part is stored by the `package_id` and `index` of the package.
+    pub fn save_part(self: &Self, layer_key: &str, package: &Package, raw: Vec<u8>) -> Result<SaveData, StorageError> {
+        self.measure("save_part", layer_key, &package.package_id, |_span| {
+            let options = assembly::part_options(&package.package_id);
+            self.save_with_options(layer_key, &assembly::part_key(&package.package_id, package.index), raw, &options)
+        })
+    }
+
+    /// Get the sorted indices of all parts stored for a package.
+    pub fn get_part_indices(self: &Self, layer_key: &str, package_id: &str) -> Result<Vec<u64>, StorageError> {
+        self.measure("get_part_indices", layer_key, package_id, |_span| {
+            Ok(assembly::part_indices(self.provider(layer_key)?, package_id)?)
+        })
+    }
+
+    /// Get the indices of all parts which are missing to complete a package with `part_count` parts.
+    pub fn get_missing_part_indices(self: &Self, layer_key: &str, package_id: &str, part_count: u64) -> Result<Vec<u64>, StorageError> {
+        self.measure("get_missing_part_indices", layer_key, package_id, |_span| {
+            Ok(assembly::missing_part_indices(self.provider(layer_key)?, package_id, part_count)?)
+        })
+    }
+
+    /// Assemble a complete package into a single entry with the key `target_key`.
+    /// 
+    /// The parts are streamed into the entry. Returns `Err` if a part is missing and `StorageError::ChecksumMismatch` if the optional checksum validation fails.
+    pub fn assemble_package(
+        self: &Self,
+        layer_key: &str,
+        package_id: &str,
+        part_count: u64,
+        target_key: &str,
+        checksum: Option<&ChecksumValidation>,
+    ) -> Result<SaveData, StorageError> {
+        self.measure("assemble_package", layer_key, package_id, |span| {
+            let provider = self.writable_provider(layer_key)?;
+            self.ensure_capacity(layer_key, provider)?;
+            let mut reader = ChecksumReader::new(assembly::open_reader(provider, package_id, part_count)?);
+            let size = assembly::package_size(provider, package_id, part_count)?;
+            span.record_size(size);
+            let previous_size = provider.stat(target_key).ok().map(|stat| stat.size as u64);
+            let reservation = self.quotas.reserve(layer_key, None, target_key, previous_size, size)?;
+
+            // the parts are streamed into the entry, which is only replaced if the checksum of the package matches
+            let options = SaveOptions::default();
+            let written = Unverified::write(provider, target_key, &mut reader, &options);
+            let (package_checksum, size) = reader.finish();
+            let verified = match checksum {
+                Some(validation) => validation.matches(package_checksum),
+                None => true,
+            };
+            let result = match written {
+                Ok(unverified) if verified => unverified.commit(provider, target_key, &options),
+                Ok(unverified) => {
+                    unverified.discard(provider);
+                    Err(StorageError::ChecksumMismatch(target_key.to_owned()))
+                }
+                Err(err) => Err(StorageError::Provider(err)),
+            };
+            self.track_write(layer_key, &result);
+            if let Err(err) = result {
+                self.quotas.release(reservation);
+                return Err(err);
+            }
+            self.record_bytes(layer_key, Transfer::Read, size);
+            self.record_bytes(layer_key, Transfer::Written, size);
+            self.events.emit(StorageEventKind::Saved, layer_key, target_key, size);
+            Ok(SaveData {
+                key: target_key.to_owned(),
+                size: size as usize,
+                version: None,
+            })
+        })
+    }
+
+    /// Open a reader which streams a complete package part by part.
+    pub fn read_package(self: &Self, layer_key: &str, package_id: &str, part_count: u64) -> Result<PackageReader<'_>, StorageError> {
+        self.measure("read_package", layer_key, package_id, |_span| {
+            Ok(assembly::open_reader(self.provider(layer_key)?, package_id, part_count)?)
+        })
+    }
+
+    pub(crate) fn provider(self: &Self, layer_key: &str) -> Result<&dyn StorageProvider, StorageError> {
+        match self.storage_providers.get(layer_key) {
+            Some(provider) => Ok(provider.as_ref()),
+            None => Err(StorageError::LayerNotFound(layer_key.to_owned())),
+        }
+    }
+
+    fn save_internal(
+        self: &Self,
+        layer_key: &str,
+        client: Option<&str>,
+        key: &str,
+        raw: Vec<u8>,
+        options: &SaveOptions,
+    ) -> Result<SaveData, StorageError> {
+        // a degraded layer is skipped and the entry is saved to its failover layer, the save is measured on the written layer
+        let routed = self.breaker.route(layer_key);
+        let layer_key = routed.as_deref().unwrap_or(layer_key);
+        self.measure("save", layer_key, key, |span| {
+            span.record_size(raw.len() as u64);
+            if routed.is_err() {
+                return Err(StorageError::Unavailable(layer_key.to_owned()));
+            }
+            let provider = self.provider(layer_key)?;
+            self.ensure_capacity(layer_key, provider)?;
+            let previous_size = provider.stat(key).ok().map(|stat| stat.size as u64);
+            self.quotas.reserve(layer_key, client, key, previous_size, raw.len() as u64).and_then(|reservation| {
+                match provider.save_with_options(key, raw, options) {
+                    Ok(result) => {
+                        self.breaker.succeeded(layer_key);
+                        self.record_bytes(layer_key, Transfer::Written, result.size as u64);
+                        self.events.emit(StorageEventKind::Saved, layer_key, key, result.size as u64);
+                        Ok(result)
+                    }
+                    Err(err) => {
+                        self.breaker.failed(layer_key);
+                        self.quotas.release(reservation);
+                        Err(StorageError::Provider(err))
+                    }
+                }
+            })
+        })
+    }
+
+    /// Provider of a layer for writes which do not fail over, returns `StorageError::Unavailable` while the layer is degraded.
+    pub(crate) fn writable_provider(self: &Self, layer_key: &str) -> Result<&dyn StorageProvider, StorageError> {
+        let provider = self.provider(layer_key)?;
+        if !self.breaker.allows(layer_key) {
+            return Err(StorageError::Unavailable(layer_key.to_owned()));
+        }
+        Ok(provider)
+    }
+
+    /// Count the result of a write for the circuit breaker of the layer, only provider errors are failures of the layer.
+    pub(crate) fn track_write<T>(self: &Self, layer_key: &str, result: &Result<T, StorageError>) {
+        match result {
+            Ok(_) => self.breaker.succeeded(layer_key),
+            Err(StorageError::Provider(_)) => self.breaker.failed(layer_key),
+            Err(_) => {}
+        }
+    }
+
+    fn get_measured(self: &Self, layer_key: &str, provider: &dyn StorageProvider, key: &str) -> Result<GetData, StorageError> {
+        // providers report a missing key as a provider error
+        let outcome = |result: &Result<GetData, StorageError>| if result.is_ok() { Outcome::Hit } else { Outcome::Miss };
+        self.measure_with("get", layer_key, key, outcome, |span| {
+            let result = provider.get(key).map_err(StorageError::Provider);
+            if let Ok(data) = &result {
+                span.record_size(data.size as u64);
+                self.breaker.succeeded(layer_key);
+                self.record_bytes(layer_key, Transfer::Read, data.size as u64);
+            }
+            result
+        })
+    }
+
+    /// Run an operation on a layer inside its span and record its duration and outcome in the metrics.
+    fn measure<R: TraceOutcome + MeasuredOutcome>(self: &Self, operation: &'static str, layer_key: &str, key: &str, f: impl FnOnce(&OperationSpan) -> R) -> R {
+        self.measure_with(operation, layer_key, key, R::outcome, f)
+    }
+
+    fn measure_with<R: TraceOutcome>(
+        self: &Self,
+        operation: &'static str,
+        layer_key: &str,
+        key: &str,
+        outcome: impl Fn(&R) -> Outcome,
+        f: impl FnOnce(&OperationSpan) -> R,
+    ) -> R {
+        let started = Instant::now();
+        let result = OperationSpan::layer(operation, layer_key, key).run(f);
+        self.record_operation(layer_key, operation, started.elapsed(), outcome(&result));
+        result
+    }
+
+    pub(crate) fn record_operation(self: &Self, layer_key: &str, operation: &str, duration: Duration, outcome: Outcome) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_operation(layer_key, operation, duration, outcome);
+        }
+    }
+
+    pub(crate) fn record_bytes(self: &Self, layer_key: &str, transfer: Transfer, bytes: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_bytes(layer_key, transfer, bytes);
+        }
+    }
+
+    fn delete_internal(self: &Self, layer_key: &str, provider: &dyn StorageProvider, key: &str) {
+        self.measure("delete", layer_key, key, |_span| {
+            let _result = self.remove_entry(layer_key, provider, key);
+        })
+    }
+
+    /// Queue an entry for deletion, returns `Err` if the entry still exists afterwards.
+    fn remove_entry(self: &Self, layer_key: &str, provider: &dyn StorageProvider, key: &str) -> Result<(), StorageError> {
+        let before = provider.stat(key);
+        provider.delete(key);
+        if provider.stat(key).is_ok() {
+            return Err(StorageError::Provider(format!("Could not delete `{}`", key)));
+        }
+        if let Ok(stat) = before {
+            self.quotas.removed(layer_key, key, stat.size as u64);
+            self.events.emit(StorageEventKind::Deleted, layer_key, key, stat.size as u64);
+        }
+        Ok(())
+    }
+
+    /// Run a free function on a layer, entries which expired are reported as deleted and removed entries of the delete queue as purged.
+    fn free_layer(self: &Self, layer_key: &str, provider: &dyn StorageProvider, free: impl Fn(&dyn StorageProvider)) {
+        self.measure("free", layer_key, "", |_span| {
+            let before = provider.list_deleted().unwrap_or_default();
+            free(provider);
+            self.refresh_usage(layer_key, provider);
+            if let Ok(after) = provider.list_deleted() {
+                let before_keys: HashSet<&String> = before.iter().map(|(key, _size)| key).collect();
+                let after_keys: HashSet<&String> = after.iter().map(|(key, _size)| key).collect();
+                for (key, size) in after.iter().filter(|(key, _size)| !before_keys.contains(key)) {
+                    self.events.emit(StorageEventKind::Deleted, layer_key, key, *size);
+                }
+                for (key, size) in before.iter().filter(|(key, _size)| !after_keys.contains(key)) {
+                    self.events.emit(StorageEventKind::Purged, layer_key, key, *size);
+                }
+            }
+        })
+    }
+
+    /// Check the free space of a layer against its watermarks, the delete queue is purged when the level gets worse.
+    fn check_layer_capacity(self: &Self, layer_key: &str, provider: &dyn StorageProvider) -> CapacityLevel {
+        let watermarks = match self.capacity.watermarks(layer_key) {
+            Some(watermarks) => watermarks,
+            None => return CapacityLevel::Normal,
+        };
+        // the last level is kept if the provider can not report its capacity
+        let mut free_bytes = match provider.capacity() {
+            Ok(capacity) => capacity.free_bytes,
+            Err(_) => return self.capacity.level(layer_key),
+        };
+        let mut level = watermarks.level(free_bytes);
+        if level > self.capacity.level(layer_key) {
+            let all = level == CapacityLevel::Critical;
+            self.free_layer(layer_key, provider, |provider| provider.force_free(all));
+            if let Ok(capacity) = provider.capacity() {
+                free_bytes = capacity.free_bytes;
+                level = watermarks.level(free_bytes);
+            }
+        }
+        if self.capacity.update(layer_key, level) {
+            let kind = match level {
+                CapacityLevel::Normal => StorageEventKind::CapacityNormal,
+                CapacityLevel::Low => StorageEventKind::CapacityLow,
+                CapacityLevel::Critical => StorageEventKind::CapacityCritical,
+            };
+            self.events.emit(kind, layer_key, "", free_bytes);
+        }
+        level
+    }
+
+    /// Refuse saves to a layer whose free space is below the critical watermark.
+    pub(crate) fn ensure_capacity(self: &Self, layer_key: &str, provider: &dyn StorageProvider) -> Result<(), StorageError> {
+        match self.check_layer_capacity(layer_key, provider) {
+            CapacityLevel::Critical => Err(StorageError::InsufficientSpace(layer_key.to_owned())),
+            _ => Ok(()),
+        }
+    }
+
+    fn filter_keys(self: &Self, layer_key: &str, filter: &EntryFilter) -> Result<Vec<String>, StorageError> {
+        let provider = self.provider(layer_key)?;
+        match filter {
+            EntryFilter::Prefix(prefix) => Ok(provider.list(prefix)?),
+            EntryFilter::Predicate(predicate) => Ok(provider
+                .list("")?
+                .into_iter()
+                .filter(|key| provider.stat(key).map(|stat| predicate(&stat)).unwrap_or(false))
+                .collect()),
+        }
+    }
+
+    fn refresh_usage(self: &Self, layer_key: &str, provider: &dyn StorageProvider) {
+        if let Ok(usage) = provider.usage() {
+            self.quotas.refresh(layer_key, usage, |key| provider.stat(key).is_ok());
+        }
+    }
+}
+
+/// Data of an entry which is written but not visible until its checksum is verified.
+enum Unverified {
+    /// Token of a save staged by the provider.
+    Staged(String),
+    /// Data buffered in memory for providers which do not support staging.
+    Buffered(Vec<u8>),
+}
+
+impl Unverified {
+    fn write(provider: &dyn StorageProvider, key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<Self, String> {
+        if provider.supports_staging() {
+            return Ok(Unverified::Staged(provider.stage_save_from_reader(key, reader, options)?));
+        }
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw).map_err(|e| format!("Could not read data: {}", e))?;
+        Ok(Unverified::Buffered(raw))
+    }
+
+    /// Open a reader for the written data.
+    fn reader<'a>(self: &'a Self, provider: &'a dyn StorageProvider) -> Result<Box<dyn Read + 'a>, String> {
+        match self {
+            Unverified::Staged(token) => provider.get_staged_reader(token),
+            Unverified::Buffered(raw) => Ok(Box::new(raw.as_slice())),
+        }
+    }
+
+    /// Make the verified data visible under the key.
+    fn commit(self: Self, provider: &dyn StorageProvider, key: &str, options: &SaveOptions) -> Result<(), StorageError> {
+        match self {
+            Unverified::Staged(token) => provider.commit_staged(key, &token)?,
+            Unverified::Buffered(raw) => {
+                provider.save_with_options(key, raw, options)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn discard(self: Self, provider: &dyn StorageProvider) {
+        if let Unverified::Staged(token) = self {
+            provider.discard_staged(&token);
+        }
+    }
+}
+
+/// Keys are used as file names by the providers, so a key must not contain a path or start with the `.` of the hidden folders.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('.') && !key.contains(['/', '\\', '\0']) && !key.contains("..")
+}
+
+/// First key `{key}~{n}` which does not exist in the provider.
+fn free_key(provider: &dyn StorageProvider, key: &str) -> String {
+    (1..)
+        .map(|n| format!("{}~{}", key, n))
+        .find(|candidate| provider.stat(candidate).is_err())
+        .expect("Unbounded range always yields a free key")
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{filestorage::FileStorageProvider, StorageProvider};
+    use dispnet_shared::Package;
+
+    use crate::{
+        archive::{ArchiveEntry, ArchiveWriter, ImportMode},
+        assembly::ChecksumValidation,
+        checksum::crc32,
+        capacity::{CapacityLevel, Watermarks},
+        events::StorageEventKind,
+        filestorage::FileStorageProvider,
+        health::{CircuitBreakerConfig, LayerStatus},
+        metrics::InMemoryMetrics,
+        quota::Quota,
+        GetData, Metadata, SaveData, SaveOptions, StatData, StorageError, StorageProvider, META_ORIGIN_CLIENT, META_PACKAGE_ID,
+    };
 
-    use super::StorageManager;
+    use std::{
+        io::Read,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{EntryFilter, StorageManager};
 
     const FILE_STORAGE: &str = "test_fstore";
     const DELETE_STORAGE: &str = "test_fdelete";
@@ -126,7 +1325,115 @@ mod tests {
         let f_path = format!("{}_{}", FILE_STORAGE, test_key);
         let d_path = format!("{}_{}", DELETE_STORAGE, test_key);
 
-        Box::new(FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()))
+        Box::new(FileStorageProvider::new(f_path.to_owned(), d_path.to_owned()).unwrap())
+    }
+
+    /// File storage provider which fails every commit of a staged save.
+    struct FailingCommit(FileStorageProvider);
+
+    /// Provider which corrupts staged data and does not delete entries.
+    struct Faulty(FileStorageProvider);
+
+    impl StorageProvider for Faulty {
+        fn get(self: &Self, key: &str) -> Result<GetData, String> {
+            self.0.get(key)
+        }
+
+        fn save(self: &Self, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
+            self.0.save(key, raw)
+        }
+
+        fn delete(self: &Self, _key: &str) {}
+
+        fn supports_staging(self: &Self) -> bool {
+            true
+        }
+
+        fn stage_save_from_reader(self: &Self, key: &str, reader: &mut dyn Read, options: &SaveOptions) -> Result<String, String> {
+            self.0.stage_save_from_reader(key, reader, options)
+        }
+
+        fn get_staged_reader(self: &Self, _token: &str) -> Result<Box<dyn Read + '_>, String> {
+            Ok(Box::new("corrupt".as_bytes()))
+        }
+
+        fn commit_staged(self: &Self, key: &str, token: &str) -> Result<(), String> {
+            self.0.commit_staged(key, token)
+        }
+
+        fn discard_staged(self: &Self, token: &str) {
+            self.0.discard_staged(token)
+        }
+
+        fn free(self: &Self) {
+            self.0.free()
+        }
+
+        fn force_free(self: &Self, all: bool) {
+            self.0.force_free(all)
+        }
+
+        fn list(self: &Self, prefix: &str) -> Result<Vec<String>, String> {
+            self.0.list(prefix)
+        }
+
+        fn stat(self: &Self, key: &str) -> Result<StatData, String> {
+            self.0.stat(key)
+        }
+    }
+
+    impl StorageProvider for FailingCommit {
+        fn get(self: &Self, key: &str) -> Result<GetData, String> {
+            self.0.get(key)
+        }
+
+        fn save(self: &Self, key: &str, raw: Vec<u8>) -> Result<SaveData, String> {
+            self.0.save(key, raw)
+        }
+
+        fn delete(self: &Self, key: &str) {
+            self.0.delete(key)
+        }
+
+        fn discard(self: &Self, key: &str) -> Result<(), String> {
+            self.0.discard(key)
+        }
+
+        fn supports_staging(self: &Self) -> bool {
+            true
+        }
+
+        fn stage_save(self: &Self, key: &str, raw: Vec<u8>, options: &SaveOptions) -> Result<String, String> {
+            self.0.stage_save(key, raw, options)
+        }
+
+        fn commit_staged(self: &Self, _key: &str, _token: &str) -> Result<(), String> {
+            Err("Could not commit".to_owned())
+        }
+
+        fn discard_staged(self: &Self, token: &str) {
+            self.0.discard_staged(token)
+        }
+
+        fn free(self: &Self) {
+            self.0.free()
+        }
+
+        fn force_free(self: &Self, all: bool) {
+            self.0.force_free(all)
+        }
+
+        fn free_older_than(self: &Self, age: Duration) {
+            self.0.free_older_than(age)
+        }
+
+        fn list(self: &Self, prefix: &str) -> Result<Vec<String>, String> {
+            self.0.list(prefix)
+        }
+
+        fn stat(self: &Self, key: &str) -> Result<StatData, String> {
+            self.0.stat(key)
+        }
     }
 
     #[test]
@@ -221,4 +1528,601 @@ mod tests {
         assert!(!exists);
         clean_up(f_key);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn assemble_package() {
+        let f_key = "assemble_package_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        let mut package = Package {
+            index: 1,
+            checksum: "".to_owned(),
+            compression_algorithm: "".to_owned(),
+            normalized_size: 0,
+            package_id: "p1".to_owned(),
+            size: 2,
+        };
+        manager.save_part("layer1", &package, "st".to_owned().into_bytes()).unwrap();
+        assert_eq!(manager.get_missing_part_indices("layer1", "p1", 2).unwrap(), vec![0]);
+        package.index = 0;
+        manager.save_part("layer1", &package, "te".to_owned().into_bytes()).unwrap();
+        assert_eq!(manager.get_part_indices("layer1", "p1").unwrap(), vec![0, 1]);
+        let stat = manager.stat("layer1", "p1.0").unwrap();
+        assert_eq!(stat.metadata.get(META_PACKAGE_ID).unwrap(), "p1");
+        manager.assemble_package("layer1", "p1", 2, "p1", None).unwrap();
+        let result = manager.get("layer1", "p1").unwrap();
+        assert_eq!(result.data, "test".to_owned().into_bytes());
+
+        // the entry is only replaced if the checksum of the package matches
+        let expected = format!("{:08x}", crc32(b"test"));
+        let result = manager.assemble_package("layer1", "p1", 2, "p1_checked", Some(&ChecksumValidation { expected: &expected })).unwrap();
+        assert_eq!(result.size, 4);
+        let result = manager.assemble_package("layer1", "p1", 2, "p1", Some(&ChecksumValidation { expected: "0" }));
+        assert_eq!(result.err(), Some(StorageError::ChecksumMismatch("p1".to_owned())));
+        assert_eq!(manager.get("layer1", "p1").unwrap().data, "test".to_owned().into_bytes());
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.bytes, 12);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn layer_quota() {
+        let f_key = "layer_quota_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.set_layer_quota("layer1", Some(Quota { max_bytes: Some(6), max_entries: None }));
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let result = manager.save("layer1", "5678", "test".to_owned().into_bytes());
+        assert!(matches!(result, Err(StorageError::QuotaExceeded { client: None, .. })));
+        // overwriting an entry only counts the difference
+        manager.save("layer1", FILE_KEY, "test12".to_owned().into_bytes()).unwrap();
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.bytes, 6);
+        manager.delete("layer1", FILE_KEY);
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.bytes, 0);
+        manager.save("layer1", "5678", "test".to_owned().into_bytes()).unwrap();
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn client_quota() {
+        let f_key = "client_quota_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.set_client_quota("layer1", "client1", Some(Quota { max_bytes: None, max_entries: Some(1) }));
+        manager.save_for_client("layer1", "client1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let result = manager.save_for_client("layer1", "client1", "5678", "test".to_owned().into_bytes());
+        assert!(matches!(result, Err(StorageError::QuotaExceeded { client: Some(_), .. })));
+        manager.save_for_client("layer1", "client2", "5678", "test".to_owned().into_bytes()).unwrap();
+        let usage = manager.get_client_usage("layer1", "client1").unwrap();
+        assert_eq!(usage.usage.entries, 1);
+        assert_eq!(usage.limit.unwrap().max_entries, Some(1));
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.entries, 2);
+
+        // the client usage is rebuilt from the stored entries after a restart
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.set_client_quota("layer1", "client1", Some(Quota { max_bytes: None, max_entries: Some(1) }));
+        assert_eq!(manager.get_client_usage("layer1", "client1").unwrap().usage.entries, 1);
+        assert_eq!(manager.get_client_usage("layer1", "client2").unwrap().usage.bytes, 4);
+        let result = manager.save_for_client("layer1", "client1", "9012", "test".to_owned().into_bytes());
+        assert!(matches!(result, Err(StorageError::QuotaExceeded { client: Some(_), .. })));
+        manager.save_for_client("layer1", "client1", FILE_KEY, "new".to_owned().into_bytes()).unwrap();
+        assert_eq!(manager.get_client_usage("layer1", "client1").unwrap().usage.bytes, 3);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn transaction() {
+        let f_key = "transaction_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        manager.save("layer2", "index", "old".to_owned().into_bytes()).unwrap();
+
+        let mut transaction = manager.begin();
+        transaction.save("layer1", FILE_KEY, "test".to_owned().into_bytes());
+        transaction.delete("layer2", "index");
+        transaction.save("missing", FILE_KEY, "test".to_owned().into_bytes());
+        assert!(matches!(transaction.commit(), Err(StorageError::LayerNotFound(_))));
+        assert!(manager.get("layer1", FILE_KEY).is_err());
+        assert!(manager.get("layer2", "index").is_ok());
+
+        manager.set_layer_quota("layer1", Some(Quota { max_bytes: Some(4), max_entries: None }));
+        let mut transaction = manager.begin();
+        transaction.delete("layer2", "index");
+        transaction.save("layer1", FILE_KEY, "test".to_owned().into_bytes());
+        transaction.save("layer1", "5678", "test".to_owned().into_bytes());
+        assert!(matches!(transaction.commit(), Err(StorageError::QuotaExceeded { .. })));
+        assert!(manager.get("layer1", FILE_KEY).is_err());
+        assert!(manager.get("layer2", "index").is_ok());
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.bytes, 0);
+
+        let mut transaction = manager.begin();
+        transaction.save("layer1", FILE_KEY, "test".to_owned().into_bytes());
+        transaction.delete("layer2", "index");
+        transaction.commit().unwrap();
+        assert!(manager.get("layer1", FILE_KEY).is_ok());
+        assert!(manager.get("layer2", "index").is_err());
+        // the kept copy of the deleted entry is removed after the commit
+        assert_eq!(std::fs::read_dir(format!("{}_{}_2/.tmp", FILE_STORAGE, f_key)).unwrap().count(), 0);
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+    }
+
+    #[test]
+    fn transaction_revert() {
+        let f_key = "transaction_revert_provider";
+        let f_key2 = format!("{}_2", f_key);
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        let failing = FileStorageProvider::new(format!("{}_{}", FILE_STORAGE, f_key2), format!("{}_{}", DELETE_STORAGE, f_key2)).unwrap();
+        manager.add_storage_provider("layer2".to_owned(), Box::new(FailingCommit(failing)));
+        let options = SaveOptions {
+            ttl: Some(Duration::from_secs(3_600)),
+            ..Default::default()
+        };
+        manager.save_with_options("layer1", FILE_KEY, "old".to_owned().into_bytes(), &options).unwrap();
+        manager.save("layer1", "5678", "old".to_owned().into_bytes()).unwrap();
+        manager.save("layer2", "index", "older".to_owned().into_bytes()).unwrap();
+        manager.delete("layer2", "index");
+        let usage = manager.get_layer_usage("layer1").unwrap().usage;
+
+        // the save on layer1 is committed before the commit on layer2 fails
+        let mut transaction = manager.begin();
+        transaction.save("layer1", FILE_KEY, "new".to_owned().into_bytes());
+        transaction.delete("layer1", "5678");
+        transaction.save("layer2", "index", "new".to_owned().into_bytes());
+        assert!(matches!(transaction.commit(), Err(StorageError::Provider(_))));
+        assert_eq!(manager.get("layer1", FILE_KEY).unwrap().data, "old".to_owned().into_bytes());
+        assert!(manager.stat("layer1", FILE_KEY).unwrap().expires.is_some());
+        assert_eq!(manager.get("layer1", "5678").unwrap().data, "old".to_owned().into_bytes());
+        assert!(manager.get("layer2", "index").is_err());
+        // the new entry is discarded, the older deleted entry stays in the delete queue
+        assert_eq!(std::fs::read(format!("{}_{}/index", DELETE_STORAGE, f_key2)).unwrap(), "older".to_owned().into_bytes());
+        assert_eq!(std::fs::read_dir(format!("{}_{}/.tmp", FILE_STORAGE, f_key2)).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(format!("{}_{}/.tmp", FILE_STORAGE, f_key)).unwrap().count(), 0);
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage, usage);
+        clean_up(f_key);
+        clean_up(&f_key2);
+    }
+
+    #[test]
+    fn batch() {
+        let f_key = "batch_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.set_layer_quota("layer1", Some(Quota { max_bytes: None, max_entries: Some(2) }));
+        let results = manager.save_many("layer1", vec![
+            ("a".to_owned(), "test".to_owned().into_bytes()),
+            ("b".to_owned(), "test".to_owned().into_bytes()),
+            ("c".to_owned(), "test".to_owned().into_bytes()),
+        ]).unwrap();
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert!(matches!(results[2], Err(StorageError::QuotaExceeded { .. })));
+
+        let results = manager.get_many("layer1", &["a", "c"]).unwrap();
+        assert_eq!(results[0].as_ref().unwrap().size, 4);
+        assert!(results[1].is_err());
+
+        let results = manager.delete_many("layer1", &["a", "c"]).unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(StorageError::NotFound(_))));
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.entries, 1);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn copy_and_move() {
+        let f_key = "copy_move_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        manager.save_for_client("layer1", "client1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.save("layer1", "a1", "test".to_owned().into_bytes()).unwrap();
+        manager.save("layer1", "a2", "te".to_owned().into_bytes()).unwrap();
+
+        manager.copy("layer1", "layer2", FILE_KEY).unwrap();
+        assert!(manager.get("layer1", FILE_KEY).is_ok());
+        let copy = manager.get("layer2", FILE_KEY).unwrap();
+        assert_eq!(copy.data, "test".to_owned().into_bytes());
+        assert_eq!(copy.metadata.get(META_ORIGIN_CLIENT).unwrap(), "client1");
+
+        manager.move_entry("layer1", "layer2", FILE_KEY).unwrap();
+        assert!(manager.get("layer1", FILE_KEY).is_err());
+
+        let results = manager.move_filtered("layer1", "layer2", &EntryFilter::Predicate(|stat| stat.size == 2)).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "a2");
+        let results = manager.copy_filtered("layer1", "layer2", &EntryFilter::Prefix("a".to_owned())).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(manager.list("layer2", "").unwrap(), vec![FILE_KEY, "a1", "a2"]);
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.entries, 1);
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+    }
+
+    #[test]
+    fn copy_mismatch() {
+        let f_key = "copy_mismatch_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        let faulty = FileStorageProvider::new(format!("{}_{}_2", FILE_STORAGE, f_key), format!("{}_{}_2", DELETE_STORAGE, f_key)).unwrap();
+        manager.add_storage_provider("layer2".to_owned(), Box::new(Faulty(faulty)));
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.save("layer2", FILE_KEY, "old".to_owned().into_bytes()).unwrap();
+
+        // a corrupted copy is discarded and the entry in the destination is kept
+        let result = manager.copy("layer1", "layer2", FILE_KEY);
+        assert_eq!(result.err(), Some(StorageError::ChecksumMismatch(FILE_KEY.to_owned())));
+        assert_eq!(manager.get("layer2", FILE_KEY).unwrap().data, "old".to_owned().into_bytes());
+        assert_eq!(std::fs::read_dir(format!("{}_{}_2/.tmp", FILE_STORAGE, f_key)).unwrap().count(), 0);
+        assert_eq!(manager.get_layer_usage("layer2").unwrap().usage.bytes, 3);
+
+        // a move fails if the source can not be deleted
+        let metrics = Arc::new(InMemoryMetrics::default());
+        manager.set_metrics(metrics.clone());
+        let result = manager.move_entry("layer2", "layer1", FILE_KEY);
+        assert!(matches!(result, Err(StorageError::Provider(_))));
+        assert!(manager.get("layer2", FILE_KEY).is_ok());
+        assert_eq!(metrics.operation("layer2", "move_entry").unwrap().count, 1);
+        assert!(metrics.operation("layer2", "copy").is_none());
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+    }
+
+    #[test]
+    fn conditional_save() {
+        let f_key = "conditional_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.save_if_absent("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert_eq!(manager.save_if_absent("layer1", FILE_KEY, "test".to_owned().into_bytes()).err(), Some(StorageError::Conflict(FILE_KEY.to_owned())));
+        let etag = manager.stat("layer1", FILE_KEY).unwrap().etag;
+        manager.save_if_match("layer1", FILE_KEY, "test2".to_owned().into_bytes(), &etag).unwrap();
+        assert_eq!(manager.save_if_match("layer1", FILE_KEY, "test3".to_owned().into_bytes(), &etag).err(), Some(StorageError::PreconditionFailed(FILE_KEY.to_owned())));
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.bytes, 5);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn snapshot() {
+        let f_key = "snapshot_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let snapshot = manager.snapshot("layer1").unwrap();
+        manager.delete("layer1", FILE_KEY);
+        assert_eq!(manager.list_snapshot_entries("layer1", &snapshot, "").unwrap(), vec![FILE_KEY]);
+        assert_eq!(manager.get_snapshot_entry("layer1", &snapshot, FILE_KEY).unwrap().data, "test".to_owned().into_bytes());
+        manager.delete_snapshot("layer1", &snapshot).unwrap();
+        assert!(manager.list_snapshots("layer1").unwrap().is_empty());
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn export_and_import() {
+        let f_key = "export_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        manager.save_for_client("layer1", "client1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.save("layer1", "5678", "test2".to_owned().into_bytes()).unwrap();
+        manager.save("layer2", FILE_KEY, "old".to_owned().into_bytes()).unwrap();
+
+        let mut archive = vec![];
+        assert_eq!(manager.export("layer1", &mut archive).unwrap(), 2);
+
+        let report = manager.import("layer2", &mut archive.as_slice(), ImportMode::Skip).unwrap();
+        assert_eq!(report.imported, vec!["5678"]);
+        assert_eq!(report.skipped, vec![FILE_KEY]);
+        assert_eq!(manager.get("layer2", FILE_KEY).unwrap().data, "old".to_owned().into_bytes());
+
+        let report = manager.import("layer2", &mut archive.as_slice(), ImportMode::Rename).unwrap();
+        assert_eq!(report.renamed, vec![(FILE_KEY.to_owned(), format!("{}~1", FILE_KEY)), ("5678".to_owned(), "5678~1".to_owned())]);
+        let renamed = manager.get("layer2", &format!("{}~1", FILE_KEY)).unwrap();
+        assert_eq!(renamed.data, "test".to_owned().into_bytes());
+        assert_eq!(renamed.metadata.get(META_ORIGIN_CLIENT).unwrap(), "client1");
+
+        let report = manager.import("layer2", &mut archive.as_slice(), ImportMode::Overwrite).unwrap();
+        assert_eq!(report.imported, vec![FILE_KEY, "5678"]);
+        assert_eq!(manager.get("layer2", FILE_KEY).unwrap().data, "test".to_owned().into_bytes());
+        assert_eq!(manager.get_layer_usage("layer2").unwrap().usage.entries, 4);
+
+        // corrupt the data of the first entry
+        let position = archive.windows(4).position(|window| window == b"test").unwrap();
+        archive[position] = b'x';
+        let report = manager.import("layer2", &mut archive.as_slice(), ImportMode::Overwrite).unwrap();
+        assert_eq!(report.failed, vec![(FILE_KEY.to_owned(), StorageError::ChecksumMismatch(FILE_KEY.to_owned()))]);
+        assert_eq!(manager.get("layer2", FILE_KEY).unwrap().data, "test".to_owned().into_bytes());
+        assert_eq!(manager.list("layer2", "").unwrap().len(), 4);
+        assert!(manager.import("layer2", &mut &archive[..archive.len() - 1], ImportMode::Overwrite).is_err());
+
+        // the entry which was written when the archive ended in its checksum is discarded, the first entry has the corrupted data
+        manager.add_storage_provider("layer3".to_owned(), storage_provider_instance(&format!("{}_3", f_key)));
+        let end = archive.windows(5).position(|window| window == b"test2").unwrap() + 7;
+        assert!(manager.import("layer3", &mut &archive[..end], ImportMode::Overwrite).is_err());
+        assert!(manager.list("layer3", "").unwrap().is_empty());
+        assert_eq!(manager.get_layer_usage("layer3").unwrap().usage.entries, 0);
+        assert_eq!(std::fs::read_dir(format!("{}_{}_3/.tmp", FILE_STORAGE, f_key)).unwrap().count(), 0);
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+        clean_up(&format!("{}_3", f_key));
+    }
+
+    #[test]
+    fn import_invalid_keys() {
+        let f_key = "import_keys_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.save("layer1", "5678~import", "keep".to_owned().into_bytes()).unwrap();
+
+        let mut archive = ArchiveWriter::new(vec![]).unwrap();
+        for key in ["../import_keys_outside", "/tmp/import_keys_outside", ".meta/5678", "a\\b", "5678"] {
+            let entry = ArchiveEntry {
+                key: key.to_owned(),
+                size: 4,
+                expires: None,
+                metadata: Metadata::new(),
+            };
+            archive.add(&entry, &mut "test".as_bytes()).unwrap();
+        }
+        let archive = archive.finish().unwrap();
+        let report = manager.import("layer1", &mut archive.as_slice(), ImportMode::Overwrite).unwrap();
+        assert_eq!(report.imported, vec!["5678"]);
+        let failed: Vec<&StorageError> = report.failed.iter().map(|(_key, err)| err).collect();
+        assert_eq!(failed.len(), 4);
+        assert!(failed.iter().all(|err| matches!(err, StorageError::InvalidKey(_))));
+        assert!(!std::path::Path::new("import_keys_outside").exists());
+        assert!(!std::path::Path::new("/tmp/import_keys_outside").exists());
+        assert_eq!(manager.get("layer1", "5678~import").unwrap().data, "keep".to_owned().into_bytes());
+        assert_eq!(manager.list("layer1", "").unwrap(), vec!["5678", "5678~import"]);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn backup() {
+        let f_key = "backup_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        let backups = storage_provider_instance(&format!("{}_backups", f_key));
+        manager.save_for_client("layer1", "client1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.save("layer1", "5678", "test2".to_owned().into_bytes()).unwrap();
+
+        let first = manager.backup("layer1", backups.as_ref(), None).unwrap();
+        assert_eq!(first.copied, vec![FILE_KEY, "5678"]);
+        manager.save("layer1", "5678", "changed".to_owned().into_bytes()).unwrap();
+        manager.delete("layer1", FILE_KEY);
+        let second = manager.backup("layer1", backups.as_ref(), None).unwrap();
+        assert_eq!(second.copied, vec!["5678"]);
+        assert_eq!(manager.list_backups(backups.as_ref()).unwrap(), vec![first.id.to_owned(), second.id.to_owned()]);
+
+        let results = manager.restore_backup(backups.as_ref(), &first.id, "layer2").unwrap();
+        assert!(results.iter().all(|(_key, result)| result.is_ok()));
+        let restored = manager.get("layer2", FILE_KEY).unwrap();
+        assert_eq!(restored.data, "test".to_owned().into_bytes());
+        assert_eq!(restored.metadata.get(META_ORIGIN_CLIENT).unwrap(), "client1");
+        assert_eq!(manager.get("layer2", "5678").unwrap().data, "test2".to_owned().into_bytes());
+
+        manager.restore_backup(backups.as_ref(), &second.id, "layer2").unwrap();
+        assert_eq!(manager.get("layer2", "5678").unwrap().data, "changed".to_owned().into_bytes());
+        assert_eq!(manager.list("layer2", "").unwrap(), vec![FILE_KEY, "5678"]);
+        assert_eq!(manager.get_layer_usage("layer2").unwrap().usage.entries, 2);
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+        clean_up(&format!("{}_backups", f_key));
+    }
+
+    #[test]
+    fn events() {
+        let f_key = "events_provider";
+        let log_path = format!("{}_{}.events", FILE_STORAGE, f_key);
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.enable_event_log(&log_path).unwrap();
+        let receiver = manager.subscribe();
+        let deleted = Arc::new(AtomicUsize::new(0));
+        let counter = deleted.clone();
+        manager.on_event(move |event| {
+            if event.kind == StorageEventKind::Deleted {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.delete("layer1", FILE_KEY);
+        manager.restore("layer1", FILE_KEY).unwrap();
+        manager.delete("layer1", FILE_KEY);
+        manager.force_free(true);
+        let kinds: Vec<StorageEventKind> = receiver.try_iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                StorageEventKind::Saved,
+                StorageEventKind::Deleted,
+                StorageEventKind::Restored,
+                StorageEventKind::Deleted,
+                StorageEventKind::Purged
+            ]
+        );
+        assert_eq!(deleted.load(Ordering::Relaxed), 2);
+
+        // a new manager continues the sequence of the log
+        let restarted = StorageManager::new();
+        restarted.enable_event_log(&log_path).unwrap();
+        assert_eq!(restarted.last_event_sequence(), 5);
+        let missed = restarted.events_since(3).unwrap();
+        assert_eq!(missed.len(), 2);
+        assert_eq!(missed[0].key, FILE_KEY);
+        std::fs::remove_file(&log_path).unwrap();
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn external_changes() {
+        let f_key = "external_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let receiver = manager.subscribe();
+        std::fs::write(format!("{}_{}/{}", FILE_STORAGE, f_key, "added"), "test").unwrap();
+
+        let changes = manager.poll_external_changes().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].1.key, "added");
+        assert_eq!(receiver.try_recv().unwrap().kind, StorageEventKind::Saved);
+        assert_eq!(manager.get_layer_usage("layer1").unwrap().usage.entries, 2);
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn metrics() {
+        let f_key = "metrics_provider";
+        let metrics = Arc::new(InMemoryMetrics::default());
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.set_metrics(metrics.clone());
+        manager.set_layer_quota("layer1", Some(Quota { max_bytes: None, max_entries: Some(1) }));
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(manager.save("layer1", "5678", "test".to_owned().into_bytes()).is_err());
+        manager.get("layer1", FILE_KEY).unwrap();
+        assert!(manager.get("layer1", "5678").is_err());
+        manager.delete("layer1", FILE_KEY);
+        manager.free();
+
+        let save = metrics.operation("layer1", "save").unwrap();
+        assert_eq!(save.count, 2);
+        assert_eq!(save.errors.get("quota_exceeded"), Some(&1));
+        let get = metrics.operation("layer1", "get").unwrap();
+        assert_eq!((get.hits, get.misses), (1, 1));
+        assert_eq!(metrics.operation("layer1", "delete").unwrap().count, 1);
+        assert_eq!(metrics.operation("layer1", "free").unwrap().count, 1);
+        assert_eq!(metrics.bytes("layer1").read, 4);
+        assert_eq!(metrics.bytes("layer1").written, 4);
+
+        // batch operations, copies and transactions are measured on their layers
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        manager.save_many("layer2", vec![(FILE_KEY.to_owned(), "test".to_owned().into_bytes())]).unwrap();
+        manager.copy("layer2", "layer1", FILE_KEY).unwrap();
+        let mut transaction = manager.begin();
+        transaction.save("layer2", "5678", "test".to_owned().into_bytes());
+        transaction.commit().unwrap();
+        assert_eq!(metrics.operation("layer2", "save_many").unwrap().count, 1);
+        assert_eq!(metrics.operation("layer2", "copy").unwrap().count, 1);
+        assert_eq!(metrics.operation("layer2", "commit").unwrap().count, 1);
+        assert_eq!(metrics.bytes("layer2").read, 4);
+        assert_eq!(metrics.bytes("layer2").written, 8);
+        assert_eq!(metrics.bytes("layer1").written, 8);
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+    }
+
+    #[test]
+    fn health() {
+        let f_key = "health_provider";
+        let f_key_failover = "health_failover_provider";
+        let metrics = Arc::new(InMemoryMetrics::default());
+        let mut manager = StorageManager::new();
+        manager.set_metrics(metrics.clone());
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(f_key_failover));
+        manager.set_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 2,
+            retry_after: Duration::from_secs(60),
+            max_error_rate: 0.5,
+        });
+        manager.set_failover_layer("layer1", Some("layer2"));
+        let report = manager.health_report();
+        assert_eq!(report[0].status, LayerStatus::Healthy);
+        assert!(report[0].provider.as_ref().unwrap().writable);
+
+        // saves fail while the temp folder is missing, failures of all writes count for the breaker
+        let temp = format!("{}_{}/.tmp", FILE_STORAGE, f_key);
+        std::fs::remove_dir_all(&temp).unwrap();
+        assert!(manager.save_if_absent("layer1", FILE_KEY, "test".to_owned().into_bytes()).is_err());
+        assert!(manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).is_err());
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(manager.get("layer2", FILE_KEY).is_ok());
+        assert_eq!(manager.find(FILE_KEY).unwrap().size, 4);
+        // the failover save is measured on the written layer
+        assert_eq!(metrics.operation("layer1", "save").unwrap().count, 1);
+        assert_eq!(metrics.operation("layer2", "save").unwrap().count, 1);
+        assert_eq!(metrics.bytes("layer2").written, 4);
+
+        // writes which do not fail over are rejected while the layer is degraded
+        assert!(matches!(manager.save_if_absent("layer1", "5678", "test".to_owned().into_bytes()), Err(StorageError::Unavailable(_))));
+        assert!(matches!(manager.save_many("layer1", vec![("5678".to_owned(), "test".to_owned().into_bytes())]), Err(StorageError::Unavailable(_))));
+        assert!(matches!(manager.copy("layer2", "layer1", FILE_KEY), Err(StorageError::Unavailable(_))));
+        let mut transaction = manager.begin();
+        transaction.save("layer1", "5678", "test".to_owned().into_bytes());
+        assert!(matches!(transaction.commit(), Err(StorageError::Unavailable(_))));
+
+        std::fs::create_dir(&temp).unwrap();
+        let report = manager.health_report();
+        assert_eq!(report[0].status, LayerStatus::Degraded);
+        assert_eq!(report[0].consecutive_failures, 2);
+        assert_eq!(report[0].provider.as_ref().unwrap().error_rate, 1.0);
+        // the error rate is kept, the layer stays degraded until the retry time has passed
+        assert_eq!(manager.health_report()[0].status, LayerStatus::Degraded);
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(manager.get("layer1", FILE_KEY).is_err());
+        manager.set_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 2,
+            retry_after: Duration::ZERO,
+            max_error_rate: 0.5,
+        });
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        assert!(manager.get("layer1", FILE_KEY).is_ok());
+        clean_up(f_key);
+        clean_up(f_key_failover);
+    }
+
+    #[test]
+    fn capacity() {
+        let f_key = "capacity_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        let receiver = manager.subscribe();
+        assert!(manager.capacity("layer1").unwrap().free_bytes > 0);
+        manager.save("layer1", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        manager.delete("layer1", FILE_KEY);
+
+        manager.set_watermarks("layer1", Some(Watermarks { low_free_bytes: u64::MAX, critical_free_bytes: u64::MAX }));
+        assert!(matches!(manager.save("layer1", "5678", "test".to_owned().into_bytes()), Err(StorageError::InsufficientSpace(_))));
+        let kinds: Vec<StorageEventKind> = receiver.try_iter().map(|event| event.kind).collect();
+        assert!(kinds.contains(&StorageEventKind::Purged));
+        assert_eq!(kinds.last(), Some(&StorageEventKind::CapacityCritical));
+
+        manager.set_watermarks("layer1", Some(Watermarks { low_free_bytes: 0, critical_free_bytes: 0 }));
+        assert_eq!(manager.check_capacity(), vec![("layer1".to_owned(), CapacityLevel::Normal)]);
+        assert_eq!(receiver.try_recv().unwrap().kind, StorageEventKind::CapacityNormal);
+        manager.save("layer1", "5678", "test".to_owned().into_bytes()).unwrap();
+        clean_up(f_key);
+    }
+
+    #[test]
+    fn capacity_bulk() {
+        let f_key = "capacity_bulk_provider";
+        let mut manager = StorageManager::new();
+        manager.add_storage_provider("layer1".to_owned(), storage_provider_instance(f_key));
+        manager.add_storage_provider("layer2".to_owned(), storage_provider_instance(&format!("{}_2", f_key)));
+        let backups = storage_provider_instance(&format!("{}_backups", f_key));
+        manager.save("layer2", FILE_KEY, "test".to_owned().into_bytes()).unwrap();
+        let mut archive = vec![];
+        manager.export("layer2", &mut archive).unwrap();
+        let backup = manager.backup("layer2", backups.as_ref(), None).unwrap();
+        manager.set_watermarks("layer1", Some(Watermarks { low_free_bytes: u64::MAX, critical_free_bytes: u64::MAX }));
+
+        let report = manager.import("layer1", &mut archive.as_slice(), ImportMode::Overwrite).unwrap();
+        assert!(report.imported.is_empty());
+        assert!(matches!(report.failed[0].1, StorageError::InsufficientSpace(_)));
+        let results = manager.restore_backup(backups.as_ref(), &backup.id, "layer1").unwrap();
+        assert!(matches!(results[0].1, Err(StorageError::InsufficientSpace(_))));
+        let mut transaction = manager.begin();
+        transaction.save("layer1", FILE_KEY, "test".to_owned().into_bytes());
+        assert!(matches!(transaction.commit(), Err(StorageError::InsufficientSpace(_))));
+        assert!(manager.list("layer1", "").unwrap().is_empty());
+        clean_up(f_key);
+        clean_up(&format!("{}_2", f_key));
+        clean_up(&format!("{}_backups", f_key));
+    }
+}
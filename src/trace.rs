@@ -0,0 +1,253 @@
+//! Spans around storage operations and policy evaluations, they are only recorded with the feature `tracing`.
+
+use std::fmt::Display;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+/// Result of a traced call.
+pub(crate) trait TraceOutcome {
+    /// Error message of a failed call.
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    fn error(self: &Self) -> Option<String>;
+}
+
+impl<T, E: Display> TraceOutcome for Result<T, E> {
+    fn error(self: &Self) -> Option<String> {
+        self.as_ref().err().map(|e| e.to_string())
+    }
+}
+
+impl TraceOutcome for () {
+    fn error(self: &Self) -> Option<String> {
+        None
+    }
+}
+
+/// Span of a storage operation with the fields `operation`, `layer`, `key`, `size`, `duration_us`, `outcome` and `error`.
+/// 
+/// Spans of storage providers have the field `folder` instead of `layer`.
+pub(crate) struct OperationSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl OperationSpan {
+    /// Span of an operation of the storage manager on a layer.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn layer(operation: &'static str, layer: &str, key: &str) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("storage", operation, layer, key, size = Empty, duration_us = Empty, outcome = Empty, error = Empty),
+        }
+    }
+
+    /// Span of an operation of a storage provider on its folder, provider spans are used with `entered`.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn provider(operation: &'static str, folder: &str, key: &str) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("provider", operation, folder, key, size = Empty, duration_us = Empty, outcome = Empty, error = Empty),
+        }
+    }
+
+    /// Record the byte size of the read or written data.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn record_size(self: &Self, size: u64) {
+        #[cfg(feature = "tracing")]
+        self.span.record("size", size);
+    }
+
+    /// Enter the span until the returned guard is dropped, the guard records the duration.
+    pub fn entered(self: Self) -> EnteredOperationSpan {
+        EnteredOperationSpan {
+            #[cfg(feature = "tracing")]
+            started: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: self.span.entered(),
+        }
+    }
+
+    /// Call `f` inside the span and record its duration and outcome.
+    pub fn run<R: TraceOutcome>(self: Self, f: impl FnOnce(&Self) -> R) -> R {
+        #[cfg(feature = "tracing")]
+        {
+            let started = Instant::now();
+            let result = self.span.in_scope(|| f(&self));
+            self.span.record("duration_us", started.elapsed().as_micros() as u64);
+            record_outcome(&self.span, &result);
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        f(&self)
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn record_outcome(span: &tracing::Span, result: &impl TraceOutcome) {
+    match result.error() {
+        None => span.record("outcome", "ok"),
+        Some(err) => span.record("outcome", "error").record("error", err.as_str()),
+    };
+}
+
+/// Guard of an entered `OperationSpan`.
+pub(crate) struct EnteredOperationSpan {
+    #[cfg(feature = "tracing")]
+    started: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl EnteredOperationSpan {
+    /// Record the byte size of the read or written data.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn record_size(self: &Self, size: u64) {
+        #[cfg(feature = "tracing")]
+        self.span.record("size", size);
+    }
+
+    /// Record the outcome and the error of a call.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn record_outcome(self: &Self, result: &impl TraceOutcome) {
+        #[cfg(feature = "tracing")]
+        record_outcome(&self.span, result);
+    }
+
+    /// Call `f` inside the entered span and record its outcome, the duration is recorded when the span is dropped.
+    pub fn run<R: TraceOutcome>(self: Self, f: impl FnOnce(&Self) -> R) -> R {
+        let result = f(&self);
+        self.record_outcome(&result);
+        result
+    }
+}
+
+impl Drop for EnteredOperationSpan {
+    fn drop(self: &mut Self) {
+        #[cfg(feature = "tracing")]
+        self.span.record("duration_us", self.started.elapsed().as_micros() as u64);
+    }
+}
+
+/// Span of a policy evaluation with the fields `evaluation`, `client`, `package_id`, `rule` and `decision`.
+pub(crate) struct PolicySpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl PolicySpan {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn new(evaluation: &'static str, client: &str, package_id: &str) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("policy", evaluation, client, package_id, rule = Empty, decision = Empty),
+        }
+    }
+
+    /// Record the rule which decided the evaluation, `None` if no rule decided it.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn decided(self: &Self, rule: Option<&str>, decision: &str) {
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(rule) = rule {
+                self.span.record("rule", rule);
+            }
+            self.span.record("decision", decision);
+        }
+    }
+
+    /// Call `f` inside the span.
+    pub fn run<T>(self: Self, f: impl FnOnce(&Self) -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(|| f(&self));
+        #[cfg(not(feature = "tracing"))]
+        f(&self)
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        subscriber::with_default,
+        Event, Metadata, Subscriber,
+    };
+
+    use super::OperationSpan;
+
+    /// Collects the recorded field values of all spans.
+    #[derive(Clone, Default)]
+    struct Collector {
+        fields: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Visit for Collector {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields.lock().unwrap().push((field.name().to_owned(), format!("{:?}", value)));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn operation_span() {
+        let collector = Collector::default();
+        let result: Result<(), String> = with_default(collector.clone(), || {
+            OperationSpan::layer("save", "layer1", "1234").run(|span| {
+                span.record_size(4);
+                Err("disk full".to_owned())
+            })
+        });
+        assert!(result.is_err());
+        let fields = collector.fields.lock().unwrap();
+        assert!(fields.contains(&("layer".to_owned(), "\"layer1\"".to_owned())));
+        assert!(fields.contains(&("size".to_owned(), "4".to_owned())));
+        assert!(fields.contains(&("outcome".to_owned(), "\"error\"".to_owned())));
+        assert!(fields.contains(&("error".to_owned(), "\"disk full\"".to_owned())));
+        assert!(fields.iter().any(|(name, _value)| name == "duration_us"));
+    }
+
+    #[test]
+    fn entered_provider_span() {
+        let collector = Collector::default();
+        let result: Result<(), String> = with_default(collector.clone(), || {
+            OperationSpan::provider("get", "folder1", "1234").entered().run(|span| {
+                span.record_size(4);
+                Err("Not found".to_owned())
+            })
+        });
+        assert!(result.is_err());
+        let fields = collector.fields.lock().unwrap();
+        assert!(fields.contains(&("folder".to_owned(), "\"folder1\"".to_owned())));
+        assert!(fields.contains(&("size".to_owned(), "4".to_owned())));
+        assert!(fields.contains(&("outcome".to_owned(), "\"error\"".to_owned())));
+        assert!(fields.contains(&("error".to_owned(), "\"Not found\"".to_owned())));
+        assert!(fields.iter().any(|(name, _value)| name == "duration_us"));
+    }
+}
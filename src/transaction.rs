@@ -0,0 +1,385 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    events::StorageEventKind,
+    metrics::{MeasuredOutcome, Transfer},
+    quota::Reservation,
+    storage_manager::StorageManager,
+    SaveOptions, StorageError, StorageProvider,
+};
+
+enum Operation {
+    Save { layer: String, key: String, raw: Vec<u8>, options: SaveOptions },
+    Delete { layer: String, key: String },
+}
+
+impl Operation {
+    fn target(self: &Self) -> (&str, &str) {
+        match self {
+            Operation::Save { layer, key, .. } => (layer, key),
+            Operation::Delete { layer, key } => (layer, key),
+        }
+    }
+}
+
+/// Save which is written but not visible until it is committed.
+struct Staged {
+    layer: String,
+    key: String,
+    token: String,
+    previous: Option<Previous>,
+}
+
+/// Entry which was overwritten or deleted by the transaction.
+struct Previous {
+    data: Kept,
+    size: u64,
+    options: SaveOptions,
+}
+
+/// Data of a previous entry, kept as a staged copy if the provider supports staging, otherwise in memory.
+enum Kept {
+    Staged(String),
+    Buffered(Vec<u8>),
+}
+
+impl Previous {
+    /// Keep the data of an entry to put it back on a revert, returns `None` if the entry does not exist.
+    fn read(provider: &dyn StorageProvider, key: &str) -> Result<Option<Previous>, StorageError> {
+        let stat = match provider.stat(key) {
+            Ok(stat) => stat,
+            Err(_) => return Ok(None),
+        };
+        // the entry is saved again with its metadata and remaining time to live
+        let options = SaveOptions {
+            ttl: stat.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO)),
+            metadata: stat.metadata,
+        };
+        let data = if provider.supports_staging() {
+            let mut reader = provider.get_reader(key)?;
+            Kept::Staged(provider.stage_save_from_reader(key, &mut reader, &options)?)
+        } else {
+            Kept::Buffered(provider.get(key)?.data)
+        };
+        Ok(Some(Previous { data, size: stat.size as u64, options }))
+    }
+
+    /// Save the entry again under the key.
+    fn put_back(self: Self, provider: &dyn StorageProvider, key: &str) -> Result<(), String> {
+        match self.data {
+            Kept::Staged(token) => {
+                let result = provider.commit_staged(key, &token);
+                if result.is_err() {
+                    provider.discard_staged(&token);
+                }
+                result
+            }
+            Kept::Buffered(raw) => provider.save_with_options(key, raw, &self.options).map(|_| ()),
+        }
+    }
+
+    /// Remove the kept data, the entry is not needed anymore.
+    fn release(self: Self, provider: &dyn StorageProvider) {
+        if let Kept::Staged(token) = self.data {
+            provider.discard_staged(&token);
+        }
+    }
+}
+
+/// Information to revert an applied operation.
+enum Undo {
+    Save { layer: String, key: String, previous: Option<Previous> },
+    Delete { layer: String, key: String, previous: Previous },
+}
+
+#[derive(Default)]
+struct CommitState {
+    staged: Vec<Staged>,
+    reservations: Vec<Reservation>,
+    undo: Vec<Undo>,
+    removed: Vec<(String, String, u64)>,
+    committed_staged: usize,
+}
+
+/// Saves and deletes on multiple layers which are applied together.
+/// 
+/// Saves on providers which support staging are written before anything is changed and become visible together at the end of the commit.
+/// All other operations are applied one by one and are reverted if a later operation fails.
+/// 
+/// # Example
+/// ```
+/// use dispnet_storage::storage_manager::StorageManager;
+/// 
+/// let manager = StorageManager::new();
+/// let mut transaction = manager.begin();
+/// transaction.save("layer1", "package", vec![1, 2]);
+/// transaction.save("index", "package", vec![0]);
+/// // fails because the layers are not registered, nothing was changed
+/// assert!(transaction.commit().is_err());
+/// ```
+pub struct Transaction<'a> {
+    manager: &'a StorageManager,
+    operations: Vec<Operation>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(manager: &'a StorageManager) -> Self {
+        Self {
+            manager,
+            operations: vec![],
+        }
+    }
+
+    /// Stage a save on a layer.
+    pub fn save(self: &mut Self, layer_key: &str, key: &str, raw: Vec<u8>) -> &mut Self {
+        self.save_with_options(layer_key, key, raw, SaveOptions::default())
+    }
+
+    /// Stage a save with additional options on a layer.
+    pub fn save_with_options(self: &mut Self, layer_key: &str, key: &str, raw: Vec<u8>, options: SaveOptions) -> &mut Self {
+        self.operations.push(Operation::Save {
+            layer: layer_key.to_owned(),
+            key: key.to_owned(),
+            raw,
+            options,
+        });
+        self
+    }
+
+    /// Stage a delete on a layer.
+    pub fn delete(self: &mut Self, layer_key: &str, key: &str) -> &mut Self {
+        self.operations.push(Operation::Delete {
+            layer: layer_key.to_owned(),
+            key: key.to_owned(),
+        });
+        self
+    }
+
+    /// Count of staged operations.
+    pub fn len(self: &Self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(self: &Self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Discard all staged operations, nothing was changed before the commit.
+    pub fn rollback(self) {}
+
+    /// Apply all staged operations.
+    /// 
+    /// On `Err` all applied operations are reverted, also saves which were already committed.
+    /// Returns `StorageError::Transaction` if the revert was not possible.
+    pub fn commit(self) -> Result<(), StorageError> {
+        let started = Instant::now();
+        let result = self.commit_operations();
+        // the commit is measured on every layer of the transaction
+        let layers: BTreeSet<&str> = self.operations.iter().map(|operation| operation.target().0).collect();
+        for layer in layers.into_iter().filter(|layer| self.manager.provider(layer).is_ok()) {
+            self.manager.record_operation(layer, "commit", started.elapsed(), result.outcome());
+        }
+        result
+    }
+
+    fn commit_operations(self: &Self) -> Result<(), StorageError> {
+        let mut state = CommitState::default();
+        match self.apply(&mut state) {
+            Ok(()) => {
+                self.release(state.undo);
+                for operation in self.operations.iter() {
+                    if let Operation::Save { layer, key, raw, .. } = operation {
+                        self.manager.record_bytes(layer, Transfer::Written, raw.len() as u64);
+                        self.manager.events.emit(StorageEventKind::Saved, layer, key, raw.len() as u64);
+                    }
+                }
+                for (layer, key, size) in state.removed {
+                    self.manager.quotas.removed(&layer, &key, size);
+                    self.manager.events.emit(StorageEventKind::Deleted, &layer, &key, size);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                let errors = self.revert(state);
+                if errors.is_empty() {
+                    Err(err)
+                } else {
+                    Err(StorageError::Transaction(format!("Could not revert the transaction after `{}`: {}", err, errors.join(", "))))
+                }
+            }
+        }
+    }
+
+    fn apply(self: &Self, state: &mut CommitState) -> Result<(), StorageError> {
+        let mut targets = HashSet::new();
+        for operation in self.operations.iter() {
+            // a degraded layer is not written, transactions do not fail over
+            self.manager.writable_provider(operation.target().0)?;
+            if !targets.insert(operation.target()) {
+                let (layer, key) = operation.target();
+                return Err(StorageError::Transaction(format!("Key `{}` on layer `{}` is used more than once", key, layer)));
+            }
+        }
+
+        // write all staged saves before the first visible change
+        for operation in self.operations.iter() {
+            if let Operation::Save { layer, key, raw, options } = operation {
+                let provider = self.manager.provider(layer)?;
+                if provider.supports_staging() {
+                    self.reserve(state, provider, layer, key, raw.len())?;
+                    let previous = Previous::read(provider, key)?;
+                    let token = provider.stage_save(key, raw.clone(), options).map_err(StorageError::Provider);
+                    self.manager.track_write(layer, &token);
+                    let token = match token {
+                        Ok(token) => token,
+                        Err(err) => {
+                            if let Some(previous) = previous {
+                                previous.release(provider);
+                            }
+                            return Err(err);
+                        }
+                    };
+                    state.staged.push(Staged {
+                        layer: layer.to_owned(),
+                        key: key.to_owned(),
+                        token,
+                        previous,
+                    });
+                }
+            }
+        }
+
+        for operation in self.operations.iter() {
+            match operation {
+                Operation::Save { layer, key, raw, options } => {
+                    let provider = self.manager.provider(layer)?;
+                    if !provider.supports_staging() {
+                        self.reserve(state, provider, layer, key, raw.len())?;
+                        let previous = Previous::read(provider, key)?;
+                        let saved = provider.save_with_options(key, raw.clone(), options).map_err(StorageError::Provider);
+                        self.manager.track_write(layer, &saved);
+                        if let Err(err) = saved {
+                            if let Some(previous) = previous {
+                                previous.release(provider);
+                            }
+                            return Err(err);
+                        }
+                        state.undo.push(Undo::Save {
+                            layer: layer.to_owned(),
+                            key: key.to_owned(),
+                            previous,
+                        });
+                    }
+                }
+                Operation::Delete { layer, key } => {
+                    let provider = self.manager.provider(layer)?;
+                    if let Some(previous) = Previous::read(provider, key)? {
+                        provider.delete(key);
+                        let deleted = match provider.stat(key) {
+                            Ok(_) => Err(StorageError::Provider(format!("Could not delete `{}`", key))),
+                            Err(_) => Ok(()),
+                        };
+                        self.manager.track_write(layer, &deleted);
+                        if let Err(err) = deleted {
+                            previous.release(provider);
+                            return Err(err);
+                        }
+                        state.removed.push((layer.to_owned(), key.to_owned(), previous.size));
+                        state.undo.push(Undo::Delete {
+                            layer: layer.to_owned(),
+                            key: key.to_owned(),
+                            previous,
+                        });
+                    }
+                }
+            }
+        }
+
+        for staged in state.staged.iter_mut() {
+            let committed = self.manager.provider(&staged.layer)?.commit_staged(&staged.key, &staged.token).map_err(StorageError::Provider);
+            self.manager.track_write(&staged.layer, &committed);
+            committed?;
+            state.undo.push(Undo::Save {
+                layer: staged.layer.to_owned(),
+                key: staged.key.to_owned(),
+                previous: staged.previous.take(),
+            });
+            state.committed_staged += 1;
+        }
+        Ok(())
+    }
+
+    fn reserve(self: &Self, state: &mut CommitState, provider: &dyn StorageProvider, layer: &str, key: &str, size: usize) -> Result<(), StorageError> {
+        self.manager.ensure_capacity(layer, provider)?;
+        let previous_size = provider.stat(key).ok().map(|stat| stat.size as u64);
+        let reservation = self.manager.quotas.reserve(layer, None, key, previous_size, size as u64)?;
+        state.reservations.push(reservation);
+        Ok(())
+    }
+
+    /// Remove the kept data of all entries which were overwritten or deleted by the applied operations.
+    fn release(self: &Self, undo: Vec<Undo>) {
+        for undo in undo {
+            let (layer, previous) = match undo {
+                Undo::Save { layer, previous, .. } => (layer, previous),
+                Undo::Delete { layer, previous, .. } => (layer, Some(previous)),
+            };
+            if let (Ok(provider), Some(previous)) = (self.manager.provider(&layer), previous) {
+                previous.release(provider);
+            }
+        }
+    }
+
+    /// Revert all applied operations in reverse order, returns the errors of the operations which could not be reverted.
+    fn revert(self: &Self, state: CommitState) -> Vec<String> {
+        let mut errors = vec![];
+        for staged in state.staged.into_iter().skip(state.committed_staged) {
+            if let Ok(provider) = self.manager.provider(&staged.layer) {
+                provider.discard_staged(&staged.token);
+                if let Some(previous) = staged.previous {
+                    previous.release(provider);
+                }
+            }
+        }
+        for undo in state.undo.into_iter().rev() {
+            let (layer, key, previous, deleted) = match undo {
+                Undo::Save { layer, key, previous } => (layer, key, previous, false),
+                Undo::Delete { layer, key, previous } => (layer, key, Some(previous), true),
+            };
+            let provider = match self.manager.provider(&layer) {
+                Ok(provider) => provider,
+                Err(err) => {
+                    errors.push(err.to_string());
+                    continue;
+                }
+            };
+            let result = match previous {
+                // a deleted entry is restored from the delete queue if possible, otherwise saved again
+                Some(previous) if deleted && provider.restore(&key).is_ok() => {
+                    previous.release(provider);
+                    Ok(())
+                }
+                Some(previous) => previous.put_back(provider, &key),
+                // a new entry is discarded, it must not be restorable from the delete queue or replace an older deleted entry
+                None => provider.discard(&key).or_else(|_| {
+                    // providers which can not discard queue the entry for deletion
+                    provider.delete(&key);
+                    match provider.stat(&key) {
+                        Ok(_) => Err("entry was not deleted".to_owned()),
+                        Err(_) => Ok(()),
+                    }
+                }),
+            };
+            if let Err(err) = result {
+                errors.push(format!("Could not revert `{}` on layer `{}`: {}", key, layer, err));
+            }
+        }
+        for reservation in state.reservations.into_iter().rev() {
+            self.manager.quotas.release(reservation);
+        }
+        errors
+    }
+}